@@ -2,29 +2,76 @@
  * IMPORTS
  * ====================================================================*/
 // local files
+use crate::bluetooth::ble_default_services::{attribute_name, lookup_attribute};
 use crate::bluetooth::cps::*;
-use crate::bluetooth::{bt_adapter_scan, bt_scan};
-use crate::zwo_reader::zwo_command::{create_timeseries, WorkoutTimeSeries};
+use crate::bluetooth::cscs::*;
+use crate::bluetooth::device_info::{
+    read_device_information, subscribe_battery_level, BatteryLevel, DeviceDetails,
+};
+use crate::bluetooth::discovery::{start_discovery, DiscoveredDevice, Discovery};
+use crate::bluetooth::fec::{FecPage, GeneralFeData, SpecificTrainerData};
+use crate::bluetooth::ftms::*;
+use crate::bluetooth::gatt::*;
+use crate::bluetooth::session::*;
+use crate::bluetooth::supervisor::{spawn_supervisor, Backoff, ConnectionEvent, WatchList};
+use crate::bluetooth::trainer::{create_trainer, SpinDownEvent, Trainer, TrainerData};
+use crate::bluetooth::virtual_trainer::{VirtualTrainer, VirtualTrainerSettings};
+use crate::bluetooth::{bt_adapter_scan, find_characteristic, BleRequestError};
+use crate::calibration::{
+    format_unix_time, unix_time_now, CalibrationKind, CalibrationLog, CalibrationRecord,
+    CALIBRATION_FILE,
+};
+use crate::known_devices::{DeviceRole, KnownDevice, KnownDevices, KNOWN_DEVICES_FILE};
+use crate::ride_record::{RideRecord, RideSample};
+use crate::simulation::{GradeProfile, RiderSettings, SimulationController};
+use crate::zwo_reader::zwo_command::{
+    create_timeseries, free_ride_mask, interval_numbers, WorkoutTimeSeries,
+};
 use crate::zwo_reader::{zwo_read, Workout};
 
 // external crates
-use async_std::stream::StreamExt;
 use async_std::task;
 use btleplug::{
-    api::{Central, Peripheral as Peripheral_api},
-    platform::{Adapter, Peripheral},
+    api::{Central, CharPropFlags, Peripheral as Peripheral_api, WriteType},
+    platform::{Adapter, Peripheral, PeripheralId},
 };
 use eframe::egui::{self, Ui};
 use eframe::epaint::Vec2;
 use egui_file::FileDialog;
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
-use tokio::runtime::Runtime;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
 
 /*=======================================================================
  * CONSTANTS
  * ====================================================================*/
+/// bits of the Cycling Power Measurement content mask, for the checkboxes
+const CPS_MASK_FIELDS: [(u16, &str); 9] = [
+    (0, "balance"),
+    (1, "torque"),
+    (2, "wheel"),
+    (3, "crank"),
+    (4, "extreme magnitudes"),
+    (5, "extreme angles"),
+    (6, "top dead spot"),
+    (7, "bottom dead spot"),
+    (8, "energy"),
+];
+
+/// trainer status events kept for the event list
+const TRAINER_EVENT_HISTORY: usize = 8;
+
+/// spin downs listed under the spin down calibration
+const SPIN_DOWN_HISTORY: usize = 5;
+
+/// remembered devices that haven't advertised by then are probably switched off
+const AUTO_CONNECT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// side of the square the pedal stroke plot is drawn in, px
+const PEDAL_STROKE_PLOT_SIZE: f32 = 220.0;
 
 /*=======================================================================
  * ENUMS
@@ -34,9 +81,53 @@ enum Tabs {
     Main,
     Workouts,
     Bluetooth,
+    Gatt,
     Help,
 }
 
+/// everything the workout thread needs to run a loaded workout
+struct WorkoutPlan {
+    time_series: WorkoutTimeSeries,
+    free_ride: Vec<bool>,
+    intervals: Vec<usize>, // workout section of every second
+    user_ftp: u32,
+    erg_mode: bool, // power targets are only sent when the rider asked for ERG
+    // Some when FreeRide sections should be ridden in sim mode
+    simulation: Option<SimulationController>,
+    rider_settings: RiderSettings,
+    step: Duration, // one second of the workout, shorter in tests
+}
+
+/// sent from the GUI to the workout thread
+#[derive(Debug, PartialEq, Eq)]
+enum WorkoutCommand {
+    Stop,
+    Pause,
+    Resume,
+    /// the trainer dropped out and is back, control and targets have to be sent again
+    TrainerReconnected,
+    /// another app took control of the trainer, it is taken back the same way
+    ControlLost,
+}
+
+/// how far connecting the remembered devices on launch has got
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AutoConnect {
+    Off,
+    Waiting,           // for an adapter to be picked
+    FindingAdapter,    // adapter scan running on its own thread
+    Scanning(Instant), // started at
+}
+
+/// what the auto connect threads did, finished off on the GUI thread
+enum AutoConnected {
+    /// every adapter and the one the remembered devices were found on
+    Adapters(Vec<Adapter>, usize),
+    Trainer(Peripheral, Option<Box<dyn Trainer>>, DeviceDetails),
+    Sensor(Box<SessionDevice>, DeviceDetails),
+    Failed(String),
+}
+
 /*=======================================================================
  * STRUCTS
  * ====================================================================*/
@@ -45,6 +136,48 @@ pub struct WorkoutMessage {
     time: usize,
     target_cadence: i32,
     target_power: f32,
+    interval: usize,
+    erg_status: Option<String>,
+}
+
+/// the power meter a zero offset panel is drawn for
+struct CpsMeter<'a> {
+    id: DeviceId,
+    name: &'a str,
+    peripheral: &'a Peripheral,
+    connected: bool, // false while the supervisor is reconnecting it
+}
+
+/// Cycling Power Control Point panel of one device
+#[derive(Default)]
+struct CpsControlState {
+    feature: Option<CpsFeature>,
+    setting_text: HashMap<CpsSetting, String>,
+    cumulative_text: String,
+    supported_locations: Vec<SensorLocation>,
+    mask: u16,              // fields to hide, CpsContentMask bits
+    status: Option<String>, // result of the last procedure
+    request_pending: Option<(
+        CpsRequest,
+        std::sync::mpsc::Receiver<Result<CpsResponse, String>>,
+    )>,
+    // zero offset calibration
+    calibration_unloaded: bool, // rider confirmed the cranks are unloaded
+    calibration_pending: Option<(
+        CalibrationKind,
+        std::sync::mpsc::Receiver<Result<CpsResponse, String>>,
+    )>,
+    calibration_result: Option<String>,
+}
+
+/// guided trainer spin down
+#[derive(Default)]
+struct SpinDownState {
+    warmed_up: bool, // rider confirmed the trainer has been ridden for a while
+    pending: Option<std::sync::mpsc::Receiver<SpinDownEvent>>,
+    target_speed: Option<(f32, f32)>, // km/h
+    stop_pedaling: bool,
+    result: Option<String>,
 }
 
 pub struct BikeApp {
@@ -63,14 +196,72 @@ pub struct BikeApp {
     peripheral_text: String,
     peripheral_connected: bool,
     peripheral_channel: (
-        std::sync::mpsc::Sender<Option<Vec<Peripheral>>>,
-        std::sync::mpsc::Receiver<Option<Vec<Peripheral>>>,
+        std::sync::mpsc::Sender<DiscoveredDevice>,
+        std::sync::mpsc::Receiver<DiscoveredDevice>,
+    ),
+    discovery: Option<Discovery>,
+    discovered: HashMap<PeripheralId, DiscoveredDevice>,
+    fitness_only: bool,
+    known_devices: KnownDevices,
+    calibration_log: CalibrationLog,
+    auto_connect: AutoConnect,
+    auto_connect_channel: (
+        std::sync::mpsc::Sender<AutoConnected>,
+        std::sync::mpsc::Receiver<AutoConnected>,
+    ),
+    auto_connect_tried: HashSet<String>, // ids, each remembered device is tried once
+    trainer: Option<Arc<dyn Trainer>>,
+    trainer_data_receiver: Option<std::sync::mpsc::Receiver<TrainerData>>,
+    trainer_status_receiver: Option<std::sync::mpsc::Receiver<FtmsStatus>>,
+    trainer_events: Vec<(bool, String)>, // latest last, true for echoed targets
+    virtual_trainer_settings: VirtualTrainerSettings,
+    virtual_trainer: Option<Arc<VirtualTrainer>>, // also in trainer while in use
+    power_measurement: Option<CpsMeasurement>,
+    indoor_bike_data: Option<IndoorBikeData>,
+    fec_general_data: Option<GeneralFeData>,
+    fec_trainer_data: Option<SpecificTrainerData>,
+    trainer_decoder: MetricDecoder,
+    actual_power: f32,
+    actual_cadence: f32,
+    actual_speed: f32,
+    actual_heart_rate: Option<u16>,
+    actual_pedal_dynamics: PedalDynamics,
+    // sensors connected next to the trainer
+    sensor_peripheral_number: Option<usize>,
+    sensors: Vec<SessionDevice>,
+    next_device_id: DeviceId,
+    sensor_event_channel: (
+        std::sync::mpsc::Sender<DeviceEvent>,
+        std::sync::mpsc::Receiver<DeviceEvent>,
     ),
-    bt_queue_channel: (
-        std::sync::mpsc::Sender<Vec<u8>>,
-        std::sync::mpsc::Receiver<Vec<u8>>,
+    metric_sources: MetricSources,
+    wheel_revolutions_text: String,
+    // reconnects after dropouts
+    watch_list: WatchList,
+    connection_event_channel: (
+        std::sync::mpsc::Sender<ConnectionEvent>,
+        std::sync::mpsc::Receiver<ConnectionEvent>,
     ),
-    power_measurement_subscribed: bool,
+    reconnecting: Vec<(DeviceId, Instant)>,
+    cps_controls: HashMap<DeviceId, CpsControlState>,
+    spin_down: SpinDownState,
+    // Device Information and battery of the trainer and every sensor
+    device_details: HashMap<DeviceId, DeviceDetails>,
+    battery_channel: (
+        std::sync::mpsc::Sender<BatteryLevel>,
+        std::sync::mpsc::Receiver<BatteryLevel>,
+    ),
+    // GATT explorer
+    gatt_device: Option<DeviceId>,
+    gatt_values: HashMap<(DeviceId, Uuid, Option<Uuid>), String>, // (device, characteristic, descriptor)
+    gatt_write_text: HashMap<(DeviceId, Uuid), String>,
+    gatt_watched: Arc<Mutex<HashSet<(DeviceId, Uuid)>>>,
+    gatt_loggers: HashSet<DeviceId>,
+    gatt_notification_channel: (
+        std::sync::mpsc::Sender<RawNotification>,
+        std::sync::mpsc::Receiver<RawNotification>,
+    ),
+    gatt_log: NotificationLog,
     // workout file stuff
     user_ftp: u32,
     user_ftp_string: String,
@@ -78,6 +269,9 @@ pub struct BikeApp {
     workout_file_dialog: Option<FileDialog>,
     workout: Option<Workout>,
     workout_time_series: Option<WorkoutTimeSeries>,
+    workout_free_ride: Option<Vec<bool>>,
+    workout_intervals: Option<Vec<usize>>,
+    workout_warning: Option<String>, // targets the trainer can't hold
     workout_running: bool,
     display_time: usize,
     display_cadence: i32,
@@ -85,6 +279,7 @@ pub struct BikeApp {
     // Main/testing stuff
     resistance_text: String,
     resistance_value: u8,
+    trainer_controlled: bool, // start was sent for Set Resistance, cleared when control may be gone
     // workout thread:
     workout_channel: (
         std::sync::mpsc::Sender<WorkoutMessage>,
@@ -92,11 +287,35 @@ pub struct BikeApp {
     ),
     // stop workout channel
     stop_workout_flag: bool,
-    stop_workout_sender: Option<std::sync::mpsc::Sender<bool>>,
+    workout_paused: bool,
+    workout_command_sender: Option<std::sync::mpsc::Sender<WorkoutCommand>>,
+    // ERG
+    erg_mode: bool,
+    erg_status: Option<String>,
+    // sim mode
+    sim_mode: bool,
+    grade_profile_text: String,
+    rider_settings: RiderSettings,
+    live_speed: Arc<Mutex<f32>>, // read by the workout thread to move along the course
+    ride_record: RideRecord,
 }
 
 impl Default for BikeApp {
     fn default() -> Self {
+        let known_devices = match KnownDevices::load(Path::new(KNOWN_DEVICES_FILE)) {
+            Ok(known_devices) => known_devices,
+            Err(e) => {
+                println!("Failed to load known devices: {}", e);
+                KnownDevices::default()
+            }
+        };
+        let calibration_log = match CalibrationLog::load(Path::new(CALIBRATION_FILE)) {
+            Ok(calibration_log) => calibration_log,
+            Err(e) => {
+                println!("Failed to load calibrations: {}", e);
+                CalibrationLog::default()
+            }
+        };
         Self {
             active_tab: Tabs::Main,
             bt_adapters: None,
@@ -111,23 +330,81 @@ impl Default for BikeApp {
             peripheral_text: "None selected".to_string(),
             peripheral_connected: false,
             peripheral_channel: std::sync::mpsc::channel(),
-            bt_queue_channel: std::sync::mpsc::channel(),
-            power_measurement_subscribed: false,
+            discovery: None,
+            discovered: HashMap::new(),
+            fitness_only: true,
+            auto_connect: if known_devices.is_empty() {
+                AutoConnect::Off
+            } else {
+                AutoConnect::Waiting
+            },
+            auto_connect_channel: std::sync::mpsc::channel(),
+            known_devices,
+            calibration_log,
+            auto_connect_tried: HashSet::new(),
+            trainer: None,
+            trainer_data_receiver: None,
+            trainer_status_receiver: None,
+            trainer_events: Vec::new(),
+            virtual_trainer_settings: VirtualTrainerSettings::default(),
+            virtual_trainer: None,
+            power_measurement: None,
+            indoor_bike_data: None,
+            fec_general_data: None,
+            fec_trainer_data: None,
+            trainer_decoder: MetricDecoder::default(),
+            actual_power: 0.0,
+            actual_cadence: 0.0,
+            actual_speed: 0.0,
+            actual_heart_rate: None,
+            actual_pedal_dynamics: PedalDynamics::default(),
+            sensor_peripheral_number: None,
+            sensors: Vec::new(),
+            next_device_id: TRAINER_DEVICE + 1,
+            sensor_event_channel: std::sync::mpsc::channel(),
+            metric_sources: MetricSources::default(),
+            wheel_revolutions_text: "0".to_string(),
+            watch_list: WatchList::default(),
+            connection_event_channel: std::sync::mpsc::channel(),
+            reconnecting: Vec::new(),
+            cps_controls: HashMap::new(),
+            spin_down: SpinDownState::default(),
+            device_details: HashMap::new(),
+            battery_channel: std::sync::mpsc::channel(),
+            gatt_device: None,
+            gatt_values: HashMap::new(),
+            gatt_write_text: HashMap::new(),
+            gatt_watched: Arc::new(Mutex::new(HashSet::new())),
+            gatt_loggers: HashSet::new(),
+            gatt_notification_channel: std::sync::mpsc::channel(),
+            gatt_log: NotificationLog::default(),
             user_ftp: 100,
             user_ftp_string: "100".to_string(),
             workout_file: None,
             workout_file_dialog: None,
             workout: None,
             workout_time_series: None,
+            workout_free_ride: None,
+            workout_intervals: None,
+            workout_warning: None,
             workout_running: false,
             display_time: 0,
             display_cadence: 0,
             display_power: 0.0,
             resistance_text: "0".to_string(),
             resistance_value: 0,
+            trainer_controlled: false,
             workout_channel: std::sync::mpsc::channel(),
             stop_workout_flag: true,
-            stop_workout_sender: None,
+            workout_paused: false,
+            workout_command_sender: None,
+            erg_mode: true,
+            erg_status: None,
+            sim_mode: false,
+            grade_profile_text: "0:0".to_string(),
+            rider_settings: RiderSettings::default(),
+            live_speed: Arc::new(Mutex::new(0.0)),
+            ride_record: RideRecord::default(),
         }
     }
 }
@@ -138,6 +415,7 @@ impl eframe::App for BikeApp {
         ctx.request_repaint(); // update gui with new message - otherwise waits on mouse
         update_text_edits(self);
         println!("Update text");
+        start_auto_connect(self);
 
         if self.bt_adapters.is_some() && self.selected_adapter_number.is_some() {
             if self.adapter_moved == false {
@@ -153,6 +431,12 @@ impl eframe::App for BikeApp {
                         .remove(self.selected_adapter_number.clone().unwrap()),
                 );
                 self.adapter_moved = true;
+                spawn_supervisor(
+                    self.selected_adapter.clone().unwrap(),
+                    self.watch_list.clone(),
+                    Backoff::default(),
+                    self.connection_event_channel.0.clone(),
+                );
             }
         }
         if self.peripheral_list.is_some() && self.selected_peripheral_number.is_some() {
@@ -188,6 +472,23 @@ impl eframe::App for BikeApp {
             }
         }
 
+        // notifications are drained here so every tab sees live values
+        receive_measurements(self);
+        receive_discoveries(self);
+        receive_auto_connect(self);
+        receive_battery_levels(self);
+        receive_trainer_status(self);
+        receive_spin_down(self);
+        for state in self.cps_controls.values_mut() {
+            receive_cps_response(state);
+        }
+        handle_connection_events(self);
+        let now = Instant::now();
+        while let Ok(notification) = self.gatt_notification_channel.1.try_recv() {
+            let name = device_name(self, notification.device);
+            self.gatt_log.push(&notification, &name, now);
+        }
+
         // parse text boxes
         match self.resistance_text.parse::<u8>() {
             Ok(value) => self.resistance_value = value,
@@ -201,6 +502,7 @@ impl eframe::App for BikeApp {
                 ui.selectable_value(&mut self.active_tab, Tabs::Main, "Main");
                 ui.selectable_value(&mut self.active_tab, Tabs::Workouts, "Workouts");
                 ui.selectable_value(&mut self.active_tab, Tabs::Bluetooth, "Bluetooth");
+                ui.selectable_value(&mut self.active_tab, Tabs::Gatt, "GATT");
                 ui.selectable_value(&mut self.active_tab, Tabs::Help, "Help");
             });
             ui.separator();
@@ -214,6 +516,9 @@ impl eframe::App for BikeApp {
                 Tabs::Bluetooth => {
                     draw_bluetooth_tab(ui, self);
                 }
+                Tabs::Gatt => {
+                    draw_gatt_tab(ui, self);
+                }
                 Tabs::Help => {}
             }
         });
@@ -256,253 +561,1902 @@ async fn update_peripheral_text(peripheral: &Peripheral) -> String {
 
 /// draws the main tab
 fn draw_main_tab(ui: &mut Ui, app_struct: &mut BikeApp) {
-    if app_struct.peripheral_connected && app_struct.selected_peripheral.is_some() {
-        let peripheral = app_struct.selected_peripheral.clone().unwrap();
-        let _ = task::block_on(peripheral.discover_services());
-        let characteristics = peripheral.characteristics();
-        let feature_char = characteristics
-            .iter()
-            .find(|c| c.uuid == CPS_POWER_FEATURE)
-            .unwrap();
-        let feature_char2 = characteristics
-            .iter()
-            .find(|c| c.uuid == CPS_POWER_MEASUREMENT)
-            .unwrap();
-        let feature_char3 = characteristics
-            .iter()
-            .find(|c| c.uuid == CPS_CONTROL_POINT)
-            .unwrap();
-        if ui.button("Read CPS Power Feature").clicked() {
-            // probably don't need to read this one to get working for a single bike
-            let read_result = task::block_on(peripheral.read(feature_char));
-            // subscribe and notify instead?
-            match read_result {
-                Ok(buf) => {
-                    println!("Feature buffer length: {:?}", buf.len());
-                    let hack_buffer = u32::from_le_bytes(buf.clone().try_into().unwrap());
-                    let hack_struct = CpsFeature(hack_buffer);
-                    println!("{:?}", hack_struct);
-                    let combined_buffer = u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]);
-                    let feature_struct = CpsFeature(combined_buffer);
-                    println!("{:?}", feature_struct);
-                }
-                Err(e) => {
-                    ui.label(e.to_string());
-                }
-            }
-        }
-        if ui.button("Subscribe to CPS Power Measurement").clicked() {
-            let subscribe_result = task::block_on(peripheral.subscribe(feature_char2));
-            match subscribe_result {
-                Ok(k) => {
-                    println!("Subscribed to Power Measurement. {:?}", k);
-                    app_struct.power_measurement_subscribed = true;
-                    // spawn a thread to receive notifications
-                    let notification_sender = app_struct.bt_queue_channel.0.clone();
-                    let subscribed_peripheral = peripheral.clone();
-                    thread::spawn(move || {
-                        task::block_on(async move {
-                            let notification_result = subscribed_peripheral.notifications().await;
-                            match notification_result {
-                                Ok(notif) => {
-                                    let mut reading = notif.take(1);
-                                    while let Some(data) = reading.next().await {
-                                        println!("Reading: {:?}", data.value);
-                                        // TODO: better error handling here
-                                        notification_sender.send(data.value).unwrap();
-                                        println!("Reading sent.");
-                                    }
-                                }
-                                Err(_) => {} // noting here yet
-                            }
-                        });
-                    });
+    draw_battery_warnings(ui, app_struct);
+    // the virtual trainer has no peripheral behind it
+    if let Some(trainer) = app_struct.trainer.clone() {
+        draw_trainer_controls(ui, app_struct, trainer.as_ref());
+    }
+    // a real trainer left connected under the virtual one isn't in use
+    if !app_struct.peripheral_connected || app_struct.virtual_trainer.is_some() {
+        return;
+    }
+    let Some(peripheral) = app_struct.selected_peripheral.clone() else {
+        return;
+    };
+    let _ = task::block_on(peripheral.discover_services());
+    if app_struct.trainer.is_none() {
+        ui.label("Device is not a trainer or power meter.");
+    }
+    // FTMS/FE-C only trainers don't have CPS, nothing else to show
+    if find_characteristic(&peripheral, CPS_CONTROL_POINT).is_some() {
+        let connected = !is_reconnecting(&app_struct.reconnecting, TRAINER_DEVICE);
+        let state = app_struct.cps_controls.entry(TRAINER_DEVICE).or_default();
+        draw_cps_control_point(ui, state, &peripheral, TRAINER_DEVICE);
+        draw_zero_offset_calibration(
+            ui,
+            state,
+            &mut app_struct.calibration_log,
+            &app_struct.metric_sources,
+            CpsMeter {
+                id: TRAINER_DEVICE,
+                name: &app_struct.peripheral_text,
+                peripheral: &peripheral,
+                connected,
+            },
+        );
+    }
+}
+
+/// Cycling Power Control Point procedures, only the ones the feature says are supported
+fn draw_cps_control_point(
+    ui: &mut Ui,
+    state: &mut CpsControlState,
+    peripheral: &Peripheral,
+    device: DeviceId,
+) {
+    egui::CollapsingHeader::new("Power meter settings")
+        .id_source(format!("cps_control_{}", device))
+        .show(ui, |ui| {
+            let Some(feature) = state.feature else {
+                if ui.button("Read CPS Power Feature").clicked() {
+                    match task::block_on(read_cps_feature(peripheral)) {
+                        Ok(feature) => {
+                            println!("{:?}", feature);
+                            state.feature = Some(feature);
+                        }
+                        Err(e) => state.status = Some(format!("Failed to read feature: {}", e)),
+                    }
                 }
-                Err(e) => {
-                    println!("Failed to subscribe to Power Measurement: {:?}", e);
+                if let Some(status) = &state.status {
+                    ui.label(status);
                 }
+                return;
+            };
+            // the meter takes one procedure at a time
+            if state.calibration_pending.is_some() {
+                ui.label("Waiting for the zero offset calibration...");
+                ui.set_enabled(false);
             }
-        }
-        if ui.button("Read Feature 3").clicked() {
-            // IDK, don't bother with this one yet I guess
-            // it's writable and supports indicate (probably for write result?)
-            // don't think this one is necessary either
-            let read_result3 = task::block_on(peripheral.read(feature_char3));
-            match read_result3 {
-                Ok(buf) => {
-                    println!("Control Point length: {:?}", buf.len());
-                    //let combined_buffer = u16::from_le_bytes(buf.clone().try_into().unwrap());
-                    //let control_struct = Cps
+            egui::Grid::new(format!("cps_settings_{}", device)).show(ui, |ui| {
+                for setting in CpsSetting::ALL {
+                    if !setting.request().is_supported(feature) {
+                        continue;
+                    }
+                    let text = state.setting_text.entry(setting).or_default();
+                    ui.label(setting.label());
+                    ui.text_edit_singleline(text);
+                    let set_request = text
+                        .parse::<f32>()
+                        .ok()
+                        .and_then(|value| setting.set_request(value));
+                    if ui.button("Set").clicked() {
+                        match set_request {
+                            Some(request) => {
+                                run_cps_request(state, peripheral, &request);
+                            }
+                            None => state.status = Some(format!("Enter a {}", setting.label())),
+                        }
+                    }
+                    if ui.button("Get").clicked() {
+                        run_cps_request(state, peripheral, &setting.request());
+                    }
+                    ui.end_row();
                 }
-                Err(e) => {
-                    println!("{:?}", e);
+                if feature.wheel_revolution_data_supported() {
+                    ui.label("Wheel revolutions");
+                    ui.text_edit_singleline(&mut state.cumulative_text);
+                    if ui.button("Set").clicked() {
+                        match state.cumulative_text.parse::<u32>() {
+                            Ok(value) => {
+                                run_cps_request(
+                                    state,
+                                    peripheral,
+                                    &CpsRequest::SetCumulativeValue(value),
+                                );
+                            }
+                            Err(_) => {
+                                state.status = Some("Enter a whole number of revolutions".into())
+                            }
+                        }
+                    }
+                    ui.end_row();
                 }
+            });
+            if feature.multiple_sensor_locations_supported() {
+                ui.horizontal_wrapped(|ui| {
+                    if ui.button("Supported Locations").clicked() {
+                        let request = CpsRequest::RequestSupportedSensorLocations;
+                        run_cps_request(state, peripheral, &request);
+                    }
+                    for location in state.supported_locations.clone() {
+                        if ui.button(format!("{:?}", location)).clicked() {
+                            let request = CpsRequest::UpdateSensorLocation(location);
+                            run_cps_request(state, peripheral, &request);
+                        }
+                    }
+                });
             }
-        }
-        if app_struct.power_measurement_subscribed {
-            // receive notifications sent and display here
-            match app_struct.bt_queue_channel.1.try_recv() {
-                Ok(message) => {
-                    println!("Message received!");
-                    for msg in message.iter() {
-                        // TODO: but label values in app struct or something
-                        ui.label(msg.to_string());
+            if feature.cycling_power_measurement_characteristic_content_masking_supported() {
+                ui.horizontal_wrapped(|ui| {
+                    ui.label("Hide:");
+                    for (bit, label) in CPS_MASK_FIELDS {
+                        let mut hidden = state.mask & (1 << bit) != 0;
+                        if ui.checkbox(&mut hidden, label).changed() {
+                            state.mask ^= 1 << bit;
+                        }
                     }
+                    if ui.button("Apply").clicked() {
+                        let request =
+                            CpsRequest::MaskMeasurementContent(CpsContentMask(state.mask));
+                        run_cps_request(state, peripheral, &request);
+                    }
+                });
+            }
+            ui.horizontal(|ui| {
+                // offset compensation has its own guided panel
+                let request = CpsRequest::RequestFactoryCalibrationDate;
+                if request.is_supported(feature) && ui.button("Factory Calibration Date").clicked()
+                {
+                    run_cps_request(state, peripheral, &request);
                 }
-                Err(_) => {}
+            });
+            if let Some(status) = &state.status {
+                ui.label(status);
+            }
+        });
+}
+
+/// guided zero offset: checks the cranks are unloaded, runs (enhanced) offset compensation
+/// on its own thread and saves the offset with the time for the device
+fn draw_zero_offset_calibration(
+    ui: &mut Ui,
+    state: &mut CpsControlState,
+    calibration_log: &mut CalibrationLog,
+    sources: &MetricSources,
+    meter: CpsMeter,
+) {
+    let CpsMeter {
+        id: device,
+        name,
+        peripheral,
+        connected,
+    } = meter;
+    let Some(feature) = state.feature else {
+        ui.label("Read the power feature above to see if the meter can be zeroed.");
+        return;
+    };
+    let (kind, request) = if feature.enhanced_offset_compensation_supported() {
+        (
+            CalibrationKind::EnhancedZeroOffset,
+            CpsRequest::StartEnhancedOffsetCompensation,
+        )
+    } else if feature.offset_compensation_supported() {
+        (
+            CalibrationKind::ZeroOffset,
+            CpsRequest::StartOffsetCompensation,
+        )
+    } else {
+        return;
+    };
+    let device_id = peripheral.id().to_string();
+    egui::CollapsingHeader::new("Zero offset calibration")
+        .id_source(format!("zero_offset_{}", device))
+        .show(ui, |ui| {
+            // (time, offset) of the last zero offset
+            let previous =
+                calibration_log
+                    .history(&device_id)
+                    .find_map(|record| match record.kind {
+                        CalibrationKind::ZeroOffset | CalibrationKind::EnhancedZeroOffset => {
+                            Some((record.time, record.value?))
+                        }
+                        CalibrationKind::SpinDown => None,
+                    });
+            if let Some((time, value)) = previous {
+                ui.label(format!(
+                    "Last offset: {} ({})",
+                    value,
+                    format_unix_time(time)
+                ));
+            }
+            if let Some((kind, receiver)) = &state.calibration_pending {
+                let result = match receiver.try_recv() {
+                    Ok(result) => result,
+                    Err(std::sync::mpsc::TryRecvError::Empty) => {
+                        ui.label("Calibrating, keep the cranks still...");
+                        return;
+                    }
+                    Err(std::sync::mpsc::TryRecvError::Disconnected) => {
+                        Err("calibration thread stopped".to_string())
+                    }
+                };
+                state.calibration_result = Some(match result {
+                    Ok(CpsResponse::OffsetCompensation(offset)) => {
+                        calibration_log.add(CalibrationRecord {
+                            device_id: device_id.clone(),
+                            device_name: name.to_string(),
+                            time: unix_time_now(),
+                            kind: *kind,
+                            value: Some(offset as f32),
+                        });
+                        if let Err(e) = calibration_log.save(Path::new(CALIBRATION_FILE)) {
+                            println!("Failed to save calibrations: {}", e);
+                        }
+                        match previous {
+                            Some((_, previous)) => format!(
+                                "New offset: {} (change {:+})",
+                                offset,
+                                offset as f32 - previous
+                            ),
+                            None => format!("New offset: {}", offset),
+                        }
+                    }
+                    Ok(other) => format!("Unexpected response: {}", other),
+                    Err(e) => format!("Calibration failed: {}", e),
+                });
+                println!("{}: {}", name, state.calibration_result.clone().unwrap());
+                state.calibration_pending = None;
+            }
+            let now = Instant::now();
+            let pedaling = [Metric::Power, Metric::Cadence].iter().any(|metric| {
+                sources
+                    .fresh(device, *metric, now)
+                    .is_some_and(|value| value > 0.0)
+            });
+            ui.label("1. Unclip, or take all weight off the pedals.");
+            ui.label("2. Put the cranks where the manufacturer says to calibrate.");
+            ui.checkbox(
+                &mut state.calibration_unloaded,
+                "3. Cranks are unloaded and still",
+            );
+            if !connected {
+                ui.colored_label(egui::Color32::RED, "Meter is not connected.");
+            }
+            if pedaling {
+                ui.colored_label(
+                    egui::Color32::RED,
+                    "Still reading power or cadence, stop pedaling first.",
+                );
+            }
+            if let Some((pending, _)) = &state.request_pending {
+                ui.label(format!("Waiting for {:?} first.", pending.op_code()));
+            }
+            let ready = connected
+                && state.calibration_unloaded
+                && !pedaling
+                && state.request_pending.is_none();
+            if ui
+                .add_enabled(ready, egui::Button::new(format!("Start {}", kind)))
+                .clicked()
+            {
+                let (sender, receiver) = std::sync::mpsc::channel();
+                let peripheral = peripheral.clone();
+                thread::spawn(move || {
+                    let result = task::block_on(cps_request(&peripheral, &request));
+                    let _ = sender.send(result.map_err(|e| e.to_string()));
+                });
+                state.calibration_pending = Some((kind, receiver));
+                state.calibration_unloaded = false; // confirm again next time
+                state.calibration_result = None;
+            }
+            if let Some(result) = &state.calibration_result {
+                ui.label(result);
             }
+        });
+}
+
+/// runs a procedure on its own thread, receive_cps_response picks up how it went
+/// one at a time, the meter answers one procedure before it takes the next
+fn run_cps_request(state: &mut CpsControlState, peripheral: &Peripheral, request: &CpsRequest) {
+    if let Some((pending, _)) = &state.request_pending {
+        state.status = Some(format!("Still waiting for {:?}", pending.op_code()));
+        return;
+    }
+    if state.calibration_pending.is_some() {
+        state.status = Some("Still waiting for the zero offset calibration".to_string());
+        return;
+    }
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let peripheral = peripheral.clone();
+    let thread_request = request.clone();
+    thread::spawn(move || {
+        let result = task::block_on(cps_request(&peripheral, &thread_request));
+        let _ = sender.send(result.map_err(|e| e.to_string()));
+    });
+    state.status = Some(format!("Waiting for {:?}...", request.op_code()));
+    state.request_pending = Some((request.clone(), receiver));
+}
+
+/// shows how the pending procedure went, answers to Get and Supported Locations fill the panel
+fn receive_cps_response(state: &mut CpsControlState) {
+    let Some((request, receiver)) = &state.request_pending else {
+        return;
+    };
+    let result = match receiver.try_recv() {
+        Ok(result) => result,
+        Err(std::sync::mpsc::TryRecvError::Empty) => return,
+        Err(std::sync::mpsc::TryRecvError::Disconnected) => {
+            Err("request thread stopped".to_string())
+        }
+    };
+    let status = match &result {
+        Ok(response) => format!("{:?}: {}", request.op_code(), response),
+        Err(e) => format!("{:?} failed: {}", request.op_code(), e),
+    };
+    println!("{}", status);
+    state.status = Some(status);
+    if let Ok(response) = result {
+        let setting = CpsSetting::ALL
+            .into_iter()
+            .find(|setting| setting.request() == *request);
+        if let (Some(setting), Some(value)) = (setting, CpsSetting::value(&response)) {
+            state.setting_text.insert(setting, value.to_string());
+        }
+        if let CpsResponse::SupportedSensorLocations(locations) = response {
+            state.supported_locations = locations;
         }
     }
+    state.request_pending = None;
 }
 
-/// draws the workout tab
-fn draw_workout_tab(ctx: &egui::Context, ui: &mut Ui, app_struct: &mut BikeApp) {
-    ui.horizontal(|ui| {
-        ui.label("Workout:");
-        // TODO: make this a text box so you can manually enter the file path???
-        if let Some(workout_file) = &app_struct.workout_file {
-            ui.label(format!("{:?}", workout_file));
-        } else {
-            ui.label("None");
+/// trainer data subscription, manual resistance and the latest trainer data
+fn draw_trainer_controls(ui: &mut Ui, app_struct: &mut BikeApp, trainer: &dyn Trainer) {
+    ui.label(format!("Trainer: {}", trainer.kind()));
+    let ranges = trainer.supported_ranges();
+    for (name, range, unit) in [
+        ("Power", ranges.power, " W"),
+        ("Resistance", ranges.resistance, ""),
+        ("Inclination", ranges.inclination, " %"),
+        ("Speed", ranges.speed, " km/h"),
+    ] {
+        if let Some(range) = range {
+            ui.label(format!(
+                "{} range: {} to {}{} in steps of {}",
+                name, range.minimum, range.maximum, unit, range.increment
+            ));
         }
-        if ui.button("Open").clicked() {
-            let mut dialog = FileDialog::open_file(app_struct.workout_file.clone())
-                .default_size(Vec2::new(500.0, 200.0));
-            dialog.open();
-            app_struct.workout_file_dialog = Some(dialog);
+    }
+    if app_struct.trainer_data_receiver.is_none()
+        && ui.button("Subscribe to Trainer Data").clicked()
+    {
+        if let Err(e) = subscribe_trainer_data(app_struct, trainer) {
+            println!("Failed to subscribe to trainer data: {}", e);
         }
-        if let Some(dialog) = &mut app_struct.workout_file_dialog {
-            if dialog.show(ctx).selected() {
-                if let Some(file) = dialog.path() {
-                    app_struct.workout_file = Some(file);
-                    // TODO: maybe actually load the file here?
+    }
+    if trainer.capabilities().resistance {
+        ui.horizontal(|ui| {
+            ui.label("Resistance:");
+            ui.text_edit_singleline(&mut app_struct.resistance_text);
+            if ui.button("Set Resistance").clicked() {
+                let result = if app_struct.trainer_controlled {
+                    Ok(())
+                } else {
+                    trainer.start(&app_struct.rider_settings)
+                };
+                app_struct.trainer_controlled = result.is_ok();
+                let result =
+                    result.and_then(|_| trainer.set_resistance(app_struct.resistance_value as f32));
+                match result {
+                    Ok(_) => println!("Resistance set to {}", app_struct.resistance_value),
+                    Err(e) => println!("Failed to set resistance: {}", e),
                 }
             }
+        });
+    }
+    if app_struct.trainer_data_receiver.is_some() {
+        if let Some(data) = &app_struct.indoor_bike_data {
+            draw_indoor_bike_data(ui, data);
+        }
+        draw_fec_data(ui, app_struct);
+        if let Some(measurement) = &app_struct.power_measurement {
+            draw_power_measurement(ui, measurement);
+            ui.horizontal(|ui| {
+                ui.label("Cadence:");
+                ui.label(format!("{:.0} rpm", app_struct.actual_cadence));
+            });
+            ui.horizontal(|ui| {
+                ui.label("Speed:");
+                ui.label(format!("{:.1} km/h", app_struct.actual_speed));
+            });
         }
+    }
+    draw_trainer_events(ui, app_struct);
+    if trainer.capabilities().spin_down {
+        draw_spin_down_calibration(ui, app_struct, trainer);
+    }
+    ui.separator();
+}
 
-        // separate load button for now, worry about it later
-        if ui.button("Load").clicked() {
-            println!("Loading workout...");
-            // TODO: error message if file not correct type, etc.
-            if app_struct.workout_file.is_some() {
-                let filepath = app_struct.workout_file.clone().unwrap();
-                match zwo_read(filepath) {
-                    Ok(workout) => app_struct.workout = Some(workout),
-                    Err(e) => println!("{:?}", e),
-                }
+/// guided spin down: warm up, speed up to what the trainer asks for, coast until it stops
+/// progress is drawn outside the header so it shows while the header is collapsed
+fn draw_spin_down_calibration(ui: &mut Ui, app_struct: &mut BikeApp, trainer: &dyn Trainer) {
+    let Some(peripheral) = app_struct.selected_peripheral.clone() else {
+        return;
+    };
+    let mut start = false;
+    let state = &mut app_struct.spin_down;
+    if state.pending.is_some() {
+        match state.target_speed {
+            Some((low, high)) if low == high => {
+                ui.label(format!("Spin down: speed up to {:.1} km/h.", low));
             }
-            if app_struct.workout.is_some() {
-                let workout = app_struct.workout.clone().unwrap();
-                match create_timeseries(workout) {
-                    Ok(time_series) => app_struct.workout_time_series = Some(time_series),
-                    Err(e) => println!("Error: {:?}", e),
-                }
+            Some((low, high)) => {
+                ui.label(format!(
+                    "Spin down: speed up to between {:.1} and {:.1} km/h.",
+                    low, high
+                ));
             }
-            println!("Complete.");
-        }
-
-        // demo of time series data
-        if ui.button("Start").clicked() {
-            app_struct.stop_workout_flag = false; // assuming this is necessary after clicking stop
-            if app_struct.workout_time_series.is_some() {
-                app_struct.workout_running = true;
-                // create receiver to give to new thread
-                let (tx, stop_receiver) = std::sync::mpsc::channel();
-                app_struct.stop_workout_sender = Some(tx);
-                println!("Workout started!");
-                let time_series = app_struct.workout_time_series.clone().unwrap();
-                let workout_sender = app_struct.workout_channel.0.clone();
-                thread::spawn(move || {
-                    for (i, _) in time_series.time.iter().enumerate() {
-                        // check to see if stop button has been clicked
-                        match stop_receiver.try_recv() {
-                            Ok(flag) => {
-                                if flag {
-                                    break;
-                                }
-                            }
-                            Err(_) => {}
-                        }
-                        let message = WorkoutMessage {
-                            time: time_series.time[i].clone(),
-                            target_cadence: time_series.cadence[i].clone(),
-                            target_power: time_series.power[i].clone(),
-                        };
-                        // TODO: better error handling here
-                        workout_sender.send(message).unwrap();
-                        thread::sleep(Duration::from_secs(1));
-                    }
-                });
-            } else {
-                println!("Load a workout first.");
+            None => {
+                ui.label("Spin down: waiting for the trainer...");
             }
         }
-
-        if ui.button("Stop").clicked() {
-            app_struct.stop_workout_flag = true;
-            // TODO: app_struct.workout running = false;
-            if app_struct.stop_workout_sender.is_some() {
-                let stop_sender = app_struct.stop_workout_sender.as_ref().unwrap();
-                // TODO: better error handling here
-                stop_sender
-                    .send(app_struct.stop_workout_flag.clone())
-                    .unwrap();
-            }
+        ui.label(format!("Speed: {:.1} km/h", app_struct.actual_speed));
+        if state.stop_pedaling {
+            ui.colored_label(
+                egui::Color32::GREEN,
+                "Stop pedaling and let the trainer coast to a stop.",
+            );
+        }
+        return;
+    }
+    egui::CollapsingHeader::new("Spin down calibration").show(ui, |ui| {
+        ui.label("1. Ride for about 10 minutes so the trainer is warmed up.");
+        ui.checkbox(&mut state.warmed_up, "2. Trainer is warmed up");
+        ui.label("3. Speed up to the target speed, then stop pedaling when asked.");
+        if app_struct.workout_running {
+            ui.colored_label(egui::Color32::RED, "Stop the workout first.");
+        }
+        let ready = state.warmed_up && !app_struct.workout_running;
+        start = ui
+            .add_enabled(ready, egui::Button::new("Start spin down"))
+            .clicked();
+        if let Some(result) = &state.result {
+            ui.label(result);
+        }
+        let device_id = peripheral.id().to_string();
+        let history = app_struct
+            .calibration_log
+            .history(&device_id)
+            .filter(|record| record.kind == CalibrationKind::SpinDown)
+            .take(SPIN_DOWN_HISTORY);
+        for record in history {
+            let time = match record.value {
+                Some(time) => format!("{:.2} s", time),
+                None => "done".to_string(),
+            };
+            ui.label(format!("{}: {}", format_unix_time(record.time), time));
         }
     });
+    if start {
+        app_struct.spin_down.warmed_up = false; // confirm again next time
+                                                // the rider needs the speed to reach the target
+        let subscribed = match app_struct.trainer_data_receiver {
+            Some(_) => Ok(()),
+            None => subscribe_trainer_data(app_struct, trainer),
+        };
+        let state = &mut app_struct.spin_down;
+        state.result = None;
+        match subscribed.and_then(|()| trainer.spin_down()) {
+            Ok(receiver) => state.pending = Some(receiver),
+            Err(e) => state.result = Some(format!("Spin down failed: {}", e)),
+        }
+    }
+}
 
-    // GUI for running workout
-    // user input
-    ui.horizontal(|ui| {
-        ui.label("FTP:");
-        ui.text_edit_singleline(&mut app_struct.user_ftp_string);
-    });
+/// speed, power and cadence from the trainer from now on
+fn subscribe_trainer_data(
+    app_struct: &mut BikeApp,
+    trainer: &dyn Trainer,
+) -> Result<(), BleRequestError> {
+    let receiver = trainer.measurements()?;
+    println!("Subscribed to {} trainer data.", trainer.kind());
+    // old samples would produce a bogus first rate
+    app_struct.trainer_decoder.reset();
+    app_struct.trainer_data_receiver = Some(receiver);
+    Ok(())
+}
 
-    if app_struct.workout_running {
-        // receive message from workout thread
-        match app_struct.workout_channel.1.try_recv() {
-            Ok(message) => {
-                app_struct.display_time = message.time;
-                app_struct.display_cadence = message.target_cadence;
-                app_struct.display_power = message.target_power;
+/// follows a running spin down, the result is saved with the time for the trainer
+fn receive_spin_down(app_struct: &mut BikeApp) {
+    let state = &mut app_struct.spin_down;
+    let Some(receiver) = &state.pending else {
+        return;
+    };
+    let outcome = loop {
+        match receiver.try_recv() {
+            Ok(SpinDownEvent::TargetSpeed(low, high)) => {
+                state.target_speed = Some((low, high));
+                state.stop_pedaling = false; // slowed down too early
+            }
+            Ok(SpinDownEvent::StopPedaling) => state.stop_pedaling = true,
+            Ok(SpinDownEvent::Done(result)) => break Ok(result),
+            Ok(SpinDownEvent::Failed(reason)) => break Err(reason),
+            Err(std::sync::mpsc::TryRecvError::Empty) => return,
+            Err(std::sync::mpsc::TryRecvError::Disconnected) => {
+                break Err("calibration thread stopped".to_string())
             }
-            Err(_) => {}
         }
+    };
+    *state = SpinDownState::default();
+    let result = match outcome {
+        Ok(result) => result,
+        Err(reason) => {
+            state.result = Some(format!("Spin down failed: {}", reason));
+            println!("{}", state.result.clone().unwrap());
+            return;
+        }
+    };
+    let device_id = app_struct
+        .selected_peripheral
+        .as_ref()
+        .map(|peripheral| peripheral.id().to_string())
+        .unwrap_or_default();
+    let mut text = "Spin down done".to_string();
+    if let Some(time) = result.spin_down_time {
+        text.push_str(&format!(", {:.2} s", time));
+        let previous = app_struct
+            .calibration_log
+            .history(&device_id)
+            .filter(|record| record.kind == CalibrationKind::SpinDown)
+            .find_map(|record| record.value);
+        if let Some(previous) = previous {
+            text.push_str(&format!(" (change {:+.2} s)", time - previous));
+        }
+    }
+    if let Some(temperature) = result.temperature {
+        text.push_str(&format!(" at {:.1} °C", temperature));
+    }
+    if let Some(offset) = result.zero_offset {
+        text.push_str(&format!(", zero offset {}", offset));
+    }
+    println!("{}: {}", app_struct.peripheral_text, text);
+    state.result = Some(text);
+    app_struct.calibration_log.add(CalibrationRecord {
+        device_id,
+        device_name: app_struct.peripheral_text.clone(),
+        time: unix_time_now(),
+        kind: CalibrationKind::SpinDown,
+        value: result.spin_down_time,
+    });
+    if let Err(e) = app_struct.calibration_log.save(Path::new(CALIBRATION_FILE)) {
+        println!("Failed to save calibrations: {}", e);
+    }
+}
+
+/// the latest FE-C pages 16/25
+fn draw_fec_data(ui: &mut Ui, app_struct: &BikeApp) {
+    if let Some(data) = &app_struct.fec_trainer_data {
+        if let Some(power) = data.instantaneous_power {
+            ui.horizontal(|ui| {
+                ui.label("Power:");
+                ui.label(format!("{} W", power));
+            });
+        }
+        if let Some(cadence) = data.cadence {
+            ui.horizontal(|ui| {
+                ui.label("Cadence:");
+                ui.label(format!("{} rpm", cadence));
+            });
+        }
+        if data.user_configuration_required {
+            ui.label("Trainer needs user configuration.");
+        }
+        if data.bicycle_power_calibration_required || data.resistance_calibration_required {
+            ui.label("Trainer needs calibration.");
+        }
+    }
+    if let Some(data) = &app_struct.fec_general_data {
         ui.horizontal(|ui| {
-            ui.label("Time:");
-            ui.label(app_struct.display_time.to_string());
-        });
-        ui.horizontal(|ui| {
-            ui.label("Cadence:");
-            ui.label(app_struct.display_cadence.to_string());
+            ui.label("Speed:");
+            ui.label(format!("{:.2} km/h", data.speed));
         });
         ui.horizontal(|ui| {
-            ui.label("Power:");
-            ui.label((app_struct.display_power * app_struct.user_ftp as f32).to_string());
+            ui.label("State:");
+            ui.label(format!("{:?}", data.fe_state));
         });
     }
 }
 
-/// draws the bluetooth tab
-fn draw_bluetooth_tab(ui: &mut Ui, app_struct: &mut BikeApp) {
-    ui.horizontal(|ui| {
-        if ui.button("Adapter").clicked() {
-            println!("Searching for adapter...");
-            app_struct.bt_adapters = task::block_on(bt_adapter_scan());
-            println!("Complete.");
+/// drains trainer and sensor data, then picks each live value from its assigned device
+fn receive_measurements(app_struct: &mut BikeApp) {
+    let now = Instant::now();
+    if let Some(receiver) = &app_struct.trainer_data_receiver {
+        while let Ok(data) = receiver.try_recv() {
+            match &data {
+                TrainerData::Cps(measurement) => {
+                    app_struct.power_measurement = Some(measurement.clone());
+                }
+                TrainerData::IndoorBike(data) => app_struct.indoor_bike_data = Some(data.clone()),
+                TrainerData::Fec(FecPage::GeneralFeData(data)) => {
+                    app_struct.fec_general_data = Some(*data);
+                }
+                TrainerData::Fec(FecPage::SpecificTrainerData(data)) => {
+                    app_struct.fec_trainer_data = Some(*data);
+                }
+                TrainerData::Fec(_) => {} // other pages (e.g. echoed commands) carry no live values
+            }
+            let values = app_struct
+                .trainer_decoder
+                .decode(&SensorData::Trainer(data), now);
+            for (metric, value) in values {
+                app_struct
+                    .metric_sources
+                    .update(TRAINER_DEVICE, metric, value, now);
+            }
         }
-        if app_struct.adapter_moved {
-            let adapter_info_str: String;
-            match task::block_on(app_struct.selected_adapter.clone().unwrap().adapter_info()) {
-                Ok(info) => adapter_info_str = info,
-                Err(e) => adapter_info_str = e.to_string(),
+    }
+    while let Ok(event) = app_struct.sensor_event_channel.1.try_recv() {
+        let Some(device) = app_struct.sensors.iter_mut().find(|d| d.id == event.device) else {
+            continue; // disconnected while the notification was queued
+        };
+        for (metric, value) in device.decoder.decode(&event.data, now) {
+            app_struct
+                .metric_sources
+                .update(device.id, metric, value, now);
+        }
+        if let SensorData::PowerVector(vector) = &event.data {
+            device.pedal_stroke.update(vector);
+        }
+        if let SensorData::HeartRate(measurement) = event.data {
+            let is_source = app_struct
+                .metric_sources
+                .current(Metric::HeartRate, now)
+                .is_some_and(|(id, _)| id == device.id);
+            // RR intervals from two straps at once would make no sense
+            if app_struct.workout_running && is_source {
+                app_struct
+                    .ride_record
+                    .add_rr_intervals(&measurement.rr_intervals);
             }
-            egui::ComboBox::from_label("Choose an adapter.")
-                .selected_text(&app_struct.adapter_text)
-                .show_ui(ui, |ui| {
-                    ui.selectable_value(
-                        &mut app_struct.selected_adapter_number,
-                        Some(0 as usize),
+            device.heart_rate_measurement = Some(measurement);
+        }
+    }
+    // evaluated every frame so a device that goes quiet hands over to the fallback
+    let sources = &app_struct.metric_sources;
+    let current = |metric| sources.current(metric, now).map(|(_, value)| value);
+    app_struct.actual_power = current(Metric::Power).unwrap_or(0.0);
+    app_struct.actual_cadence = current(Metric::Cadence).unwrap_or(0.0);
+    app_struct.actual_speed = current(Metric::Speed).unwrap_or(0.0);
+    app_struct.actual_heart_rate = current(Metric::HeartRate).map(|bpm| bpm as u16);
+    app_struct.actual_pedal_dynamics = PedalDynamics {
+        left_balance: current(Metric::LeftBalance),
+        torque_effectiveness: current(Metric::TorqueEffectiveness),
+        pedal_smoothness: current(Metric::PedalSmoothness),
+    };
+    if let Ok(mut live_speed) = app_struct.live_speed.lock() {
+        *live_speed = app_struct.actual_speed;
+    }
+}
+
+/// name, RSSI and advertised services for the device combo boxes
+/// None if the fitness filter hides it
+fn discovered_label(
+    discovered: &HashMap<PeripheralId, DiscoveredDevice>,
+    fitness_only: bool,
+    peripheral: &Peripheral,
+) -> Option<String> {
+    match discovered.get(&peripheral.id()) {
+        Some(device) if fitness_only && !device.is_fitness_device() => None,
+        Some(device) => Some(device.label()),
+        None if fitness_only => None,
+        None => Some(task::block_on(update_peripheral_text(peripheral))),
+    }
+}
+
+/// names of a discovered peripheral's services, for the Bluetooth tab and logs
+fn service_summary(peripheral: &Peripheral) -> String {
+    peripheral
+        .services()
+        .iter()
+        .map(|service| attribute_name(service.uuid))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// trainer or sensor name for logs
+fn device_name(app_struct: &BikeApp, device: DeviceId) -> String {
+    match app_struct.sensors.iter().find(|d| d.id == device) {
+        Some(sensor) => sensor.name.clone(),
+        None if device == TRAINER_DEVICE => "Trainer".to_string(),
+        None => format!("Device {}", device),
+    }
+}
+
+/// logs dropouts and restores subscriptions and trainer control once the supervisor is back
+fn handle_connection_events(app_struct: &mut BikeApp) {
+    while let Ok(event) = app_struct.connection_event_channel.1.try_recv() {
+        let message = match event {
+            ConnectionEvent::Disconnected { device } => {
+                app_struct.reconnecting.push((device, Instant::now()));
+                format!("{} disconnected", device_name(app_struct, device))
+            }
+            ConnectionEvent::Reconnected { device, gap } => {
+                app_struct.reconnecting.retain(|(id, _)| *id != device);
+                // the explorer's notification stream and subscriptions ended with the old connection
+                app_struct.gatt_loggers.remove(&device);
+                if let Ok(mut watched) = app_struct.gatt_watched.lock() {
+                    watched.retain(|(id, _)| *id != device);
+                }
+                let result = match device {
+                    TRAINER_DEVICE => resume_trainer(app_struct),
+                    _ => match app_struct.sensors.iter_mut().find(|d| d.id == device) {
+                        Some(sensor) => {
+                            resubscribe_sensor(sensor, app_struct.sensor_event_channel.0.clone())
+                        }
+                        None => Ok(()),
+                    },
+                };
+                let result = result.and_then(|()| resubscribe_battery_level(app_struct, device));
+                if let Err(e) = result {
+                    println!(
+                        "Failed to restore {}: {}",
+                        device_name(app_struct, device),
+                        e
+                    );
+                }
+                format!(
+                    "{} reconnected after {:.1} s",
+                    device_name(app_struct, device),
+                    gap.as_secs_f32()
+                )
+            }
+        };
+        println!("{}", message);
+        if app_struct.workout_running {
+            app_struct
+                .ride_record
+                .add_event(app_struct.display_time, message);
+        }
+    }
+}
+
+/// re-subscribes to trainer data and has the workout thread take control again
+fn resume_trainer(app_struct: &mut BikeApp) -> Result<(), BleRequestError> {
+    let Some(trainer) = app_struct.trainer.clone() else {
+        return Ok(());
+    };
+    if app_struct.trainer_data_receiver.is_some() {
+        app_struct.trainer_decoder.reset();
+        app_struct.trainer_data_receiver = Some(trainer.measurements()?);
+    }
+    if app_struct.trainer_status_receiver.is_some() {
+        app_struct.trainer_status_receiver = Some(trainer.status()?);
+    }
+    app_struct.trainer_controlled = false; // a reconnected trainer has forgotten us
+    if let Some(sender) = &app_struct.workout_command_sender {
+        let _ = sender.send(WorkoutCommand::TrainerReconnected);
+    }
+    Ok(())
+}
+
+/// connects the selected peripheral as the trainer
+fn connect_trainer(app_struct: &mut BikeApp) {
+    println!("Connecting to device...");
+    let peripheral = app_struct.selected_peripheral.clone().unwrap();
+    match task::block_on(peripheral.connect()) {
+        Ok(()) => {
+            println!("Device connected.");
+            app_struct.peripheral_connected = true;
+            match task::block_on(peripheral.discover_services()) {
+                Ok(()) => {
+                    let trainer = create_trainer(&peripheral);
+                    use_trainer(app_struct, &peripheral, trainer);
+                    load_device_details(app_struct, TRAINER_DEVICE, &peripheral);
+                }
+                Err(e) => println!("Failed to discover services.  {:?}", e),
+            }
+        }
+        Err(e) => {
+            println!("Failed to connect.  {:?}", e);
+        }
+    }
+}
+
+/// makes a connected peripheral with its services discovered the trainer
+fn use_trainer(
+    app_struct: &mut BikeApp,
+    peripheral: &Peripheral,
+    trainer: Option<Box<dyn Trainer>>,
+) {
+    app_struct.trainer = trainer.map(Arc::from);
+    app_struct.trainer_controlled = false;
+    app_struct.virtual_trainer = None;
+    app_struct.trainer_data_receiver = None;
+    app_struct.trainer_events.clear();
+    // only FTMS trainers report their status
+    app_struct.trainer_status_receiver = app_struct
+        .trainer
+        .as_ref()
+        .and_then(|trainer| trainer.status().ok());
+    app_struct
+        .watch_list
+        .watch(TRAINER_DEVICE, peripheral.clone());
+    println!("Services: {}", service_summary(peripheral));
+    match &app_struct.trainer {
+        Some(trainer) => println!("Trainer type: {}", trainer.kind()),
+        None => println!("Device is not a trainer or power meter."),
+    }
+    let name = app_struct.peripheral_text.clone();
+    remember_device(app_struct, peripheral, DeviceRole::Trainer, name);
+}
+
+/// saves a device that just connected, so it is connected again on the next launch
+fn remember_device(
+    app_struct: &mut BikeApp,
+    peripheral: &Peripheral,
+    role: DeviceRole,
+    name: String,
+) {
+    let adapter = match &app_struct.selected_adapter {
+        Some(adapter) => task::block_on(update_adapter_text(adapter)),
+        None => String::new(),
+    };
+    app_struct.known_devices.remember(KnownDevice {
+        id: peripheral.id().to_string(),
+        address: peripheral.address().to_string(),
+        role,
+        adapter,
+        name,
+    });
+    if let Err(e) = app_struct.known_devices.save(Path::new(KNOWN_DEVICES_FILE)) {
+        println!("Failed to save known devices: {}", e);
+    }
+}
+
+/// on launch, picks the adapter the remembered devices were found on and scans for them
+/// the adapter scan runs on its own thread, receive_auto_connect picks up the adapters
+fn start_auto_connect(app_struct: &mut BikeApp) {
+    match app_struct.auto_connect {
+        AutoConnect::Waiting if app_struct.adapter_moved => {
+            println!("Scanning for remembered devices...");
+            let peripheral_sender = app_struct.peripheral_channel.0.clone();
+            let selected_adapter = app_struct.selected_adapter.clone().unwrap();
+            app_struct.discovery = Some(start_discovery(selected_adapter, peripheral_sender));
+            app_struct.auto_connect = AutoConnect::Scanning(Instant::now());
+        }
+        // moved into selected_adapter later this frame
+        AutoConnect::Waiting if app_struct.selected_adapter_number.is_some() => {}
+        AutoConnect::Waiting => {
+            app_struct.auto_connect = AutoConnect::FindingAdapter;
+            let known_adapters: Vec<String> = app_struct
+                .known_devices
+                .devices
+                .iter()
+                .map(|known| known.adapter.clone())
+                .collect();
+            let sender = app_struct.auto_connect_channel.0.clone();
+            thread::spawn(move || {
+                let adapters = task::block_on(bt_adapter_scan()).unwrap_or_default();
+                let index = adapters
+                    .iter()
+                    .position(|adapter| {
+                        known_adapters.contains(&task::block_on(update_adapter_text(adapter)))
+                    })
+                    .unwrap_or(0);
+                let _ = sender.send(AutoConnected::Adapters(adapters, index));
+            });
+        }
+        AutoConnect::Scanning(since) if since.elapsed() >= AUTO_CONNECT_TIMEOUT => {
+            println!("Not every remembered device showed up, stopping scan.");
+            app_struct.auto_connect = AutoConnect::Off;
+            app_struct.discovery = None;
+        }
+        _ => {}
+    }
+}
+
+/// finishes off what the auto connect threads did
+fn receive_auto_connect(app_struct: &mut BikeApp) {
+    while let Ok(connected) = app_struct.auto_connect_channel.1.try_recv() {
+        match connected {
+            AutoConnected::Adapters(adapters, _) if adapters.is_empty() => {
+                println!("No adapter, not connecting remembered devices.");
+                app_struct.auto_connect = AutoConnect::Off;
+            }
+            AutoConnected::Adapters(adapters, index) => {
+                app_struct.bt_adapters = Some(adapters);
+                app_struct.selected_adapter_number = Some(index);
+                app_struct.auto_connect = AutoConnect::Waiting;
+            }
+            AutoConnected::Trainer(peripheral, trainer, details) => {
+                println!("Device connected.");
+                app_struct.peripheral_connected = true;
+                use_trainer(app_struct, &peripheral, trainer);
+                app_struct.device_details.insert(TRAINER_DEVICE, details);
+                check_battery(app_struct, TRAINER_DEVICE);
+            }
+            AutoConnected::Sensor(device, details) => {
+                let id = device.id;
+                add_sensor(app_struct, *device);
+                app_struct.device_details.insert(id, details);
+                check_battery(app_struct, id);
+            }
+            AutoConnected::Failed(message) => println!("{}", message),
+        }
+    }
+}
+
+/// adds newly discovered peripherals to the device lists, connecting remembered ones
+fn receive_discoveries(app_struct: &mut BikeApp) {
+    while let Ok(device) = app_struct.peripheral_channel.1.try_recv() {
+        let id = device.id();
+        if matches!(app_struct.auto_connect, AutoConnect::Scanning(_)) {
+            auto_connect_device(app_struct, &device);
+        }
+        let peripherals = app_struct.peripheral_list.get_or_insert_with(Vec::new);
+        let known = peripherals.iter().any(|peripheral| peripheral.id() == id)
+            || app_struct
+                .selected_peripheral
+                .as_ref()
+                .is_some_and(|peripheral| peripheral.id() == id);
+        if !known {
+            // appended so the indices the combo boxes hold stay valid
+            peripherals.push(device.peripheral.clone());
+        }
+        app_struct.discovered.insert(id, device);
+    }
+}
+
+/// connects a peripheral in the role it was remembered with, once per launch
+/// connecting and the first reads run on their own thread, see receive_auto_connect
+fn auto_connect_device(app_struct: &mut BikeApp, device: &DiscoveredDevice) {
+    let peripheral = device.peripheral.clone();
+    let id = peripheral.id().to_string();
+    let address = peripheral.address().to_string();
+    let Some(known) = app_struct.known_devices.find(&id, &address).cloned() else {
+        return;
+    };
+    if !app_struct.auto_connect_tried.insert(known.id.clone()) {
+        return;
+    }
+    println!("Found remembered {} {}.", known.role, known.name);
+    let sender = app_struct.auto_connect_channel.0.clone();
+    let battery_sender = app_struct.battery_channel.0.clone();
+    match known.role {
+        DeviceRole::Trainer if !app_struct.peripheral_moved => {
+            // selected straight away so nothing else is picked as the trainer meanwhile
+            app_struct.peripheral_text = known.name;
+            app_struct.selected_peripheral = Some(peripheral.clone());
+            app_struct.peripheral_moved = true;
+            thread::spawn(move || {
+                let connected = task::block_on(async {
+                    peripheral.connect().await?;
+                    peripheral.discover_services().await
+                });
+                let _ = sender.send(match connected {
+                    Ok(()) => {
+                        let trainer = create_trainer(&peripheral);
+                        let details =
+                            read_device_details(&peripheral, TRAINER_DEVICE, battery_sender);
+                        AutoConnected::Trainer(peripheral, trainer, details)
+                    }
+                    Err(e) => AutoConnected::Failed(format!("Failed to connect.  {:?}", e)),
+                });
+            });
+        }
+        DeviceRole::Trainer => println!("Another trainer is already selected."),
+        DeviceRole::Sensor => {
+            // taken now, other sensors may connect while this one does
+            let id = app_struct.next_device_id;
+            app_struct.next_device_id += 1;
+            let advertised = device.classification.clone();
+            let event_sender = app_struct.sensor_event_channel.0.clone();
+            thread::spawn(move || {
+                let connected =
+                    connect_sensor(peripheral, id, known.name, Some(&advertised), event_sender);
+                let _ = sender.send(match connected {
+                    Ok(device) => {
+                        let details = read_device_details(&device.peripheral, id, battery_sender);
+                        AutoConnected::Sensor(Box::new(device), details)
+                    }
+                    Err(e) => AutoConnected::Failed(format!("Failed to connect sensor: {}", e)),
+                });
+            });
+        }
+    }
+    if app_struct.auto_connect_tried.len() >= app_struct.known_devices.devices.len() {
+        println!("Tried every remembered device, stopping scan.");
+        app_struct.auto_connect = AutoConnect::Off;
+        app_struct.discovery = None;
+    }
+}
+
+/// reads Device Information and the battery level of a device that just connected
+fn load_device_details(app_struct: &mut BikeApp, device: DeviceId, peripheral: &Peripheral) {
+    let details = read_device_details(peripheral, device, app_struct.battery_channel.0.clone());
+    app_struct.device_details.insert(device, details);
+    check_battery(app_struct, device);
+}
+
+/// battery notifications go to battery_sender from now on
+fn read_device_details(
+    peripheral: &Peripheral,
+    device: DeviceId,
+    battery_sender: std::sync::mpsc::Sender<BatteryLevel>,
+) -> DeviceDetails {
+    let mut details = DeviceDetails {
+        information: task::block_on(read_device_information(peripheral)),
+        ..Default::default()
+    };
+    match subscribe_battery_level(peripheral, device, battery_sender) {
+        Ok(level) => details.battery_level = level,
+        Err(e) => println!("Failed to read battery level: {}", e),
+    }
+    details
+}
+
+/// the battery notification thread ended with the old connection
+fn resubscribe_battery_level(
+    app_struct: &mut BikeApp,
+    device: DeviceId,
+) -> Result<(), BleRequestError> {
+    let Some(peripheral) = device_peripheral(app_struct, device) else {
+        return Ok(());
+    };
+    let level = subscribe_battery_level(&peripheral, device, app_struct.battery_channel.0.clone())?;
+    if let Some(details) = app_struct.device_details.get_mut(&device) {
+        details.battery_level = level;
+    }
+    check_battery(app_struct, device);
+    Ok(())
+}
+
+fn receive_battery_levels(app_struct: &mut BikeApp) {
+    while let Ok(battery) = app_struct.battery_channel.1.try_recv() {
+        let Some(details) = app_struct.device_details.get_mut(&battery.device) else {
+            continue; // disconnected while the notification was queued
+        };
+        details.battery_level = Some(battery.level);
+        check_battery(app_struct, battery.device);
+    }
+}
+
+/// shows every trainer status event and passes the ones it has to act on to the workout
+fn receive_trainer_status(app_struct: &mut BikeApp) {
+    let Some(receiver) = &app_struct.trainer_status_receiver else {
+        return;
+    };
+    let statuses: Vec<FtmsStatus> = receiver.try_iter().collect();
+    for status in statuses {
+        let message = status.to_string();
+        println!("{}", message);
+        let target_change =
+            matches!(&status, FtmsStatus::Machine(machine) if machine.is_target_change());
+        // targets are echoed every step in ERG mode, only the latest is worth showing
+        if target_change && app_struct.trainer_events.last().is_some_and(|(t, _)| *t) {
+            app_struct.trainer_events.pop();
+        }
+        app_struct
+            .trainer_events
+            .push((target_change, message.clone()));
+        if app_struct.trainer_events.len() > TRAINER_EVENT_HISTORY {
+            app_struct.trainer_events.remove(0);
+        }
+        if status == FtmsStatus::Machine(MachineStatus::ControlPermissionLost) {
+            app_struct.trainer_controlled = false;
+        }
+        if !app_struct.workout_running {
+            continue;
+        }
+        if !target_change {
+            app_struct
+                .ride_record
+                .add_event(app_struct.display_time, message);
+        }
+        let Some(sender) = &app_struct.workout_command_sender else {
+            continue;
+        };
+        // our own pause and resume are echoed too, those change nothing here
+        let (command, paused) = match status {
+            FtmsStatus::Machine(MachineStatus::ControlPermissionLost) => {
+                (WorkoutCommand::ControlLost, app_struct.workout_paused)
+            }
+            FtmsStatus::Machine(
+                MachineStatus::StoppedOrPaused(_) | MachineStatus::StoppedBySafetyKey,
+            ) if !app_struct.workout_paused => (WorkoutCommand::Pause, true),
+            FtmsStatus::Machine(MachineStatus::StartedOrResumed) if app_struct.workout_paused => {
+                (WorkoutCommand::Resume, false)
+            }
+            _ => continue,
+        };
+        if sender.send(command).is_ok() {
+            app_struct.workout_paused = paused;
+        }
+    }
+}
+
+/// latest trainer status events, oldest first
+fn draw_trainer_events(ui: &mut Ui, app_struct: &BikeApp) {
+    if app_struct.trainer_events.is_empty() {
+        return;
+    }
+    egui::CollapsingHeader::new("Trainer events")
+        .default_open(true)
+        .show(ui, |ui| {
+            for (_, message) in &app_struct.trainer_events {
+                ui.label(message);
+            }
+        });
+}
+
+/// logs a battery going low once, and into the ride record if a workout is running
+fn check_battery(app_struct: &mut BikeApp, device: DeviceId) {
+    let Some(details) = app_struct.device_details.get(&device) else {
+        return;
+    };
+    let low = details.is_low_battery();
+    if low && !details.low_battery_warned {
+        let message = low_battery_message(app_struct, device, details);
+        println!("{}", message);
+        if app_struct.workout_running {
+            app_struct
+                .ride_record
+                .add_event(app_struct.display_time, message);
+        }
+    }
+    if let Some(details) = app_struct.device_details.get_mut(&device) {
+        details.low_battery_warned = low; // warns again if it was charged in between
+    }
+}
+
+fn low_battery_message(app_struct: &BikeApp, device: DeviceId, details: &DeviceDetails) -> String {
+    format!(
+        "{} battery low ({}%)",
+        device_name(app_struct, device),
+        details.battery_level.unwrap_or(0)
+    )
+}
+
+/// connects the selected sensor alongside the trainer
+fn connect_sensor_device(app_struct: &mut BikeApp, peripheral: Peripheral) {
+    if app_struct
+        .sensors
+        .iter()
+        .any(|device| device.peripheral.id() == peripheral.id())
+    {
+        println!("Sensor is already connected.");
+        return;
+    }
+    let name = task::block_on(update_peripheral_text(&peripheral));
+    println!("Connecting to {}...", name);
+    let id = app_struct.next_device_id;
+    let advertised = app_struct
+        .discovered
+        .get(&peripheral.id())
+        .map(|device| &device.classification);
+    match connect_sensor(
+        peripheral,
+        id,
+        name,
+        advertised,
+        app_struct.sensor_event_channel.0.clone(),
+    ) {
+        Ok(device) => {
+            app_struct.next_device_id += 1;
+            let peripheral = device.peripheral.clone();
+            add_sensor(app_struct, device);
+            load_device_details(app_struct, id, &peripheral);
+        }
+        Err(e) => println!("Failed to connect sensor: {}", e),
+    }
+}
+
+/// adds a connected sensor to the session and remembers it
+fn add_sensor(app_struct: &mut BikeApp, device: SessionDevice) {
+    println!("Subscribed to {} ({}).", device.name, device.classification);
+    remember_device(
+        app_struct,
+        &device.peripheral,
+        DeviceRole::Sensor,
+        device.name.clone(),
+    );
+    app_struct
+        .metric_sources
+        .pre_assign(device.id, device.classification.class.roles());
+    app_struct
+        .watch_list
+        .watch(device.id, device.peripheral.clone());
+    app_struct.sensors.push(device);
+}
+
+/// draws the fields of the latest Indoor Bike Data, skipping ones the trainer doesn't send
+fn draw_indoor_bike_data(ui: &mut Ui, data: &IndoorBikeData) {
+    if let Some(power) = data.instantaneous_power {
+        ui.horizontal(|ui| {
+            ui.label("Power:");
+            ui.label(format!("{} W", power));
+        });
+    }
+    if let Some(cadence) = data.instantaneous_cadence {
+        ui.horizontal(|ui| {
+            ui.label("Cadence:");
+            ui.label(format!("{:.1} rpm", cadence));
+        });
+    }
+    if let Some(speed) = data.instantaneous_speed {
+        ui.horizontal(|ui| {
+            ui.label("Speed:");
+            ui.label(format!("{:.2} km/h", speed));
+        });
+    }
+    if let Some(distance) = data.total_distance {
+        ui.horizontal(|ui| {
+            ui.label("Distance:");
+            ui.label(format!("{} m", distance));
+        });
+    }
+    if let Some(resistance) = data.resistance_level {
+        ui.horizontal(|ui| {
+            ui.label("Resistance:");
+            ui.label(resistance.to_string());
+        });
+    }
+    if let Some(heart_rate) = data.heart_rate {
+        ui.horizontal(|ui| {
+            ui.label("Heart rate:");
+            ui.label(format!("{} bpm", heart_rate));
+        });
+    }
+    if let Some(energy) = data.total_energy {
+        ui.horizontal(|ui| {
+            ui.label("Energy:");
+            ui.label(format!("{} kcal", energy));
+        });
+    }
+    if let Some(elapsed) = data.elapsed_time {
+        ui.horizontal(|ui| {
+            ui.label("Elapsed:");
+            ui.label(format!("{} s", elapsed));
+        });
+    }
+}
+
+/// draws the fields of the latest power measurement, skipping ones the sensor doesn't send
+fn draw_power_measurement(ui: &mut Ui, measurement: &CpsMeasurement) {
+    ui.horizontal(|ui| {
+        ui.label("Power:");
+        ui.label(format!("{} W", measurement.instantaneous_power));
+    });
+    if let Some(balance) = measurement.pedal_power_balance {
+        ui.horizontal(|ui| {
+            ui.label("Pedal balance:");
+            ui.label(format!("{:.1} %", balance));
+        });
+    }
+    if let Some(torque) = measurement.accumulated_torque {
+        ui.horizontal(|ui| {
+            ui.label("Accumulated torque:");
+            ui.label(format!("{:.2} Nm", torque));
+        });
+    }
+    if let Some(wheel) = measurement.wheel_revolution_data {
+        ui.horizontal(|ui| {
+            ui.label("Wheel revolutions:");
+            ui.label(wheel.cumulative_revolutions.to_string());
+        });
+    }
+    if let Some(crank) = measurement.crank_revolution_data {
+        ui.horizontal(|ui| {
+            ui.label("Crank revolutions:");
+            ui.label(crank.cumulative_revolutions.to_string());
+        });
+    }
+    if let (Some(max), Some(min)) = (
+        measurement.maximum_force_magnitude,
+        measurement.minimum_force_magnitude,
+    ) {
+        ui.horizontal(|ui| {
+            ui.label("Force (max/min):");
+            ui.label(format!("{} / {} N", max, min));
+        });
+    }
+    if let (Some(max), Some(min)) = (
+        measurement.maximum_torque_magnitude,
+        measurement.minimum_torque_magnitude,
+    ) {
+        ui.horizontal(|ui| {
+            ui.label("Torque (max/min):");
+            ui.label(format!("{:.2} / {:.2} Nm", max, min));
+        });
+    }
+    if let (Some(max), Some(min)) = (measurement.maximum_angle, measurement.minimum_angle) {
+        ui.horizontal(|ui| {
+            ui.label("Extreme angles (max/min):");
+            ui.label(format!("{}° / {}°", max, min));
+        });
+    }
+    if let (Some(top), Some(bottom)) = (
+        measurement.top_dead_spot_angle,
+        measurement.bottom_dead_spot_angle,
+    ) {
+        ui.horizontal(|ui| {
+            ui.label("Dead spots (top/bottom):");
+            ui.label(format!("{}° / {}°", top, bottom));
+        });
+    }
+    if let Some(energy) = measurement.accumulated_energy {
+        ui.horizontal(|ui| {
+            ui.label("Energy:");
+            ui.label(format!("{} kJ", energy));
+        });
+    }
+}
+
+/// polar plot of force or torque against crank angle, top dead centre up and clockwise
+/// the circle is zero, pushing goes outside it and pulling up inside
+fn draw_pedal_stroke(ui: &mut Ui, stroke: &PedalStroke) {
+    let peak = stroke
+        .bins()
+        .map(|(_, value)| value.abs())
+        .fold(0.0, f32::max)
+        .max(1.0);
+    let (response, painter) = ui.allocate_painter(
+        egui::vec2(PEDAL_STROKE_PLOT_SIZE, PEDAL_STROKE_PLOT_SIZE),
+        egui::Sense::hover(),
+    );
+    let centre = response.rect.center();
+    let zero_radius = PEDAL_STROKE_PLOT_SIZE / 4.0;
+    let scale = (PEDAL_STROKE_PLOT_SIZE / 2.0 - zero_radius - 4.0) / peak;
+    let point = |angle: f32, radius: f32| {
+        let angle = angle.to_radians();
+        centre + egui::vec2(radius * angle.sin(), -radius * angle.cos())
+    };
+    let grid = ui.visuals().weak_text_color();
+    painter.circle_stroke(centre, zero_radius, egui::Stroke::new(1.0, grid));
+    for angle in [0.0, 90.0, 180.0, 270.0] {
+        painter.line_segment(
+            [
+                point(angle, 0.0),
+                point(angle, PEDAL_STROKE_PLOT_SIZE / 2.0),
+            ],
+            egui::Stroke::new(0.5, grid),
+        );
+    }
+    let points: Vec<egui::Pos2> = stroke
+        .bins()
+        .map(|(angle, value)| point(angle, (zero_radius + value * scale).max(0.0)))
+        .collect();
+    painter.add(egui::Shape::closed_line(
+        points,
+        egui::Stroke::new(2.0, egui::Color32::LIGHT_BLUE),
+    ));
+    let unit = if stroke.torque { "Nm" } else { "N" };
+    ui.label(format!(
+        "Peak {:.1} {} ({:?})",
+        peak, unit, stroke.direction
+    ));
+}
+
+/// draws the workout tab
+fn draw_workout_tab(ctx: &egui::Context, ui: &mut Ui, app_struct: &mut BikeApp) {
+    ui.horizontal(|ui| {
+        ui.label("Workout:");
+        // TODO: make this a text box so you can manually enter the file path???
+        if let Some(workout_file) = &app_struct.workout_file {
+            ui.label(format!("{:?}", workout_file));
+        } else {
+            ui.label("None");
+        }
+        if ui.button("Open").clicked() {
+            let mut dialog = FileDialog::open_file(app_struct.workout_file.clone())
+                .default_size(Vec2::new(500.0, 200.0));
+            dialog.open();
+            app_struct.workout_file_dialog = Some(dialog);
+        }
+        if let Some(dialog) = &mut app_struct.workout_file_dialog {
+            if dialog.show(ctx).selected() {
+                if let Some(file) = dialog.path() {
+                    app_struct.workout_file = Some(file);
+                    // TODO: maybe actually load the file here?
+                }
+            }
+        }
+
+        // separate load button for now, worry about it later
+        if ui.button("Load").clicked() {
+            println!("Loading workout...");
+            // TODO: error message if file not correct type, etc.
+            if app_struct.workout_file.is_some() {
+                let filepath = app_struct.workout_file.clone().unwrap();
+                match zwo_read(filepath) {
+                    Ok(workout) => app_struct.workout = Some(workout),
+                    Err(e) => println!("{:?}", e),
+                }
+            }
+            if app_struct.workout.is_some() {
+                let workout = app_struct.workout.clone().unwrap();
+                match free_ride_mask(&workout) {
+                    Ok(mask) => app_struct.workout_free_ride = Some(mask),
+                    Err(e) => println!("Error: {:?}", e),
+                }
+                match interval_numbers(&workout) {
+                    Ok(numbers) => app_struct.workout_intervals = Some(numbers),
+                    Err(e) => println!("Error: {:?}", e),
+                }
+                match create_timeseries(workout) {
+                    Ok(time_series) => app_struct.workout_time_series = Some(time_series),
+                    Err(e) => println!("Error: {:?}", e),
+                }
+            }
+            println!("Complete.");
+        }
+
+        // demo of time series data
+        if ui.button("Start").clicked() {
+            app_struct.stop_workout_flag = false; // assuming this is necessary after clicking stop
+            let simulation = if app_struct.sim_mode {
+                match GradeProfile::parse(&app_struct.grade_profile_text) {
+                    Ok(profile) => Some(SimulationController::new(
+                        profile,
+                        app_struct.rider_settings,
+                    )),
+                    Err(e) => {
+                        println!("{}", e);
+                        None
+                    }
+                }
+            } else {
+                None
+            };
+            if app_struct.sim_mode && simulation.is_none() {
+                println!("Fix the grade profile before starting.");
+            } else if app_struct.workout_time_series.is_some() {
+                app_struct.workout_running = true;
+                app_struct.workout_paused = false;
+                // the runner hands control back when it ends
+                app_struct.trainer_controlled = false;
+                app_struct.ride_record = RideRecord::default();
+                // batteries that were already low never cross the limit during the ride
+                for (device, details) in &app_struct.device_details {
+                    if details.is_low_battery() {
+                        let message = low_battery_message(app_struct, *device, details);
+                        app_struct.ride_record.add_event(0, message);
+                    }
+                }
+                // create receiver to give to new thread
+                let (tx, command_receiver) = std::sync::mpsc::channel();
+                app_struct.workout_command_sender = Some(tx);
+                println!("Workout started!");
+                let time_series = app_struct.workout_time_series.clone().unwrap();
+                let free_ride = match &app_struct.workout_free_ride {
+                    Some(mask) => mask.clone(),
+                    None => vec![false; time_series.time.len()],
+                };
+                let intervals = match &app_struct.workout_intervals {
+                    Some(numbers) => numbers.clone(),
+                    None => vec![0; time_series.time.len()],
+                };
+                let plan = WorkoutPlan {
+                    time_series,
+                    free_ride,
+                    intervals,
+                    user_ftp: app_struct.user_ftp,
+                    erg_mode: app_struct.erg_mode,
+                    simulation,
+                    rider_settings: app_struct.rider_settings,
+                    step: Duration::from_secs(1),
+                };
+                let workout_sender = app_struct.workout_channel.0.clone();
+                let live_speed = app_struct.live_speed.clone();
+                // only drive the trainer if it can take the targets of a checked mode
+                let erg_trainer = match &app_struct.trainer {
+                    Some(trainer)
+                        if (app_struct.erg_mode && trainer.capabilities().target_power)
+                            || (app_struct.sim_mode && trainer.capabilities().simulation) =>
+                    {
+                        Some(trainer.clone())
+                    }
+                    _ => None,
+                };
+                app_struct.workout_warning = erg_trainer
+                    .as_ref()
+                    .and_then(|trainer| trainer.supported_ranges().power)
+                    .and_then(|range| power_range_warning(&plan, range));
+                if let Some(warning) = &app_struct.workout_warning {
+                    println!("{}", warning);
+                    app_struct.ride_record.add_event(0, warning.clone());
+                }
+                thread::spawn(move || {
+                    run_workout(
+                        plan,
+                        erg_trainer,
+                        live_speed,
+                        command_receiver,
+                        workout_sender,
+                    );
+                });
+            } else {
+                println!("Load a workout first.");
+            }
+        }
+
+        let pause_text = if app_struct.workout_paused {
+            "Resume"
+        } else {
+            "Pause"
+        };
+        if ui.button(pause_text).clicked() {
+            if let Some(command_sender) = &app_struct.workout_command_sender {
+                let command = if app_struct.workout_paused {
+                    WorkoutCommand::Resume
+                } else {
+                    WorkoutCommand::Pause
+                };
+                if command_sender.send(command).is_ok() {
+                    app_struct.workout_paused = !app_struct.workout_paused;
+                }
+            }
+        }
+
+        if ui.button("Stop").clicked() {
+            app_struct.stop_workout_flag = true;
+            // TODO: app_struct.workout running = false;
+            if let Some(command_sender) = &app_struct.workout_command_sender {
+                // thread may already have finished, nothing to stop then
+                let _ = command_sender.send(WorkoutCommand::Stop);
+            }
+        }
+    });
+
+    // GUI for running workout
+    // user input
+    ui.horizontal(|ui| {
+        ui.label("FTP:");
+        ui.text_edit_singleline(&mut app_struct.user_ftp_string);
+        ui.checkbox(&mut app_struct.erg_mode, "ERG mode");
+        ui.checkbox(&mut app_struct.sim_mode, "Sim mode for free ride");
+    });
+    if app_struct.sim_mode {
+        draw_simulation_settings(ui, app_struct);
+    }
+    if !app_struct.ride_record.is_empty() {
+        ui.horizontal(|ui| {
+            if ui.button("Save Ride").clicked() {
+                let seconds = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map(|elapsed| elapsed.as_secs())
+                    .unwrap_or(0);
+                let name = format!("ride_{}", seconds);
+                match app_struct
+                    .ride_record
+                    .save(std::path::Path::new("."), &name)
+                {
+                    Ok(()) => println!("Saved {}.csv", name),
+                    Err(e) => println!("Failed to save ride: {}", e),
+                }
+            }
+            if let Some(average) = app_struct.ride_record.average_heart_rate() {
+                ui.label(format!("Average heart rate: {:.0} bpm", average));
+            }
+        });
+    }
+
+    if app_struct.workout_running {
+        // receive message from workout thread
+        match app_struct.workout_channel.1.try_recv() {
+            Ok(message) => {
+                app_struct.display_time = message.time;
+                app_struct.display_cadence = message.target_cadence;
+                app_struct.display_power = message.target_power;
+                app_struct.erg_status = message.erg_status;
+                app_struct.ride_record.add_sample(RideSample {
+                    time: message.time,
+                    target_power: message.target_power * app_struct.user_ftp as f32,
+                    power: app_struct.actual_power,
+                    cadence: app_struct.actual_cadence,
+                    speed: app_struct.actual_speed,
+                    heart_rate: app_struct.actual_heart_rate,
+                    interval: message.interval,
+                    pedal_dynamics: app_struct.actual_pedal_dynamics,
+                });
+            }
+            Err(_) => {}
+        }
+        ui.horizontal(|ui| {
+            ui.label("Time:");
+            ui.label(app_struct.display_time.to_string());
+        });
+        ui.horizontal(|ui| {
+            ui.label("Cadence:");
+            ui.label(app_struct.display_cadence.to_string());
+            ui.label("Actual:");
+            let actual_cadence = app_struct.actual_cadence.round() as i32;
+            // zwo cadence targets are a single value, allow a little slack either side
+            if (actual_cadence - app_struct.display_cadence).abs() <= 5 {
+                ui.label(actual_cadence.to_string());
+            } else {
+                ui.colored_label(egui::Color32::RED, actual_cadence.to_string());
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("Power:");
+            ui.label((app_struct.display_power * app_struct.user_ftp as f32).to_string());
+            ui.label("Actual:");
+            ui.label(format!("{:.0}", app_struct.actual_power));
+        });
+        ui.horizontal(|ui| {
+            ui.label("Speed:");
+            ui.label(format!("{:.1} km/h", app_struct.actual_speed));
+        });
+        ui.horizontal(|ui| {
+            ui.label("Heart rate:");
+            match app_struct.actual_heart_rate {
+                Some(heart_rate) => ui.label(format!("{} bpm", heart_rate)),
+                None => ui.label("-"),
+            };
+        });
+        let pedal = app_struct.actual_pedal_dynamics;
+        if pedal != PedalDynamics::default() {
+            ui.horizontal(|ui| {
+                let percent = |value: Option<f32>| match value {
+                    Some(value) => format!("{:.0} %", value),
+                    None => "-".to_string(),
+                };
+                if let (Some(left), Some(right)) = (pedal.left_balance, pedal.right_balance()) {
+                    ui.label(format!("Balance: L {:.0} / R {:.0}", left, right));
+                }
+                ui.label(format!(
+                    "TE (est.): {}",
+                    percent(pedal.torque_effectiveness)
+                ));
+                ui.label(format!("PS: {}", percent(pedal.pedal_smoothness)));
+            });
+        }
+        if let Some(erg_status) = &app_struct.erg_status {
+            ui.label(erg_status);
+        }
+        if let Some(warning) = &app_struct.workout_warning {
+            ui.colored_label(egui::Color32::RED, warning);
+        }
+    }
+    for (device, since) in &app_struct.reconnecting {
+        ui.colored_label(
+            egui::Color32::RED,
+            format!(
+                "{} dropped out, reconnecting ({} s)",
+                device_name(app_struct, *device),
+                since.elapsed().as_secs()
+            ),
+        );
+    }
+    draw_battery_warnings(ui, app_struct);
+    draw_trainer_events(ui, app_struct);
+}
+
+/// grade profile and rider settings used for FreeRide sections
+fn draw_simulation_settings(ui: &mut Ui, app_struct: &mut BikeApp) {
+    ui.horizontal(|ui| {
+        ui.label("Grade profile (m:%):");
+        ui.text_edit_singleline(&mut app_struct.grade_profile_text);
+    });
+    ui.horizontal(|ui| {
+        let settings = &mut app_struct.rider_settings;
+        ui.label("Wind (m/s):");
+        ui.add(egui::DragValue::new(&mut settings.wind_speed).speed(0.1));
+        ui.label("Crr:");
+        ui.add(
+            egui::DragValue::new(&mut settings.crr)
+                .speed(0.0001)
+                .clamp_range(0.0..=0.0255),
+        );
+        ui.label("Cw (kg/m):");
+        ui.add(
+            egui::DragValue::new(&mut settings.cw)
+                .speed(0.01)
+                .clamp_range(0.0..=2.55),
+        );
+    });
+    ui.horizontal(|ui| {
+        let settings = &mut app_struct.rider_settings;
+        ui.label("Rider weight (kg):");
+        ui.add(
+            egui::DragValue::new(&mut settings.rider_weight)
+                .speed(0.5)
+                .clamp_range(20.0..=250.0),
+        );
+        ui.label("Bike weight (kg):");
+        ui.add(
+            egui::DragValue::new(&mut settings.bike_weight)
+                .speed(0.1)
+                .clamp_range(0.0..=50.0),
+        );
+    });
+}
+
+/// warning for a workout asking for more (or less) power than the trainer can hold
+/// free ride sections are ridden in sim mode and don't count
+fn power_range_warning(plan: &WorkoutPlan, range: SupportedRange) -> Option<String> {
+    if !plan.erg_mode {
+        return None;
+    }
+    let targets: Vec<f32> = plan
+        .time_series
+        .power
+        .iter()
+        .zip(&plan.free_ride)
+        .filter(|(_, free_ride)| !**free_ride)
+        .map(|(power, _)| (power * plan.user_ftp as f32).round())
+        .collect();
+    let highest = targets.iter().copied().fold(f32::MIN, f32::max);
+    let lowest = targets.iter().copied().fold(f32::MAX, f32::min);
+    if highest > range.maximum {
+        Some(format!(
+            "Workout asks for up to {} W, the trainer holds at most {} W",
+            highest, range.maximum
+        ))
+    } else if lowest < range.minimum {
+        Some(format!(
+            "Workout asks for {} W, the trainer holds at least {} W",
+            lowest, range.minimum
+        ))
+    } else {
+        None
+    }
+}
+
+/// steps through the workout once a second, sending targets to the GUI and the trainer
+/// erg_trainer is only given when the trainer takes targets and ERG or sim mode is on
+/// FreeRide sections use sim mode instead of ERG when the plan has a simulation, the other
+/// sections only get power targets with ERG mode on
+fn run_workout(
+    mut plan: WorkoutPlan,
+    erg_trainer: Option<Arc<dyn Trainer>>,
+    live_speed: Arc<Mutex<f32>>,
+    command_receiver: std::sync::mpsc::Receiver<WorkoutCommand>,
+    workout_sender: std::sync::mpsc::Sender<WorkoutMessage>,
+) {
+    let mut erg_status: Option<String> = None;
+    if let Some(trainer) = &erg_trainer {
+        if let Err(e) = trainer.start(&plan.rider_settings) {
+            erg_status = Some(format!("Failed to take control of trainer: {}", e));
+        }
+    }
+
+    let power_range = erg_trainer
+        .as_ref()
+        .and_then(|trainer| trainer.supported_ranges().power);
+    let time_series = plan.time_series;
+    let mut i = 0;
+    let mut paused = false;
+    let mut in_simulation = false;
+    while i < time_series.time.len() {
+        // check to see if stop/pause has been clicked
+        match command_receiver.try_recv() {
+            Ok(WorkoutCommand::Stop) | Err(std::sync::mpsc::TryRecvError::Disconnected) => break,
+            Ok(WorkoutCommand::Pause) => {
+                paused = true;
+                if let Some(trainer) = &erg_trainer {
+                    if let Err(e) = trainer.pause() {
+                        println!("Failed to pause trainer: {}", e);
+                    }
+                }
+            }
+            Ok(command @ (WorkoutCommand::TrainerReconnected | WorkoutCommand::ControlLost)) => {
+                if let Some(trainer) = &erg_trainer {
+                    erg_status = Some(match trainer.start(&plan.rider_settings) {
+                        Ok(_) if command == WorkoutCommand::ControlLost => {
+                            "Control of the trainer taken back.".to_string()
+                        }
+                        Ok(_) => "Trainer reconnected.".to_string(),
+                        Err(e) => format!("Failed to take control of trainer: {}", e),
+                    });
+                }
+                // ERG targets go out every step anyway, the grade only goes out on change
+                if let Some(simulation) = &mut plan.simulation {
+                    simulation.force_update();
+                }
+            }
+            Ok(WorkoutCommand::Resume) => {
+                paused = false;
+                if let Some(trainer) = &erg_trainer {
+                    if let Err(e) = trainer.resume() {
+                        println!("Failed to resume trainer: {}", e);
+                    }
+                }
+            }
+            Err(std::sync::mpsc::TryRecvError::Empty) => {}
+        }
+        if paused {
+            thread::sleep(Duration::from_millis(100));
+            continue;
+        }
+
+        let free_ride = plan.free_ride.get(i).copied().unwrap_or(false);
+        match (&erg_trainer, &mut plan.simulation) {
+            (Some(trainer), Some(simulation)) if free_ride && trainer.capabilities().simulation => {
+                if !in_simulation {
+                    // the trainer was holding a power target, resend even if grade is unchanged
+                    simulation.force_update();
+                    in_simulation = true;
+                }
+                let speed = live_speed.lock().map(|speed| *speed).unwrap_or(0.0);
+                simulation.advance(speed, plan.step);
+                if let Some(parameters) = simulation.next_update() {
+                    erg_status = Some(match trainer.set_simulation(parameters) {
+                        Ok(_) => format!(
+                            "SIM grade: {:.1} % at {:.0} m",
+                            parameters.grade,
+                            simulation.distance()
+                        ),
+                        Err(e) => {
+                            format!("SIM grade {:.1} % not accepted: {}", parameters.grade, e)
+                        }
+                    });
+                }
+            }
+            (Some(trainer), _) if plan.erg_mode && trainer.capabilities().target_power => {
+                in_simulation = false;
+                let requested = (time_series.power[i] * plan.user_ftp as f32).round();
+                // snapped to the trainer's steps, it would reject anything else
+                let target_watts = match power_range {
+                    Some(range) => range.clamp(requested),
+                    None => requested,
+                } as i16;
+                erg_status = Some(match trainer.set_target_power(target_watts) {
+                    Ok(_) if power_range.is_some_and(|range| !range.contains(requested)) => {
+                        format!(
+                            "ERG target: {} W, {} W is outside the trainer's range",
+                            target_watts, requested
+                        )
+                    }
+                    Ok(_) => format!("ERG target: {} W", target_watts),
+                    Err(e) => format!("ERG target {} W not accepted: {}", target_watts, e),
+                });
+            }
+            _ => {}
+        }
+        let message = WorkoutMessage {
+            time: time_series.time[i],
+            target_cadence: time_series.cadence[i],
+            target_power: time_series.power[i],
+            interval: plan.intervals.get(i).copied().unwrap_or(0),
+            erg_status: erg_status.clone(),
+        };
+        if workout_sender.send(message).is_err() {
+            break; // GUI is gone
+        }
+        thread::sleep(plan.step);
+        i += 1;
+    }
+
+    if let Some(trainer) = &erg_trainer {
+        match trainer.stop() {
+            Ok(_) => println!("Released trainer control."),
+            Err(e) => println!("Failed to release trainer control: {}", e),
+        }
+    }
+}
+
+/// draws the bluetooth tab
+fn draw_bluetooth_tab(ui: &mut Ui, app_struct: &mut BikeApp) {
+    ui.horizontal(|ui| {
+        if ui.button("Adapter").clicked() {
+            println!("Searching for adapter...");
+            app_struct.bt_adapters = task::block_on(bt_adapter_scan());
+            println!("Complete.");
+        }
+        if app_struct.adapter_moved {
+            let adapter_info_str: String;
+            match task::block_on(app_struct.selected_adapter.clone().unwrap().adapter_info()) {
+                Ok(info) => adapter_info_str = info,
+                Err(e) => adapter_info_str = e.to_string(),
+            }
+            egui::ComboBox::from_label("Choose an adapter.")
+                .selected_text(&app_struct.adapter_text)
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(
+                        &mut app_struct.selected_adapter_number,
+                        Some(0 as usize),
                         adapter_info_str,
                     );
                 });
@@ -531,32 +2485,23 @@ fn draw_bluetooth_tab(ui: &mut Ui, app_struct: &mut BikeApp) {
         }
     });
     ui.horizontal(|ui| {
-        if ui.button("Scan").clicked() {
-            if app_struct.adapter_moved {
-                println!("Scanning for devices...");
-                let peripheral_sender = app_struct.peripheral_channel.0.clone();
-                let selected_adapter = app_struct.selected_adapter.clone().unwrap();
-                // lmao, this doesn't seem ideal
-                thread::spawn(move || {
-                    let rt = Runtime::new().unwrap();
-                    let peripheral_list = rt.block_on(async move {
-                        let peripheral_list = task::block_on(bt_scan(&selected_adapter));
-                        return peripheral_list;
-                    });
-                    // TODO: error handling instead of unwrap
-                    peripheral_sender.send(peripheral_list).unwrap();
-                });
-            }
-        }
-        // don't want receiver in the button click function, timelines get weird
-        // even here it's kind of a problem, it only receives when on the bluetooth tab
-        match app_struct.peripheral_channel.1.try_recv() {
-            Ok(message) => {
-                app_struct.peripheral_list = message;
-                println!("Complete.");
+        let scanning = app_struct
+            .discovery
+            .as_ref()
+            .is_some_and(|discovery| discovery.is_running());
+        if scanning {
+            if ui.button("Stop Scan").clicked() {
+                app_struct.discovery = None;
+                app_struct.auto_connect = AutoConnect::Off;
+                println!("Stopped scanning.");
             }
-            Err(_) => {}
+        } else if ui.button("Scan").clicked() && app_struct.adapter_moved {
+            println!("Scanning for devices...");
+            let peripheral_sender = app_struct.peripheral_channel.0.clone();
+            let selected_adapter = app_struct.selected_adapter.clone().unwrap();
+            app_struct.discovery = Some(start_discovery(selected_adapter, peripheral_sender));
         }
+        ui.checkbox(&mut app_struct.fitness_only, "Fitness devices only");
         if app_struct.peripheral_moved {
             let mut name_str = app_struct
                 .selected_peripheral
@@ -591,19 +2536,13 @@ fn draw_bluetooth_tab(ui: &mut Ui, app_struct: &mut BikeApp) {
                 .show_ui(ui, |ui| match &app_struct.peripheral_list {
                     Some(peripherals) => {
                         for (i, peripheral) in peripherals.iter().enumerate() {
-                            let mut name_str = peripheral.id().to_string();
-                            let properties_result = task::block_on(peripheral.properties());
-                            match properties_result {
-                                Ok(prop_option) => {
-                                    if prop_option.is_some() {
-                                        if prop_option.clone().unwrap().local_name.is_some() {
-                                            name_str =
-                                                prop_option.clone().unwrap().local_name.unwrap();
-                                        }
-                                    }
-                                }
-                                Err(_) => {}
-                            }
+                            let Some(name_str) = discovered_label(
+                                &app_struct.discovered,
+                                app_struct.fitness_only,
+                                peripheral,
+                            ) else {
+                                continue;
+                            };
                             ui.selectable_value(
                                 &mut app_struct.selected_peripheral_number,
                                 Some(i),
@@ -622,16 +2561,7 @@ fn draw_bluetooth_tab(ui: &mut Ui, app_struct: &mut BikeApp) {
         }
         if ui.button("Connect").clicked() {
             if app_struct.peripheral_moved {
-                println!("Connecting to device...");
-                match task::block_on(app_struct.selected_peripheral.clone().unwrap().connect()) {
-                    Ok(()) => {
-                        println!("Device connected.");
-                        app_struct.peripheral_connected = true;
-                    }
-                    Err(e) => {
-                        println!("Failed to connect.  {:?}", e);
-                    }
-                }
+                connect_trainer(app_struct);
             } else {
                 println!("Please scan for devices and select one to connect");
             }
@@ -640,12 +2570,695 @@ fn draw_bluetooth_tab(ui: &mut Ui, app_struct: &mut BikeApp) {
             if app_struct.selected_peripheral.is_some() {
                 println!("Disconnecting from device...");
                 let peripheral = app_struct.selected_peripheral.clone().unwrap();
+                // otherwise the supervisor would connect it straight back
+                app_struct.watch_list.unwatch(TRAINER_DEVICE);
                 let disconnect_result = task::block_on(peripheral.disconnect());
                 match disconnect_result {
                     Ok(()) => println!("Successfully disconnected."),
                     Err(e) => println!("Failed to disconnect: {:?}", e),
                 }
+                forget_trainer(app_struct);
+            }
+        }
+    });
+    match &app_struct.trainer {
+        Some(trainer) => {
+            let capabilities = trainer.capabilities();
+            ui.label(format!(
+                "Trainer: {} (ERG: {}, resistance: {}, sim: {})",
+                trainer.kind(),
+                capabilities.target_power,
+                capabilities.resistance,
+                capabilities.simulation
+            ));
+        }
+        None if app_struct.peripheral_connected => {
+            ui.label("Device is not a trainer or power meter.");
+        }
+        None => {}
+    }
+    if let (true, Some(peripheral)) = (
+        app_struct.peripheral_connected,
+        &app_struct.selected_peripheral,
+    ) {
+        ui.label(format!("Services: {}", service_summary(peripheral)));
+    }
+    ui.separator();
+    draw_sensors(ui, app_struct);
+    ui.separator();
+    draw_device_details(ui, app_struct);
+    ui.separator();
+    draw_metric_sources(ui, app_struct);
+    ui.separator();
+    draw_known_devices(ui, app_struct);
+    ui.separator();
+    draw_virtual_trainer(ui, app_struct);
+}
+
+/// sensors connected next to the trainer, they share the scanned peripheral list
+fn draw_sensors(ui: &mut Ui, app_struct: &mut BikeApp) {
+    ui.horizontal(|ui| {
+        ui.label("Sensor:");
+        egui::ComboBox::from_id_source("sensor")
+            .selected_text(
+                match (
+                    &app_struct.peripheral_list,
+                    app_struct.sensor_peripheral_number,
+                ) {
+                    (Some(peripherals), Some(i)) if i < peripherals.len() => {
+                        task::block_on(update_peripheral_text(&peripherals[i]))
+                    }
+                    _ => "None selected".to_string(),
+                },
+            )
+            .show_ui(ui, |ui| {
+                if let Some(peripherals) = &app_struct.peripheral_list {
+                    for (i, peripheral) in peripherals.iter().enumerate() {
+                        let Some(name_str) = discovered_label(
+                            &app_struct.discovered,
+                            app_struct.fitness_only,
+                            peripheral,
+                        ) else {
+                            continue;
+                        };
+                        ui.selectable_value(
+                            &mut app_struct.sensor_peripheral_number,
+                            Some(i),
+                            name_str,
+                        );
+                    }
+                }
+            });
+        if ui.button("Connect Sensor").clicked() {
+            let peripheral = match (
+                &app_struct.peripheral_list,
+                app_struct.sensor_peripheral_number,
+            ) {
+                (Some(peripherals), Some(i)) if i < peripherals.len() => {
+                    Some(peripherals[i].clone())
+                }
+                _ => None,
+            };
+            match peripheral {
+                Some(peripheral) => connect_sensor_device(app_struct, peripheral),
+                None => println!("Please scan for devices and select a sensor"),
+            }
+        }
+    });
+    let mut disconnected = None;
+    for device in &app_struct.sensors {
+        ui.horizontal(|ui| {
+            ui.label(format!(
+                "{} {}",
+                device.classification.class.icon(),
+                device.name
+            ));
+            ui.label(device.classification.to_string());
+            if let Some(location) = device.csc_sensor_location {
+                ui.label(format!("({:?})", location));
+            }
+            if let Some(location) = device.hr_sensor_location {
+                ui.label(format!("({:?})", location));
+            }
+            if ui.button("Disconnect").clicked() {
+                disconnected = Some(device.id);
+            }
+        });
+        ui.label(format!("Services: {}", service_summary(&device.peripheral)));
+        if let Some(measurement) = &device.heart_rate_measurement {
+            ui.horizontal(|ui| {
+                ui.label("Heart rate:");
+                ui.label(format!("{} bpm", measurement.heart_rate));
+                if measurement.sensor_contact == Some(false) {
+                    ui.colored_label(egui::Color32::RED, "no skin contact");
+                }
+                if let Some(energy) = measurement.energy_expended {
+                    ui.label(format!("{} kJ", energy));
+                }
+                if let Some(rr_interval) = measurement.rr_intervals.last() {
+                    ui.label(format!("RR {:.0} ms", rr_interval * 1000.0));
+                }
+            });
+        }
+        if !device.pedal_stroke.is_empty() {
+            egui::CollapsingHeader::new("Pedal stroke")
+                .id_source(format!("pedal_stroke_{}", device.id))
+                .show(ui, |ui| draw_pedal_stroke(ui, &device.pedal_stroke));
+        }
+        if device
+            .csc_feature
+            .is_some_and(|feature| feature.wheel_revolution_data_supported())
+        {
+            ui.horizontal(|ui| {
+                ui.label("Wheel revolutions:");
+                ui.text_edit_singleline(&mut app_struct.wheel_revolutions_text);
+                if ui.button("Set").clicked() {
+                    match app_struct.wheel_revolutions_text.parse::<u32>() {
+                        Ok(value) => {
+                            match task::block_on(set_cumulative_wheel_revolutions(
+                                &device.peripheral,
+                                value,
+                            )) {
+                                Ok(()) => println!("Wheel revolutions set to {}", value),
+                                Err(e) => println!("Failed to set wheel revolutions: {}", e),
+                            }
+                        }
+                        Err(_) => println!("Enter a whole number of revolutions"),
+                    }
+                }
+            });
+        }
+        if find_characteristic(&device.peripheral, CPS_CONTROL_POINT).is_some() {
+            let connected = !is_reconnecting(&app_struct.reconnecting, device.id);
+            let state = app_struct.cps_controls.entry(device.id).or_default();
+            draw_cps_control_point(ui, state, &device.peripheral, device.id);
+            draw_zero_offset_calibration(
+                ui,
+                state,
+                &mut app_struct.calibration_log,
+                &app_struct.metric_sources,
+                CpsMeter {
+                    id: device.id,
+                    name: &device.name,
+                    peripheral: &device.peripheral,
+                    connected,
+                },
+            );
+        }
+    }
+    if let Some(id) = disconnected {
+        if let Some(index) = app_struct.sensors.iter().position(|d| d.id == id) {
+            let device = app_struct.sensors.remove(index);
+            app_struct.watch_list.unwatch(id);
+            match task::block_on(device.peripheral.disconnect()) {
+                Ok(()) => println!("Disconnected {}.", device.name),
+                Err(e) => println!("Failed to disconnect: {:?}", e),
+            }
+            app_struct.metric_sources.remove_device(id);
+            app_struct.device_details.remove(&id);
+            app_struct.cps_controls.remove(&id);
+        }
+    }
+}
+
+/// which device every live value is taken from, with a fallback for when it drops out
+fn draw_metric_sources(ui: &mut Ui, app_struct: &mut BikeApp) {
+    let mut devices: Vec<(DeviceId, String)> = Vec::new();
+    if let Some(trainer) = &app_struct.trainer {
+        devices.push((TRAINER_DEVICE, format!("Trainer ({})", trainer.kind())));
+    }
+    for device in &app_struct.sensors {
+        devices.push((device.id, device.name.clone()));
+    }
+    let device_name = |id: Option<DeviceId>| match id {
+        Some(id) => devices
+            .iter()
+            .find(|(device, _)| *device == id)
+            .map_or("Disconnected".to_string(), |(_, name)| name.clone()),
+        None => "Automatic".to_string(),
+    };
+    let now = Instant::now();
+    egui::Grid::new("metric_sources").show(ui, |ui| {
+        ui.label("");
+        ui.label("Primary");
+        ui.label("Fallback");
+        ui.label("Reading from");
+        ui.end_row();
+        for metric in Metric::ALL {
+            let current = app_struct.metric_sources.current(metric, now);
+            let assignment = app_struct.metric_sources.assignment_mut(metric);
+            ui.label(metric.to_string());
+            for (slot, selected) in [
+                ("primary", &mut assignment.primary),
+                ("fallback", &mut assignment.fallback),
+            ] {
+                egui::ComboBox::from_id_source(format!("{}_{}", metric, slot))
+                    .selected_text(device_name(*selected))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(selected, None, "Automatic");
+                        for (id, name) in &devices {
+                            ui.selectable_value(selected, Some(*id), name);
+                        }
+                    });
+            }
+            ui.label(device_name(current.map(|(id, _)| id)));
+            ui.end_row();
+        }
+    });
+}
+
+fn draw_battery_warnings(ui: &mut Ui, app_struct: &BikeApp) {
+    let mut devices: Vec<(&DeviceId, &DeviceDetails)> = app_struct.device_details.iter().collect();
+    devices.sort_by_key(|(device, _)| **device);
+    for (device, details) in devices {
+        if details.is_low_battery() {
+            ui.colored_label(
+                egui::Color32::RED,
+                low_battery_message(app_struct, *device, details),
+            );
+        }
+    }
+}
+
+/// manufacturer, firmware and battery of every connected device
+fn draw_device_details(ui: &mut Ui, app_struct: &BikeApp) {
+    let mut devices: Vec<(&DeviceId, &DeviceDetails)> = app_struct.device_details.iter().collect();
+    devices.sort_by_key(|(device, _)| **device);
+    if devices.is_empty() {
+        return;
+    }
+    ui.label("Device details:");
+    for (device, details) in devices {
+        egui::CollapsingHeader::new(device_name(app_struct, *device))
+            .id_source(format!("details_{}", device))
+            .show(ui, |ui| {
+                egui::Grid::new(format!("details_grid_{}", device)).show(ui, |ui| {
+                    for (label, value) in details.information.fields() {
+                        ui.label(label);
+                        ui.label(value.as_deref().unwrap_or("-"));
+                        ui.end_row();
+                    }
+                    ui.label("Battery");
+                    match details.battery_level {
+                        Some(level) if details.is_low_battery() => {
+                            ui.colored_label(egui::Color32::RED, format!("{}%", level));
+                        }
+                        Some(level) => {
+                            ui.label(format!("{}%", level));
+                        }
+                        None => {
+                            ui.label("-");
+                        }
+                    }
+                    ui.end_row();
+                });
+            });
+    }
+}
+
+/// devices connected in earlier sessions, these are connected automatically on launch
+fn draw_known_devices(ui: &mut Ui, app_struct: &mut BikeApp) {
+    ui.label("Remembered devices:");
+    if app_struct.known_devices.is_empty() {
+        ui.label("None yet, connected devices are remembered.");
+        return;
+    }
+    let mut forgotten = None;
+    for known in &app_struct.known_devices.devices {
+        ui.horizontal(|ui| {
+            ui.label(format!(
+                "{} ({}, {})",
+                known.name, known.role, known.adapter
+            ));
+            if ui.button("Forget").clicked() {
+                forgotten = Some(known.id.clone());
+            }
+        });
+    }
+    if let Some(id) = forgotten {
+        app_struct.known_devices.forget(&id);
+        match app_struct.known_devices.save(Path::new(KNOWN_DEVICES_FILE)) {
+            Ok(()) => println!("Forgot device {}.", id),
+            Err(e) => println!("Failed to save known devices: {}", e),
+        }
+    }
+}
+
+/// rider model and fault injection for the software trainer, for use without an adapter
+fn draw_virtual_trainer(ui: &mut Ui, app_struct: &mut BikeApp) {
+    let settings = &mut app_struct.virtual_trainer_settings;
+    ui.horizontal(|ui| {
+        ui.label("Virtual rider power (W):");
+        ui.add(
+            egui::DragValue::new(&mut settings.rider.cruise_power)
+                .speed(1.0)
+                .clamp_range(0.0..=2000.0),
+        );
+        ui.label("Cadence (rpm):");
+        ui.add(
+            egui::DragValue::new(&mut settings.rider.cadence)
+                .speed(1.0)
+                .clamp_range(0.0..=200.0),
+        );
+    });
+    ui.horizontal(|ui| {
+        ui.label("Noise (W):");
+        ui.add(
+            egui::DragValue::new(&mut settings.noise)
+                .speed(1.0)
+                .clamp_range(0.0..=200.0),
+        );
+        ui.label("Dropout chance:");
+        ui.add(
+            egui::DragValue::new(&mut settings.dropout_chance)
+                .speed(0.01)
+                .clamp_range(0.0..=1.0),
+        );
+    });
+    if ui.button("Use Virtual Trainer").clicked() {
+        println!("Using virtual trainer.");
+        // the real trainer stays connected but nothing of it should reach the workout
+        app_struct.watch_list.unwatch(TRAINER_DEVICE);
+        forget_trainer(app_struct);
+        let trainer = Arc::new(VirtualTrainer::new(app_struct.virtual_trainer_settings));
+        app_struct.trainer = Some(trainer.clone());
+        app_struct.virtual_trainer = Some(trainer);
+    }
+    if let Some(trainer) = &app_struct.virtual_trainer {
+        ui.label(format!("Virtual trainer mode: {:?}", trainer.mode()));
+    }
+}
+
+/// the supervisor is still trying to get the device back
+fn is_reconnecting(reconnecting: &[(DeviceId, Instant)], device: DeviceId) -> bool {
+    reconnecting.iter().any(|(id, _)| *id == device)
+}
+
+/// drops the trainer and everything subscribed from or drawn for it
+fn forget_trainer(app_struct: &mut BikeApp) {
+    app_struct.trainer = None;
+    app_struct.trainer_controlled = false;
+    app_struct.virtual_trainer = None;
+    app_struct.trainer_data_receiver = None;
+    app_struct.trainer_status_receiver = None;
+    app_struct.device_details.remove(&TRAINER_DEVICE);
+    app_struct.cps_controls.remove(&TRAINER_DEVICE);
+    app_struct.spin_down = SpinDownState::default();
+}
+
+/// peripheral behind a device id, the trainer only counts while connected
+fn device_peripheral(app_struct: &BikeApp, device: DeviceId) -> Option<Peripheral> {
+    match device {
+        TRAINER_DEVICE if app_struct.peripheral_connected => app_struct.selected_peripheral.clone(),
+        TRAINER_DEVICE => None,
+        _ => app_struct
+            .sensors
+            .iter()
+            .find(|sensor| sensor.id == device)
+            .map(|sensor| sensor.peripheral.clone()),
+    }
+}
+
+/// every service, characteristic and descriptor of a connected device, with raw access
+fn draw_gatt_tab(ui: &mut Ui, app_struct: &mut BikeApp) {
+    let mut devices: Vec<DeviceId> = Vec::new();
+    if app_struct.peripheral_connected && app_struct.selected_peripheral.is_some() {
+        devices.push(TRAINER_DEVICE);
+    }
+    devices.extend(app_struct.sensors.iter().map(|sensor| sensor.id));
+    ui.horizontal(|ui| {
+        ui.label("Device:");
+        egui::ComboBox::from_id_source("gatt_device")
+            .selected_text(match app_struct.gatt_device {
+                Some(device) => device_name(app_struct, device),
+                None => "None selected".to_string(),
+            })
+            .show_ui(ui, |ui| {
+                for device in &devices {
+                    let name = device_name(app_struct, *device);
+                    ui.selectable_value(&mut app_struct.gatt_device, Some(*device), name);
+                }
+            });
+    });
+    let Some(device) = app_struct.gatt_device else {
+        ui.label("Connect a trainer or sensor on the Bluetooth tab.");
+        return;
+    };
+    let Some(peripheral) = device_peripheral(app_struct, device) else {
+        ui.label("Device is not connected.");
+        return;
+    };
+    egui::ScrollArea::vertical()
+        .id_source("gatt_services")
+        .max_height(ui.available_height() * 0.6)
+        .show(ui, |ui| {
+            for service in peripheral.services() {
+                let primary = match service.primary {
+                    true => "primary",
+                    false => "secondary",
+                };
+                egui::CollapsingHeader::new(format!(
+                    "{} ({})",
+                    attribute_name(service.uuid),
+                    primary
+                ))
+                .id_source(service.uuid)
+                .show(ui, |ui| {
+                    for characteristic in &service.characteristics {
+                        draw_gatt_characteristic(
+                            ui,
+                            app_struct,
+                            device,
+                            &peripheral,
+                            characteristic,
+                        );
+                    }
+                });
+            }
+        });
+    ui.separator();
+    ui.horizontal(|ui| {
+        ui.label("Notifications");
+        if ui.button("Clear").clicked() {
+            app_struct.gatt_log.clear();
+        }
+    });
+    egui::ScrollArea::vertical()
+        .id_source("gatt_log")
+        .stick_to_bottom(true)
+        .show(ui, |ui| {
+            for line in app_struct.gatt_log.lines() {
+                ui.monospace(line);
+            }
+        });
+}
+
+/// one characteristic with read/write/notify controls and its descriptors
+fn draw_gatt_characteristic(
+    ui: &mut Ui,
+    app_struct: &mut BikeApp,
+    device: DeviceId,
+    peripheral: &Peripheral,
+    characteristic: &btleplug::api::Characteristic,
+) {
+    let uuid = characteristic.uuid;
+    let properties = characteristic.properties;
+    let data_type = match lookup_attribute(uuid) {
+        Some(attribute) => format!("  {:?}", attribute.data_type),
+        None => String::new(),
+    };
+    ui.label(format!(
+        "{}  [{}]{}",
+        attribute_name(uuid),
+        property_labels(properties),
+        data_type
+    ));
+    ui.horizontal(|ui| {
+        if properties.contains(CharPropFlags::READ) && ui.button("Read").clicked() {
+            let value = match task::block_on(peripheral.read(characteristic)) {
+                Ok(buf) => format_value(uuid, &buf),
+                Err(e) => format!("Failed to read: {:?}", e),
+            };
+            app_struct.gatt_values.insert((device, uuid, None), value);
+        }
+        let write_type = if properties.contains(CharPropFlags::WRITE) {
+            Some(WriteType::WithResponse)
+        } else if properties.contains(CharPropFlags::WRITE_WITHOUT_RESPONSE) {
+            Some(WriteType::WithoutResponse)
+        } else {
+            None
+        };
+        if let Some(write_type) = write_type {
+            let text = app_struct
+                .gatt_write_text
+                .entry((device, uuid))
+                .or_default();
+            ui.add(egui::TextEdit::singleline(text).hint_text("hex, e.g. 05 2A 00"));
+            if ui.button("Write").clicked() {
+                let value = match parse_hex(text) {
+                    Ok(buf) => {
+                        match task::block_on(peripheral.write(characteristic, &buf, write_type)) {
+                            Ok(()) => format!("Wrote {}", to_hex(&buf)),
+                            Err(e) => format!("Failed to write: {:?}", e),
+                        }
+                    }
+                    Err(e) => e,
+                };
+                app_struct.gatt_values.insert((device, uuid, None), value);
+            }
+        }
+        if properties.intersects(CharPropFlags::NOTIFY | CharPropFlags::INDICATE) {
+            let watching = app_struct
+                .gatt_watched
+                .lock()
+                .map(|watched| watched.contains(&(device, uuid)))
+                .unwrap_or(false);
+            if watching {
+                // only stops logging, the app itself may still need the subscription
+                if ui.button("Stop Logging").clicked() {
+                    if let Ok(mut watched) = app_struct.gatt_watched.lock() {
+                        watched.remove(&(device, uuid));
+                    }
+                }
+            } else if ui.button("Subscribe").clicked() {
+                match task::block_on(peripheral.subscribe(characteristic)) {
+                    Ok(()) => {
+                        if app_struct.gatt_loggers.insert(device) {
+                            spawn_notification_logger(
+                                peripheral.clone(),
+                                device,
+                                app_struct.gatt_watched.clone(),
+                                app_struct.gatt_notification_channel.0.clone(),
+                            );
+                        }
+                        if let Ok(mut watched) = app_struct.gatt_watched.lock() {
+                            watched.insert((device, uuid));
+                        }
+                    }
+                    Err(e) => {
+                        let value = format!("Failed to subscribe: {:?}", e);
+                        app_struct.gatt_values.insert((device, uuid, None), value);
+                    }
+                }
             }
         }
+        if let Some(value) = app_struct.gatt_values.get(&(device, uuid, None)) {
+            ui.monospace(value);
+        }
     });
+    for descriptor in &characteristic.descriptors {
+        ui.horizontal(|ui| {
+            ui.add_space(20.0);
+            ui.label(attribute_name(descriptor.uuid));
+            if ui.button("Read").clicked() {
+                let value = match task::block_on(peripheral.read_descriptor(descriptor)) {
+                    Ok(buf) => format_value(descriptor.uuid, &buf),
+                    Err(e) => format!("Failed to read: {:?}", e),
+                };
+                app_struct
+                    .gatt_values
+                    .insert((device, uuid, Some(descriptor.uuid)), value);
+            }
+            if let Some(value) = app_struct
+                .gatt_values
+                .get(&(device, uuid, Some(descriptor.uuid)))
+            {
+                ui.monospace(value);
+            }
+        });
+    }
+    ui.add_space(4.0);
+}
+
+#[test]
+fn workout_runs_on_virtual_trainer() {
+    use crate::bluetooth::virtual_trainer::VirtualMode;
+
+    let trainer = Arc::new(VirtualTrainer::new(VirtualTrainerSettings::default()));
+    let plan = WorkoutPlan {
+        time_series: WorkoutTimeSeries {
+            time: vec![0, 1, 2, 3],
+            cadence: vec![90; 4],
+            power: vec![0.5, 0.75, 1.0, 1.0],
+        },
+        // last second is free ride, ridden in sim mode
+        free_ride: vec![false, false, false, true],
+        intervals: vec![0, 0, 1, 2],
+        user_ftp: 200,
+        erg_mode: true,
+        simulation: Some(SimulationController::new(
+            GradeProfile::parse("0:3").unwrap(),
+            RiderSettings::default(),
+        )),
+        rider_settings: RiderSettings::default(),
+        step: Duration::from_millis(1),
+    };
+    let (_command_sender, command_receiver) = std::sync::mpsc::channel();
+    let (workout_sender, workout_receiver) = std::sync::mpsc::channel();
+    run_workout(
+        plan,
+        Some(trainer.clone()),
+        Arc::new(Mutex::new(0.0)),
+        command_receiver,
+        workout_sender,
+    );
+    let statuses: Vec<Option<String>> = workout_receiver
+        .try_iter()
+        .map(|message| message.erg_status)
+        .collect();
+    assert_eq!(statuses.len(), 4);
+    assert_eq!(statuses[0].as_deref(), Some("ERG target: 100 W"));
+    assert_eq!(statuses[2].as_deref(), Some("ERG target: 200 W"));
+    assert_eq!(statuses[3].as_deref(), Some("SIM grade: 3.0 % at 0 m"));
+    // control is handed back at the end
+    assert_eq!(trainer.mode(), VirtualMode::FreeRide);
+}
+
+#[test]
+fn warns_when_workout_exceeds_trainer_power_range() {
+    let plan = |power: Vec<f32>, free_ride: Vec<bool>| WorkoutPlan {
+        time_series: WorkoutTimeSeries {
+            time: (0..power.len()).collect(),
+            cadence: vec![90; power.len()],
+            power,
+        },
+        intervals: vec![0; free_ride.len()],
+        free_ride,
+        user_ftp: 300,
+        erg_mode: true,
+        simulation: None,
+        rider_settings: RiderSettings::default(),
+        step: Duration::from_millis(1),
+    };
+    let range = SupportedRange {
+        minimum: 50.0,
+        maximum: 400.0,
+        increment: 1.0,
+    };
+    assert_eq!(
+        power_range_warning(&plan(vec![0.5, 1.5], vec![false; 2]), range).as_deref(),
+        Some("Workout asks for up to 450 W, the trainer holds at most 400 W")
+    );
+    // the sprint is a free ride section
+    assert_eq!(
+        power_range_warning(&plan(vec![0.5, 1.5], vec![false, true]), range),
+        None
+    );
+}
+
+#[test]
+fn sim_only_workout_sends_no_power_targets() {
+    let trainer = Arc::new(VirtualTrainer::new(VirtualTrainerSettings::default()));
+    let plan = WorkoutPlan {
+        time_series: WorkoutTimeSeries {
+            time: vec![0, 1],
+            cadence: vec![90; 2],
+            power: vec![0.5, 0.5],
+        },
+        free_ride: vec![false, true],
+        intervals: vec![0, 1],
+        user_ftp: 200,
+        erg_mode: false,
+        simulation: Some(SimulationController::new(
+            GradeProfile::parse("0:3").unwrap(),
+            RiderSettings::default(),
+        )),
+        rider_settings: RiderSettings::default(),
+        step: Duration::from_millis(1),
+    };
+    let (_command_sender, command_receiver) = std::sync::mpsc::channel();
+    let (workout_sender, workout_receiver) = std::sync::mpsc::channel();
+    run_workout(
+        plan,
+        Some(trainer),
+        Arc::new(Mutex::new(0.0)),
+        command_receiver,
+        workout_sender,
+    );
+    let statuses: Vec<Option<String>> = workout_receiver
+        .try_iter()
+        .map(|message| message.erg_status)
+        .collect();
+    assert_eq!(statuses[0], None);
+    assert_eq!(statuses[1].as_deref(), Some("SIM grade: 3.0 % at 0 m"));
 }
@@ -1,18 +1,212 @@
 /*=======================================================================
  * IMPORTS
  * ====================================================================*/
-use btleplug::api::{Central, Manager as Manager_api, ScanFilter};
+use async_std::stream::StreamExt;
+use async_std::task;
+use ble_default_services::attribute_name;
+use btleplug::api::{
+    Characteristic, Manager as Manager_api, Peripheral as Peripheral_api, WriteType,
+};
 use btleplug::platform::{Adapter, Manager, Peripheral};
+use std::fmt;
+use std::thread;
 use std::time::Duration;
-use tokio::time::{self};
+use uuid::Uuid;
 
 pub mod ble_default_services;
+pub mod classify;
 pub mod cps;
 pub mod cscs;
+pub mod device_info;
+pub mod discovery;
+pub mod fec;
+pub mod ftms;
+pub mod gatt;
+pub mod hrs;
+pub mod session;
+pub mod supervisor;
+pub mod trainer;
+pub mod virtual_trainer;
+pub mod wahoo;
 
 /*=======================================================================
  * CONSTANTS
  * ====================================================================*/
+/// how long to wait for a control point indication before giving up
+pub const CONTROL_POINT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/*=======================================================================
+ * ENUMS
+ * ====================================================================*/
+/// result code shared by the CSC, CPS and FTMS control point response indications
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlPointResult {
+    Success,
+    OpCodeNotSupported,
+    InvalidParameter,
+    OperationFailed,
+    ControlNotPermitted, // FTMS only
+    Unknown(u8),
+}
+
+impl ControlPointResult {
+    pub fn from_u8(value: u8) -> Self {
+        match value {
+            0x01 => ControlPointResult::Success,
+            0x02 => ControlPointResult::OpCodeNotSupported,
+            0x03 => ControlPointResult::InvalidParameter,
+            0x04 => ControlPointResult::OperationFailed,
+            0x05 => ControlPointResult::ControlNotPermitted,
+            other => ControlPointResult::Unknown(other),
+        }
+    }
+}
+
+/// error from reading a characteristic or running a control point procedure
+#[derive(Debug)]
+pub enum BleRequestError {
+    MissingCharacteristic(Uuid),
+    Ble(btleplug::Error),
+    Parse(BleParseError),
+    Timeout,
+    NoResponse,
+    Rejected(ControlPointResult),
+    NotSupported(&'static str),
+}
+
+impl fmt::Display for BleRequestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BleRequestError::MissingCharacteristic(uuid) => {
+                write!(f, "device has no {}", attribute_name(*uuid))
+            }
+            BleRequestError::Ble(e) => write!(f, "bluetooth error: {}", e),
+            BleRequestError::Parse(e) => write!(f, "{}", e),
+            BleRequestError::Timeout => write!(f, "timed out waiting for a response"),
+            BleRequestError::NoResponse => write!(f, "notification stream ended"),
+            BleRequestError::Rejected(result) => write!(f, "request rejected: {:?}", result),
+            BleRequestError::NotSupported(what) => write!(f, "device does not support {}", what),
+        }
+    }
+}
+
+impl std::error::Error for BleRequestError {}
+
+impl From<btleplug::Error> for BleRequestError {
+    fn from(e: btleplug::Error) -> Self {
+        BleRequestError::Ble(e)
+    }
+}
+
+impl From<BleParseError> for BleRequestError {
+    fn from(e: BleParseError) -> Self {
+        BleRequestError::Parse(e)
+    }
+}
+
+/*=======================================================================
+ * STRUCTS
+ * ====================================================================*/
+/// error returned when a characteristic value can't be decoded
+#[derive(Debug, Clone, PartialEq)]
+pub enum BleParseError {
+    /// packet ended before a field the flags said would be there
+    Truncated {
+        characteristic: &'static str,
+        field: &'static str,
+        needed: usize,
+        available: usize,
+    },
+    /// packet is long enough but contains a value we can't make sense of
+    Invalid {
+        characteristic: &'static str,
+        reason: String,
+    },
+}
+
+impl fmt::Display for BleParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BleParseError::Truncated {
+                characteristic,
+                field,
+                needed,
+                available,
+            } => write!(
+                f,
+                "{} packet truncated at '{}': needed {} more byte(s), {} left",
+                characteristic, field, needed, available
+            ),
+            BleParseError::Invalid {
+                characteristic,
+                reason,
+            } => write!(f, "{} packet invalid: {}", characteristic, reason),
+        }
+    }
+}
+
+impl std::error::Error for BleParseError {}
+
+/// little endian cursor over a characteristic value
+/// every read names the field so truncation errors say what was missing
+pub struct ByteReader<'a> {
+    characteristic: &'static str,
+    buffer: &'a [u8],
+    position: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    pub fn new(characteristic: &'static str, buffer: &'a [u8]) -> Self {
+        Self {
+            characteristic,
+            buffer,
+            position: 0,
+        }
+    }
+
+    /// number of bytes that haven't been read yet
+    pub fn remaining(&self) -> usize {
+        self.buffer.len() - self.position
+    }
+
+    fn take(&mut self, field: &'static str, count: usize) -> Result<&'a [u8], BleParseError> {
+        if self.remaining() < count {
+            return Err(BleParseError::Truncated {
+                characteristic: self.characteristic,
+                field,
+                needed: count,
+                available: self.remaining(),
+            });
+        }
+        let bytes = &self.buffer[self.position..self.position + count];
+        self.position += count;
+        Ok(bytes)
+    }
+
+    pub fn read_u8(&mut self, field: &'static str) -> Result<u8, BleParseError> {
+        Ok(self.take(field, 1)?[0])
+    }
+
+    pub fn read_u16(&mut self, field: &'static str) -> Result<u16, BleParseError> {
+        let bytes = self.take(field, 2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn read_i16(&mut self, field: &'static str) -> Result<i16, BleParseError> {
+        let bytes = self.take(field, 2)?;
+        Ok(i16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn read_u24(&mut self, field: &'static str) -> Result<u32, BleParseError> {
+        let bytes = self.take(field, 3)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0]))
+    }
+
+    pub fn read_u32(&mut self, field: &'static str) -> Result<u32, BleParseError> {
+        let bytes = self.take(field, 4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
 
 /*=======================================================================
  * FUNCTIONS
@@ -41,21 +235,89 @@ pub async fn bt_adapter_scan() -> Option<Vec<Adapter>> {
     }
 }
 
-pub async fn bt_scan(adapter: &Adapter) -> Option<Vec<Peripheral>> {
-    println!("Scanning for peripherals...");
-    let scan_results = adapter.start_scan(ScanFilter::default()).await;
-    time::sleep(Duration::from_secs(10)).await;
-    println!("Here are the results:");
+/// looks up a characteristic on an already discovered peripheral
+pub fn find_characteristic(peripheral: &Peripheral, uuid: Uuid) -> Option<Characteristic> {
+    peripheral
+        .characteristics()
+        .into_iter()
+        .find(|c| c.uuid == uuid)
+}
 
-    match scan_results {
-        Ok(()) => {
-            let peripherals = adapter.peripherals().await.unwrap();
-            println!("{:?}", peripherals);
-            return Some(peripherals);
-        }
-        Err(e) => {
-            println!("Error scanning for peripherals: {:?}", e);
-            return None;
+/// reads a characteristic value by uuid
+pub async fn read_characteristic(
+    peripheral: &Peripheral,
+    uuid: Uuid,
+) -> Result<Vec<u8>, BleRequestError> {
+    let characteristic = find_characteristic(peripheral, uuid)
+        .ok_or(BleRequestError::MissingCharacteristic(uuid))?;
+    Ok(peripheral.read(&characteristic).await?)
+}
+
+/// writes a control point request and waits for the indication that answers it
+/// the answer starts with response_op_code followed by the op code of the request
+pub async fn control_point_request(
+    peripheral: &Peripheral,
+    uuid: Uuid,
+    request: &[u8],
+    response_op_code: u8,
+    timeout: Duration,
+) -> Result<Vec<u8>, BleRequestError> {
+    let control_point = find_characteristic(peripheral, uuid)
+        .ok_or(BleRequestError::MissingCharacteristic(uuid))?;
+    // indications have to be enabled before the write or the response is lost
+    peripheral.subscribe(&control_point).await?;
+    let mut notifications = peripheral.notifications().await?;
+    peripheral
+        .write(&control_point, request, WriteType::WithResponse)
+        .await?;
+    let response = async {
+        while let Some(data) = notifications.next().await {
+            if data.uuid == uuid
+                && data.value.len() >= 2
+                && data.value[0] == response_op_code
+                && data.value[1] == request[0]
+            {
+                return Ok(data.value);
+            }
         }
+        Err(BleRequestError::NoResponse)
+    };
+    match async_std::future::timeout(timeout, response).await {
+        Ok(result) => result,
+        Err(_) => Err(BleRequestError::Timeout),
     }
 }
+
+/// decodes a device's notifications on a separate thread and hands them to send
+/// parse gives None for characteristics this listener doesn't want
+/// runs until the connection is gone or send returns false because nobody is listening
+pub fn spawn_notification_thread<T>(
+    peripheral: Peripheral,
+    parse: impl Fn(Uuid, &[u8]) -> Option<Result<T, BleParseError>> + Send + 'static,
+    mut send: impl FnMut(T) -> bool + Send + 'static,
+) {
+    thread::spawn(move || {
+        task::block_on(async move {
+            let mut notifications = match peripheral.notifications().await {
+                Ok(notifications) => notifications,
+                Err(e) => {
+                    println!("Failed to get notifications: {:?}", e);
+                    return;
+                }
+            };
+            while let Some(data) = notifications.next().await {
+                let listening = match parse(data.uuid, &data.value) {
+                    Some(Ok(decoded)) => send(decoded),
+                    Some(Err(e)) => {
+                        println!("{}", e);
+                        true
+                    }
+                    None => true,
+                };
+                if !listening {
+                    break;
+                }
+            }
+        });
+    });
+}
@@ -1,9 +1,12 @@
+use crate::bluetooth::discovery::FEC_SERVICE;
+use crate::bluetooth::fec::{FEC_READ, FEC_WRITE};
+use crate::bluetooth::wahoo::WAHOO_TRAINER_CONTROL;
 use std::collections::HashMap;
+use std::fmt;
 use uuid::Uuid;
 
-// copied this straight from https://github.com/dmtrKovalenko/blendr
-// most of these probably aren't relevant, but it does have bike smart trainer
-// and heart rate sensor data, can clean up later if necessary
+// service and characteristic names started out copied from https://github.com/dmtrKovalenko/blendr,
+// since checked against the Bluetooth assigned numbers
 
 /// Standard services are always 16bit uuid, while we actually receiving 128bit uuids from ble devices
 pub(crate) const fn create_ble_uuid(uuid: u16) -> Uuid {
@@ -13,193 +16,500 @@ pub(crate) const fn create_ble_uuid(uuid: u16) -> Uuid {
     Uuid::from_u128(converted_uuid)
 }
 
+/// 16 bit form of a uuid on the Bluetooth base uuid, None for vendor uuids
+pub fn short_uuid(uuid: Uuid) -> Option<u16> {
+    let value = uuid.as_u128();
+    let short = (value >> 96) as u16;
+    match value == create_ble_uuid(short).as_u128() {
+        true => Some(short),
+        false => None,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttributeKind {
+    Service,
+    Characteristic,
+    Descriptor,
+}
+
+/// how a value is laid out, Struct means flags or op codes decide the fields
+/// and the matching service module has the parser
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GattDataType {
+    Utf8String,
+    Uint8,
+    Sint8,
+    Uint16,
+    Bitfield,
+    Struct,
+    ControlPoint,
+    /// services, and vendor characteristics we only know the name of
+    None,
+}
+
+impl GattDataType {
+    /// decoded value for the simple types, None if it needs a real parser
+    pub fn format_value(&self, buf: &[u8]) -> Option<String> {
+        match (self, buf) {
+            (GattDataType::Utf8String, _) => Some(
+                String::from_utf8_lossy(buf)
+                    .trim_end_matches('\0')
+                    .to_string(),
+            ),
+            (GattDataType::Uint8, [value]) => Some(value.to_string()),
+            (GattDataType::Sint8, [value]) => Some((*value as i8).to_string()),
+            (GattDataType::Uint16, [a, b]) => Some(u16::from_le_bytes([*a, *b]).to_string()),
+            _ => None,
+        }
+    }
+}
+
+/// registry entry for a known service, characteristic or descriptor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GattAttribute {
+    pub name: &'static str,
+    pub short_uuid: Option<u16>,
+    pub kind: AttributeKind,
+    pub data_type: GattDataType,
+}
+
+impl fmt::Display for GattAttribute {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.short_uuid {
+            Some(short) => write!(f, "{} (0x{:04X})", self.name, short),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+const SERVICES: &[(u16, &str)] = &[
+    (0x1800, "Generic Access"),
+    (0x1801, "Generic Attribute"),
+    (0x1802, "Immediate Alert"),
+    (0x1803, "Link Loss"),
+    (0x1804, "Tx Power"),
+    (0x1805, "Current Time Service"),
+    (0x1806, "Reference Time Update Service"),
+    (0x1807, "Next DST Change Service"),
+    (0x1808, "Glucose"),
+    (0x1809, "Health Thermometer"),
+    (0x180A, "Device Information"),
+    (0x180D, "Heart Rate"),
+    (0x180E, "Phone Alert Status Service"),
+    (0x180F, "Battery"),
+    (0x1810, "Blood Pressure"),
+    (0x1811, "Alert Notification Service"),
+    (0x1812, "Human Interface Device"),
+    (0x1813, "Scan Parameters"),
+    (0x1814, "Running Speed and Cadence"),
+    (0x1815, "Automation IO"),
+    (0x1816, "Cycling Speed and Cadence"),
+    (0x1818, "Cycling Power"),
+    (0x1819, "Location and Navigation"),
+    (0x181A, "Environmental Sensing"),
+    (0x181B, "Body Composition"),
+    (0x181C, "User Data"),
+    (0x181D, "Weight Scale"),
+    (0x181E, "Bond Management"),
+    (0x181F, "Continuous Glucose Monitoring"),
+    (0x1820, "Internet Protocol Support Service"),
+    (0x1821, "Indoor Positioning"),
+    (0x1822, "Pulse Oximeter"),
+    (0x1823, "HTTP Proxy"),
+    (0x1824, "Transport Discovery"),
+    (0x1825, "Object Transfer"),
+    (0x1826, "Fitness Machine"),
+];
+
+const CHARACTERISTICS: &[(u16, &str, GattDataType)] = &[
+    // Generic Access (0x1800)
+    (0x2A00, "Device Name", GattDataType::Utf8String),
+    (0x2A01, "Appearance", GattDataType::Uint16),
+    (0x2A02, "Peripheral Privacy Flag", GattDataType::Uint8),
+    (0x2A03, "Reconnection Address", GattDataType::Struct),
+    (
+        0x2A04,
+        "Peripheral Preferred Connection Parameters",
+        GattDataType::Struct,
+    ),
+    (0x2AA6, "Central Address Resolution", GattDataType::Uint8),
+    // Generic Attribute (0x1801)
+    (0x2A05, "Service Changed", GattDataType::Struct),
+    // Immediate Alert/Link Loss/Tx Power
+    (0x2A06, "Alert Level", GattDataType::Uint8),
+    (0x2A07, "Tx Power Level", GattDataType::Sint8),
+    // Battery (0x180F)
+    (0x2A19, "Battery Level", GattDataType::Uint8),
+    // Device Information (0x180A)
+    (0x2A23, "System ID", GattDataType::Struct),
+    (0x2A24, "Model Number String", GattDataType::Utf8String),
+    (0x2A25, "Serial Number String", GattDataType::Utf8String),
+    (0x2A26, "Firmware Revision String", GattDataType::Utf8String),
+    (0x2A27, "Hardware Revision String", GattDataType::Utf8String),
+    (0x2A28, "Software Revision String", GattDataType::Utf8String),
+    (0x2A29, "Manufacturer Name String", GattDataType::Utf8String),
+    (
+        0x2A2A,
+        "IEEE 11073-20601 Regulatory Certification Data List",
+        GattDataType::Struct,
+    ),
+    (0x2A50, "PnP ID", GattDataType::Struct),
+    // Heart Rate (0x180D)
+    (0x2A37, "Heart Rate Measurement", GattDataType::Struct),
+    (0x2A38, "Body Sensor Location", GattDataType::Uint8),
+    (
+        0x2A39,
+        "Heart Rate Control Point",
+        GattDataType::ControlPoint,
+    ),
+    // Running Speed and Cadence (0x1814)
+    (0x2A53, "RSC Measurement", GattDataType::Struct),
+    (0x2A54, "RSC Feature", GattDataType::Bitfield),
+    // Cycling Speed and Cadence (0x1816), Sensor Location and SC Control Point shared with RSC
+    (0x2A55, "SC Control Point", GattDataType::ControlPoint),
+    (0x2A5B, "CSC Measurement", GattDataType::Struct),
+    (0x2A5C, "CSC Feature", GattDataType::Bitfield),
+    (0x2A5D, "Sensor Location", GattDataType::Uint8),
+    // Cycling Power (0x1818)
+    (0x2A63, "Cycling Power Measurement", GattDataType::Struct),
+    (0x2A64, "Cycling Power Vector", GattDataType::Struct),
+    (0x2A65, "Cycling Power Feature", GattDataType::Bitfield),
+    (
+        0x2A66,
+        "Cycling Power Control Point",
+        GattDataType::ControlPoint,
+    ),
+    // Fitness Machine (0x1826)
+    (0x2ACC, "Fitness Machine Feature", GattDataType::Bitfield),
+    (0x2ACD, "Treadmill Data", GattDataType::Struct),
+    (0x2ACE, "Cross Trainer Data", GattDataType::Struct),
+    (0x2ACF, "Step Climber Data", GattDataType::Struct),
+    (0x2AD0, "Stair Climber Data", GattDataType::Struct),
+    (0x2AD1, "Rower Data", GattDataType::Struct),
+    (0x2AD2, "Indoor Bike Data", GattDataType::Struct),
+    (0x2AD3, "Training Status", GattDataType::Struct),
+    (0x2AD4, "Supported Speed Range", GattDataType::Struct),
+    (0x2AD5, "Supported Inclination Range", GattDataType::Struct),
+    (
+        0x2AD6,
+        "Supported Resistance Level Range",
+        GattDataType::Struct,
+    ),
+    (0x2AD7, "Supported Heart Rate Range", GattDataType::Struct),
+    (0x2AD8, "Supported Power Range", GattDataType::Struct),
+    (
+        0x2AD9,
+        "Fitness Machine Control Point",
+        GattDataType::ControlPoint,
+    ),
+    (0x2ADA, "Fitness Machine Status", GattDataType::Struct),
+    // User Data (0x181C)
+    (0x2A98, "Weight", GattDataType::Uint16),
+    (0x2A8E, "Height", GattDataType::Uint16),
+    (0x2A80, "Age", GattDataType::Uint8),
+    (0x2A8D, "Heart Rate Max", GattDataType::Uint8),
+    (0x2A9F, "User Control Point", GattDataType::ControlPoint),
+    (
+        0x2A7E,
+        "Aerobic Heart Rate Lower Limit",
+        GattDataType::Uint8,
+    ),
+    (0x2A7F, "Aerobic Threshold", GattDataType::Uint8),
+    (
+        0x2A81,
+        "Anaerobic Heart Rate Lower Limit",
+        GattDataType::Uint8,
+    ),
+    (
+        0x2A82,
+        "Anaerobic Heart Rate Upper Limit",
+        GattDataType::Uint8,
+    ),
+    (0x2A83, "Anaerobic Threshold", GattDataType::Uint8),
+    (
+        0x2A84,
+        "Aerobic Heart Rate Upper Limit",
+        GattDataType::Uint8,
+    ),
+    (
+        0x2A88,
+        "Fat Burn Heart Rate Lower Limit",
+        GattDataType::Uint8,
+    ),
+    (
+        0x2A89,
+        "Fat Burn Heart Rate Upper Limit",
+        GattDataType::Uint8,
+    ),
+    (0x2A8B, "Five Zone Heart Rate Limits", GattDataType::Struct),
+    (
+        0x2A91,
+        "Maximum Recommended Heart Rate",
+        GattDataType::Uint8,
+    ),
+    (0x2A92, "Resting Heart Rate", GattDataType::Uint8),
+    (
+        0x2A93,
+        "Sport Type for Aerobic and Anaerobic Thresholds",
+        GattDataType::Uint8,
+    ),
+    (0x2A94, "Three Zone Heart Rate Limits", GattDataType::Struct),
+    (0x2A95, "Two Zone Heart Rate Limit", GattDataType::Uint8),
+    (0x2A99, "Database Change Increment", GattDataType::Struct),
+    (0x2A9A, "User Index", GattDataType::Uint8),
+    (0x2AA2, "Language", GattDataType::Utf8String),
+    // Current Time (0x1805)
+    (0x2A2B, "Current Time", GattDataType::Struct),
+    (0x2A0B, "Exact Time 100", GattDataType::Struct),
+    (0x2A0C, "Exact Time 256", GattDataType::Struct),
+    (0x2A0D, "DST Offset", GattDataType::Uint8),
+    (0x2A0F, "Local Time Information", GattDataType::Struct),
+    (0x2A14, "Reference Time Information", GattDataType::Struct),
+    // Reference Time Update (0x1806)
+    (
+        0x2A16,
+        "Time Update Control Point",
+        GattDataType::ControlPoint,
+    ),
+    (0x2A17, "Time Update State", GattDataType::Struct),
+    // Next DST Change (0x1807)
+    (0x2A11, "Time with DST", GattDataType::Struct),
+    // Glucose (0x1808), Record Access Control Point shared with CGM
+    (0x2A18, "Glucose Measurement", GattDataType::Struct),
+    (0x2A34, "Glucose Measurement Context", GattDataType::Struct),
+    (0x2A51, "Glucose Feature", GattDataType::Bitfield),
+    (
+        0x2A52,
+        "Record Access Control Point",
+        GattDataType::ControlPoint,
+    ),
+    // Health Thermometer (0x1809)
+    (0x2A1C, "Temperature Measurement", GattDataType::Struct),
+    (0x2A1D, "Temperature Type", GattDataType::Uint8),
+    // Phone Alert Status (0x180E) and Alert Notification (0x1811)
+    (0x2A3F, "Alert Status", GattDataType::Bitfield),
+    (0x2A40, "Ringer Control Point", GattDataType::ControlPoint),
+    (0x2A41, "Ringer Setting", GattDataType::Uint8),
+    (0x2A42, "Alert Category ID Bit Mask", GattDataType::Bitfield),
+    (0x2A43, "Alert Category ID", GattDataType::Uint8),
+    (
+        0x2A44,
+        "Alert Notification Control Point",
+        GattDataType::ControlPoint,
+    ),
+    (0x2A45, "Unread Alert Status", GattDataType::Struct),
+    (0x2A46, "New Alert", GattDataType::Struct),
+    (
+        0x2A47,
+        "Supported New Alert Category",
+        GattDataType::Bitfield,
+    ),
+    (
+        0x2A48,
+        "Supported Unread Alert Category",
+        GattDataType::Bitfield,
+    ),
+    // Blood Pressure (0x1810)
+    (0x2A49, "Blood Pressure Feature", GattDataType::Bitfield),
+    // Environmental Sensing (0x181A)
+    (0x2A2C, "Magnetic Declination", GattDataType::Uint16),
+    (0x2A6D, "Pressure", GattDataType::Struct),
+    (0x2A6E, "Temperature", GattDataType::Struct),
+    (0x2A6F, "Humidity", GattDataType::Uint16),
+    (0x2A70, "True Wind Speed", GattDataType::Uint16),
+    (0x2A76, "UV Index", GattDataType::Uint8),
+    (0x2A77, "Irradiance", GattDataType::Uint16),
+    (0x2A78, "Rainfall", GattDataType::Uint16),
+    (0x2A79, "Wind Chill", GattDataType::Sint8),
+    (0x2A7A, "Heat Index", GattDataType::Sint8),
+    (0x2AA0, "Magnetic Flux Density - 2D", GattDataType::Struct),
+    (0x2AA1, "Magnetic Flux Density - 3D", GattDataType::Struct),
+    (0x2AA3, "Barometric Pressure Trend", GattDataType::Uint8),
+    // Body Composition (0x181B)
+    (0x2A9B, "Body Composition Feature", GattDataType::Bitfield),
+    (0x2A9C, "Body Composition Measurement", GattDataType::Struct),
+    // Weight Scale (0x181D)
+    (0x2A9D, "Weight Measurement", GattDataType::Struct),
+    (0x2A9E, "Weight Scale Feature", GattDataType::Bitfield),
+    // Bond Management (0x181E)
+    (
+        0x2AA4,
+        "Bond Management Control Point",
+        GattDataType::ControlPoint,
+    ),
+    (0x2AA5, "Bond Management Feature", GattDataType::Bitfield),
+    // Continuous Glucose Monitoring (0x181F)
+    (0x2AA7, "CGM Measurement", GattDataType::Struct),
+    (0x2AA8, "CGM Feature", GattDataType::Bitfield),
+    (0x2AA9, "CGM Status", GattDataType::Struct),
+    (0x2AAA, "CGM Session Start Time", GattDataType::Struct),
+    (0x2AAB, "CGM Session Run Time", GattDataType::Uint16),
+    (
+        0x2AAC,
+        "CGM Specific Ops Control Point",
+        GattDataType::ControlPoint,
+    ),
+    // Indoor Positioning (0x1821)
+    (
+        0x2AAD,
+        "Indoor Positioning Configuration",
+        GattDataType::Bitfield,
+    ),
+    (0x2AAE, "Latitude", GattDataType::Struct),
+    (0x2AAF, "Longitude", GattDataType::Struct),
+    (0x2AB0, "Local North Coordinate", GattDataType::Struct),
+    (0x2AB1, "Local East Coordinate", GattDataType::Struct),
+    (0x2AB2, "Floor Number", GattDataType::Uint8),
+    (0x2AB3, "Altitude", GattDataType::Uint16),
+    (0x2AB4, "Uncertainty", GattDataType::Uint8),
+];
+
+const DESCRIPTORS: &[(u16, &str, GattDataType)] = &[
+    (
+        0x2900,
+        "Characteristic Extended Properties",
+        GattDataType::Bitfield,
+    ),
+    (
+        0x2901,
+        "Characteristic User Description",
+        GattDataType::Utf8String,
+    ),
+    (
+        0x2902,
+        "Client Characteristic Configuration",
+        GattDataType::Bitfield,
+    ),
+    (
+        0x2903,
+        "Server Characteristic Configuration",
+        GattDataType::Bitfield,
+    ),
+    (
+        0x2904,
+        "Characteristic Presentation Format",
+        GattDataType::Struct,
+    ),
+    (
+        0x2905,
+        "Characteristic Aggregate Format",
+        GattDataType::Struct,
+    ),
+    (0x2906, "Valid Range", GattDataType::Struct),
+    (0x2907, "External Report Reference", GattDataType::Struct),
+    (0x2908, "Report Reference", GattDataType::Struct),
+    (
+        0x290B,
+        "Environmental Sensing Configuration",
+        GattDataType::Struct,
+    ),
+    (
+        0x290C,
+        "Environmental Sensing Measurement",
+        GattDataType::Struct,
+    ),
+    (
+        0x290D,
+        "Environmental Sensing Trigger Setting",
+        GattDataType::Struct,
+    ),
+];
+
+/// trainers that put their own protocol in a vendor service
+const VENDOR_CHARACTERISTICS: &[(Uuid, &str)] = &[
+    (FEC_READ, "Tacx FE-C Read"),
+    (FEC_WRITE, "Tacx FE-C Write"),
+    (WAHOO_TRAINER_CONTROL, "Wahoo Trainer Control"),
+];
+
+const VENDOR_SERVICES: &[(Uuid, &str)] = &[(FEC_SERVICE, "Tacx FE-C over BLE")];
+
 lazy_static::lazy_static! {
-    pub static ref SPECIAL_SERVICES_NAMES: HashMap<uuid::Uuid, &'static str> = HashMap::from([
-        (create_ble_uuid(0x1800), "Generic Access (0x1800)"),
-        (create_ble_uuid(0x1801), "Generic Attribute (0x1800)"),
-        (create_ble_uuid(0x1802), "Immediate Alert (0x1800)"),
-        (create_ble_uuid(0x1803), "Link Loss (0x1800)"),
-        (create_ble_uuid(0x1804), "Tx Power (0x1800)"),
-        (create_ble_uuid(0x1805), "Current Time Service (0x1800)"),
-        (create_ble_uuid(0x1806), "Reference Time Update Service (0x1800)"),
-        (create_ble_uuid(0x1807), "Next DST Change Service (0x1800)"),
-        (create_ble_uuid(0x1808), "Glucose (0x1800)"),
-        (create_ble_uuid(0x1809), "Health Thermometer (0x1800)"),
-        (create_ble_uuid(0x180A), "Device Information (0x1800)"),
-        (create_ble_uuid(0x180D), "Heart Rate (0x1800)"),
-        (create_ble_uuid(0x180E), "Phone Alert Status Service (0x1800)"),
-        (create_ble_uuid(0x180F), "Battery (0x1800)"),
-        (create_ble_uuid(0x1810), "Blood Pressure (0x1800)"),
-        (create_ble_uuid(0x1811), "Alert Notification Service (0x1800)"),
-        (create_ble_uuid(0x1812), "Human Interface Device (0x1800)"),
-        (create_ble_uuid(0x1813), "Scan Parameters (0x1800)"),
-        (create_ble_uuid(0x1814), "Running Speed and Cadence (0x1800)"),
-        (create_ble_uuid(0x1815), "Cycling Speed and Cadence (0x1800)"),
-        (create_ble_uuid(0x1816), "Cycling Power (0x1800)"),
-        (create_ble_uuid(0x1818), "Cycling Power Vector (0x1800)"),
-        (create_ble_uuid(0x1819), "Location and Navigation (0x1800)"),
-        (create_ble_uuid(0x181A), "Environmental Sensing (0x1800)"),
-        (create_ble_uuid(0x181B), "Body Composition (0x1800)"),
-        (create_ble_uuid(0x181C), "User Data (0x1800)"),
-        (create_ble_uuid(0x181D), "Weight Scale (0x1800)"),
-        (create_ble_uuid(0x181E), "Bond Management (0x1800)"),
-        (create_ble_uuid(0x181F), "Continuous Glucose Monitoring (0x1800)"),
-        (create_ble_uuid(0x1820), "Internet Protocol Support Service (0x1800)"),
-        (create_ble_uuid(0x1821), "Indoor Positioning (0x1800)"),
-        (create_ble_uuid(0x1822), "Pulse Oximeter (0x1800)"),
-        (create_ble_uuid(0x1823), "HTTP Proxy (0x1800)"),
-        (create_ble_uuid(0x1824), "Transport Discovery (0x1800)"),
-        (create_ble_uuid(0x1825), "Object Transfer (0x1800)"),
-    ]);
-
-    pub static ref SPECIAL_CHARACTERISTICS_NAMES: HashMap<uuid::Uuid, &'static str> = HashMap::from([
-      // Generic Access Service (0x1800)
-      (create_ble_uuid(0x2A00), "Device Name (0x2A00)"),
-      (create_ble_uuid(0x2A01), "Appearance (0x2A01)"),
-      (create_ble_uuid(0x2A02), "Peripheral Privacy Flag (0x2A02)"),
-      (create_ble_uuid(0x2A03), "Reconnection Address (0x2A03)"),
-      (create_ble_uuid(0x2A04), "Peripheral Preferred Connection Parameters (0x2A04)"),
-
-      // Generic Attribute Service (0x1801)
-      (create_ble_uuid(0x2A05), "Service Changed (0x2A05)"),
-
-      // Immediate Alert Service (0x1802)
-      (create_ble_uuid(0x2A06), "Alert Level (0x2A06)"),
-
-      // Link Loss Service (0x1803)
-      (create_ble_uuid(0x2A06), "Alert Level (0x2A06)"),
-
-      // TX Power Service (0x1804)
-      (create_ble_uuid(0x2A07), "Tx Power Level (0x2A07)"),
-      // Current Time Service (0x1805)
-      (create_ble_uuid(0x2A0B), "Current Time (0x2A0B)"),
-      (create_ble_uuid(0x2A0C), "Local Time Information (0x2A0C)"),
-      (create_ble_uuid(0x2A0D), "Reference Time Information (0x2A0D)"),
-
-      // Battery Service (0x180F)
-      (create_ble_uuid(0x2A19), "Battery Level (0x2A19)"),
-
-      // Reference Time Update Service (0x1806)
-      (create_ble_uuid(0x2A16), "Time Update Control Point (0x2A16)"),
-      (create_ble_uuid(0x2A17), "Time Update State (0x2A17)"),
-
-      // Next DST Change Service (0x1807)
-      (create_ble_uuid(0x2A1D), "Time with DST (0x2A1D)"),
-
-      // Glucose Service (0x1808)
-      (create_ble_uuid(0x2A18), "Glucose Measurement (0x2A18)"),
-      (create_ble_uuid(0x2A34), "Glucose Feature (0x2A34)"),
-      (create_ble_uuid(0x2A51), "Glucose Measurement Context (0x2A51)"),
-
-      // Health Thermometer Service (0x1809)
-      (create_ble_uuid(0x2A1C), "Temperature Measurement (0x2A1C)"),
-      (create_ble_uuid(0x2A1D), "Temperature Type (0x2A1D)"),
-
-      // Device Information Service (0x180A)
-      (create_ble_uuid(0x2A29), "Manufacturer Name String (0x2A29)"),
-      (create_ble_uuid(0x2A24), "Model Number String (0x2A24)"),
-      (create_ble_uuid(0x2A25), "Serial Number String (0x2A25)"),
-      (create_ble_uuid(0x2A26), "Firmware Revision String (0x2A26)"),
-      (create_ble_uuid(0x2A27), "Hardware Revision String (0x2A27)"),
-      (create_ble_uuid(0x2A28), "Software Revision String (0x2A28)"),
-      (create_ble_uuid(0x2A23), "System ID (0x2A23)"),
-      (create_ble_uuid(0x2A2A), "IEEE 11073-20601 Regulatory Certification Data List (0x2A2A)"),
-      (create_ble_uuid(0x2A50), "PnP ID (0x2A50)"),
-
-      // Heart Rate Service (0x180D)
-      (create_ble_uuid(0x2A37), "Heart Rate Measurement (0x2A37)"),
-      (create_ble_uuid(0x2A38), "Body Sensor Location (0x2A38)"),
-      (create_ble_uuid(0x2A39), "Heart Rate Control Point (0x2A39)"),
-
-      // Phone Alert Status Service (0x180E)
-      (create_ble_uuid(0x2A3F), "Alert Status (0x2A3F)"),
-      (create_ble_uuid(0x2A40), "Ringer Control Point (0x2A40)"),
-      (create_ble_uuid(0x2A41), "Ringer Setting (0x2A41)"),
-      (create_ble_uuid(0x2A42), "Alert Category ID Bit Mask (0x2A42)"),
-      (create_ble_uuid(0x2A43), "Alert Category ID (0x2A43)"),
-      (create_ble_uuid(0x2A44), "Alert Notification Control Point (0x2A44)"),
-      (create_ble_uuid(0x2A45), "Unread Alert Status (0x2A45)"),
-      (create_ble_uuid(0x2A46), "New Alert (0x2A46)"),
-      (create_ble_uuid(0x2A47), "Supported New Alert Category (0x2A47)"),
-      (create_ble_uuid(0x2A48), "Supported Unread Alert Category (0x2A48)"),
-      (create_ble_uuid(0x2A49), "Blood Pressure Feature (0x2A49)"),
-      (create_ble_uuid(0x2A50), "PnP ID (0x2A50)"),
-
-      // Running Speed and Cadence Service (0x1814)
-      (create_ble_uuid(0x2A53), "RSC Measurement (0x2A53)"),
-      (create_ble_uuid(0x2A54), "RSC Feature (0x2A54)"),
-      (create_ble_uuid(0x2A55), "Sensor Location (0x2A55)"),
-      (create_ble_uuid(0x2A5D), "SC Control Point (0x2A5D)"),
-
-      // Cycling Speed and Cadence Service (0x1816)
-      (create_ble_uuid(0x2A5B), "CSC Measurement (0x2A5B)"),
-      (create_ble_uuid(0x2A5C), "CSC Feature (0x2A5C)"),
-
-      // Environmental Sensing Service (0x181A)
-      (create_ble_uuid(0x2A6E), "Temperature (0x2A6E)"),
-      (create_ble_uuid(0x2A6F), "Humidity (0x2A6F)"),
-      (create_ble_uuid(0x2A76), "Irradiance (0x2A76)"),
-      (create_ble_uuid(0x2A77), "Rainfall (0x2A77)"),
-      (create_ble_uuid(0x2A78), "Wind Speed (0x2A78)"),
-      (create_ble_uuid(0x2A79), "Barometric Pressure Trend (0x2A79)"),
-      (create_ble_uuid(0x2A7A), "Magnetic Declination (0x2A7A)"),
-      (create_ble_uuid(0x2A6D), "Pressure (0x2A6D)"),
-
-      // Body Composition Service (0x181B)
-      (create_ble_uuid(0x2A9C), "Body Composition Measurement (0x2A9C)"),
-      (create_ble_uuid(0x2A9D), "Body Composition Feature (0x2A9D)"),
-      (create_ble_uuid(0x2A9E), "Body Composition Control Point (0x2A9E)"),
-
-      // User Data Service (0x181C)
-      (create_ble_uuid(0x2A9F), "User Control Point (0x2A9F)"),
-      (create_ble_uuid(0x2AA0), "User Status (0x2AA0)"),
-      (create_ble_uuid(0x2AA1), "Heart Rate Max (0x2AA1)"),
-      (create_ble_uuid(0x2AA2), "Resting Heart Rate (0x2AA2)"),
-      (create_ble_uuid(0x2AA3), "Maximum Recommended Heart Rate (0x2AA3)"),
-      (create_ble_uuid(0x2AA4), "Aerobic Threshold (0x2AA4)"),
-      (create_ble_uuid(0x2AA5), "Anaerobic Threshold (0x2AA5)"),
-      (create_ble_uuid(0x2AA6), "Sport Type for Aerobic and Anaerobic Thresholds (0x2AA6)"),
-      (create_ble_uuid(0x2AA7), "Fat Burn Heart Rate Lower Limit (0x2AA7)"),
-      (create_ble_uuid(0x2AA8), "Fat Burn Heart Rate Upper Limit (0x2AA8)"),
-      (create_ble_uuid(0x2AA9), "Aerobic Heart Rate Lower Limit (0x2AA9)"),
-      (create_ble_uuid(0x2AAA), "Aerobic Heart Rate Upper Limit (0x2AAA)"),
-      (create_ble_uuid(0x2AAB), "Anaerobic Heart Rate Lower Limit (0x2AAB)"),
-      (create_ble_uuid(0x2AAC), "Anaerobic Heart Rate Upper Limit (0x2AAC)"),
-      (create_ble_uuid(0x2AAD), "Five Zone Heart Rate Limits (0x2AAD)"),
-      (create_ble_uuid(0x2AAE), "Three Zone Heart Rate Limits (0x2AAE)"),
-      (create_ble_uuid(0x2AAF), "Two Zone Heart Rate Limit (0x2AAF)"),
-
-      // Weight Scale Service (0x181D)
-      (create_ble_uuid(0x2A9B), "Weight Measurement (0x2A9B)"),
-      (create_ble_uuid(0x2A9D), "Body Composition Feature (0x2A9D)"),
-      (create_ble_uuid(0x2A9E), "Body Composition Control Point (0x2A9E)"),
-
-      // Bond Management Service (0x181E)
-      (create_ble_uuid(0x2AA1), "Bond Management Control Point (0x2AA1)"),
-      (create_ble_uuid(0x2AA2), "Bond Management Feature (0x2AA2)"),
-
-      // Continuous Glucose Monitoring Service (0x181F)
-      (create_ble_uuid(0x2AA7), "CGM Measurement (0x2AA7)"),
-      (create_ble_uuid(0x2AA8), "CGM Feature (0x2AA8)"),
-      (create_ble_uuid(0x2AA9), "CGM Status (0x2AA9)"),
-      (create_ble_uuid(0x2AAA), "CGM Session Start Time (0x2AAA)"),
-      (create_ble_uuid(0x2AAB), "CGM Session Run Time (0x2AAB)"),
-      (create_ble_uuid(0x2AAC), "CGM Specific Ops Control Point (0x2AAC)"),
-      (create_ble_uuid(0x2AAD), "Indoor Positioning Configuration (0x2AAD)"),
-      (create_ble_uuid(0x2AAE), "Latitude (0x2AAE)"),
-      (create_ble_uuid(0x2AAF), "Longitude (0x2AAF)"),
-      (create_ble_uuid(0x2AB0), "Local North Coordinate (0x2AB0)"),
-      (create_ble_uuid(0x2AB1), "Local East Coordinate (0x2AB1)"),
-      (create_ble_uuid(0x2AB2), "Floor Number (0x2AB2)"),
-      (create_ble_uuid(0x2AB3), "Altitude (0x2AB3)"),
-    (create_ble_uuid(0x2AB4), "Uncertainty (0x2AB4)"),
-  ]);
+    static ref GATT_REGISTRY: HashMap<Uuid, GattAttribute> = {
+        let mut registry = HashMap::new();
+        for (short, name) in SERVICES {
+            registry.insert(create_ble_uuid(*short), GattAttribute {
+                name,
+                short_uuid: Some(*short),
+                kind: AttributeKind::Service,
+                data_type: GattDataType::None,
+            });
+        }
+        for (kind, table) in [
+            (AttributeKind::Characteristic, CHARACTERISTICS),
+            (AttributeKind::Descriptor, DESCRIPTORS),
+        ] {
+            for (short, name, data_type) in table {
+                registry.insert(create_ble_uuid(*short), GattAttribute {
+                    name,
+                    short_uuid: Some(*short),
+                    kind,
+                    data_type: *data_type,
+                });
+            }
+        }
+        for (kind, table) in [
+            (AttributeKind::Service, VENDOR_SERVICES),
+            (AttributeKind::Characteristic, VENDOR_CHARACTERISTICS),
+        ] {
+            for (uuid, name) in table {
+                registry.insert(*uuid, GattAttribute {
+                    name,
+                    short_uuid: None,
+                    kind,
+                    data_type: GattDataType::None,
+                });
+            }
+        }
+        registry
+    };
+}
+
+/// name, 16 bit form and data type of a known service, characteristic or descriptor
+pub fn lookup_attribute(uuid: Uuid) -> Option<GattAttribute> {
+    GATT_REGISTRY.get(&uuid).copied()
+}
+
+/// "Cycling Power Measurement (0x2A63)", or the 16 bit/full uuid if it isn't in the registry
+pub fn attribute_name(uuid: Uuid) -> String {
+    match (lookup_attribute(uuid), short_uuid(uuid)) {
+        (Some(attribute), _) => attribute.to_string(),
+        (None, Some(short)) => format!("0x{:04X}", short),
+        (None, None) => uuid.to_string(),
+    }
 }
 
 #[test]
 pub(crate) fn resolves_special_services() {
     assert_eq!(
-        SPECIAL_SERVICES_NAMES.get(&Uuid::from_u128(0x0000180a_0000_1000_8000_00805f9b34fb)),
-        Some(&"Device Information (0x1800)")
+        attribute_name(Uuid::from_u128(0x0000180a_0000_1000_8000_00805f9b34fb)),
+        "Device Information (0x180A)"
+    );
+    let measurement = lookup_attribute(create_ble_uuid(0x2A63)).unwrap();
+    assert_eq!(measurement.name, "Cycling Power Measurement");
+    assert_eq!(measurement.short_uuid, Some(0x2A63));
+    assert_eq!(measurement.kind, AttributeKind::Characteristic);
+    let cccd = lookup_attribute(create_ble_uuid(0x2902)).unwrap();
+    assert_eq!(cccd.kind, AttributeKind::Descriptor);
+    assert_eq!(attribute_name(FEC_READ), "Tacx FE-C Read");
+    assert_eq!(short_uuid(FEC_READ), None);
+    assert_eq!(attribute_name(create_ble_uuid(0xFFF1)), "0xFFF1");
+    assert_eq!(
+        attribute_name(create_ble_uuid(0x2A18)),
+        "Glucose Measurement (0x2A18)"
+    );
+    // one name per uuid, a second entry would silently replace the first
+    let mut shorts: Vec<u16> = CHARACTERISTICS.iter().map(|(short, _, _)| *short).collect();
+    shorts.sort_unstable();
+    shorts.dedup();
+    assert_eq!(shorts.len(), CHARACTERISTICS.len());
+    assert_eq!(
+        GattDataType::Utf8String.format_value(b"KICKR\0"),
+        Some("KICKR".to_string())
+    );
+    assert_eq!(
+        GattDataType::Uint8.format_value(&[87]),
+        Some("87".to_string())
     );
 }
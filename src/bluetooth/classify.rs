@@ -0,0 +1,228 @@
+// Guesses what a device is from its advertisement (services, FTMS service data, company id)
+// and, once connected, from its GATT table, so the device lists say more than a name.
+
+/*=======================================================================
+ * IMPORTS
+ * ====================================================================*/
+// local files
+use crate::bluetooth::discovery::{
+    CPS_SERVICE, CSC_SERVICE, FEC_SERVICE, FTMS_SERVICE, HEART_RATE_SERVICE,
+};
+use crate::bluetooth::session::Metric;
+use crate::bluetooth::wahoo::WAHOO_TRAINER_CONTROL;
+
+// external crates
+use btleplug::api::Peripheral as Peripheral_api;
+use btleplug::platform::Peripheral;
+use std::collections::HashMap;
+use std::fmt;
+use uuid::Uuid;
+
+/*=======================================================================
+ * CONSTANTS
+ * ====================================================================*/
+/// Fitness Machine Type bit for an indoor bike in the FTMS service data
+const FTMS_TYPE_INDOOR_BIKE: u16 = 1 << 5;
+
+/// company identifiers from manufacturer specific data
+const COMPANIES: [(u16, &str); 2] = [(0x006B, "Polar"), (0x0087, "Garmin")];
+
+/// protocols worth naming in the classification, in the order they are listed
+const PROTOCOLS: [(Uuid, &str); 6] = [
+    (FTMS_SERVICE, "FTMS"),
+    (CPS_SERVICE, "CPS"),
+    (FEC_SERVICE, "FE-C"),
+    (WAHOO_TRAINER_CONTROL, "Wahoo"),
+    (CSC_SERVICE, "CSC"),
+    (HEART_RATE_SERVICE, "HRS"),
+];
+
+/*=======================================================================
+ * ENUMS
+ * ====================================================================*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceClass {
+    SmartTrainer,
+    FitnessMachine, // FTMS, but a rower or treadmill
+    PowerMeter,
+    HeartRateMonitor,
+    SpeedCadenceSensor,
+    Unknown,
+}
+
+impl DeviceClass {
+    /// shown in front of the name in the device lists
+    pub fn icon(&self) -> &'static str {
+        match self {
+            DeviceClass::SmartTrainer => "🚲",
+            DeviceClass::FitnessMachine => "🏃",
+            DeviceClass::PowerMeter => "⚡",
+            DeviceClass::HeartRateMonitor => "❤",
+            DeviceClass::SpeedCadenceSensor => "🔄",
+            DeviceClass::Unknown => "❓",
+        }
+    }
+
+    /// metrics a sensor of this class is made primary for when it connects
+    pub fn roles(&self) -> &'static [Metric] {
+        match self {
+            DeviceClass::PowerMeter => &[
+                Metric::Power,
+                Metric::Cadence,
+                Metric::LeftBalance,
+                Metric::TorqueEffectiveness,
+                Metric::PedalSmoothness,
+            ],
+            DeviceClass::HeartRateMonitor => &[Metric::HeartRate],
+            DeviceClass::SpeedCadenceSensor => &[Metric::Cadence, Metric::Speed],
+            // a trainer connected as a sensor is just another source
+            _ => &[],
+        }
+    }
+}
+
+impl fmt::Display for DeviceClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            DeviceClass::SmartTrainer => "smart trainer",
+            DeviceClass::FitnessMachine => "fitness machine",
+            DeviceClass::PowerMeter => "power meter",
+            DeviceClass::HeartRateMonitor => "HR strap",
+            DeviceClass::SpeedCadenceSensor => "speed/cadence sensor",
+            DeviceClass::Unknown => "unknown device",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/*=======================================================================
+ * STRUCTS
+ * ====================================================================*/
+/// e.g. "smart trainer (FTMS+CPS)"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Classification {
+    pub class: DeviceClass,
+    pub protocols: Vec<&'static str>,
+    pub vendor: Option<&'static str>,
+}
+
+impl fmt::Display for Classification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.class)?;
+        if !self.protocols.is_empty() {
+            write!(f, " ({})", self.protocols.join("+"))?;
+        }
+        if let Some(vendor) = self.vendor {
+            write!(f, ", {}", vendor)?;
+        }
+        Ok(())
+    }
+}
+
+/*=======================================================================
+ * FUNCTIONS
+ * ====================================================================*/
+/// uuids can be advertised services or, once connected, services and characteristics
+pub fn classify(
+    uuids: &[Uuid],
+    service_data: &HashMap<Uuid, Vec<u8>>,
+    manufacturer_data: &HashMap<u16, Vec<u8>>,
+) -> Classification {
+    let has = |uuid: Uuid| uuids.contains(&uuid);
+    let protocols: Vec<&'static str> = PROTOCOLS
+        .iter()
+        .filter(|(uuid, _)| has(*uuid))
+        .map(|(_, name)| *name)
+        .collect();
+    let vendor = COMPANIES
+        .iter()
+        .find(|(company, _)| manufacturer_data.contains_key(company))
+        .map(|(_, name)| *name);
+    // FTMS devices advertise which kind of machine they are, older ones don't
+    let ftms_machine_type = service_data
+        .get(&FTMS_SERVICE)
+        .filter(|data| data.len() >= 3)
+        .map(|data| u16::from_le_bytes([data[1], data[2]]));
+    let class = if has(FTMS_SERVICE)
+        && ftms_machine_type.is_some_and(|kind| kind & FTMS_TYPE_INDOOR_BIKE == 0)
+    {
+        DeviceClass::FitnessMachine
+    } else if has(FTMS_SERVICE) || has(FEC_SERVICE) || has(WAHOO_TRAINER_CONTROL) {
+        DeviceClass::SmartTrainer
+    } else if has(CPS_SERVICE) {
+        DeviceClass::PowerMeter
+    } else if has(HEART_RATE_SERVICE) {
+        DeviceClass::HeartRateMonitor
+    } else if has(CSC_SERVICE) {
+        DeviceClass::SpeedCadenceSensor
+    } else {
+        DeviceClass::Unknown
+    };
+    Classification {
+        class,
+        protocols,
+        vendor,
+    }
+}
+
+/// classification of a connected peripheral, its GATT table knows more than the advertisement
+pub fn classify_connected(
+    peripheral: &Peripheral,
+    advertised: Option<&Classification>,
+) -> Classification {
+    let mut uuids: Vec<Uuid> = peripheral
+        .services()
+        .iter()
+        .map(|service| service.uuid)
+        .collect();
+    uuids.extend(
+        peripheral
+            .characteristics()
+            .iter()
+            .map(|characteristic| characteristic.uuid),
+    );
+    let mut classification = classify(&uuids, &HashMap::new(), &HashMap::new());
+    if let Some(advertised) = advertised {
+        classification.vendor = advertised.vendor;
+        // the machine type is only in the advertisement
+        if advertised.class == DeviceClass::FitnessMachine {
+            classification.class = DeviceClass::FitnessMachine;
+        }
+    }
+    classification
+}
+
+#[test]
+fn classifies_trainers_and_sensors() {
+    let no_data = HashMap::new();
+    let no_company = HashMap::new();
+    let trainer = classify(&[CPS_SERVICE, FTMS_SERVICE], &no_data, &no_company);
+    assert_eq!(trainer.class, DeviceClass::SmartTrainer);
+    assert_eq!(trainer.to_string(), "smart trainer (FTMS+CPS)");
+    let power_meter = classify(&[CSC_SERVICE, CPS_SERVICE], &no_data, &no_company);
+    assert_eq!(power_meter.to_string(), "power meter (CPS+CSC)");
+    let polar = HashMap::from([(0x006B, vec![0x3F, 0x00])]);
+    assert_eq!(
+        classify(&[HEART_RATE_SERVICE], &no_data, &polar).to_string(),
+        "HR strap (HRS), Polar"
+    );
+    assert_eq!(
+        classify(&[CSC_SERVICE], &no_data, &no_company).class,
+        DeviceClass::SpeedCadenceSensor
+    );
+    // flags, then Fitness Machine Type with only the rower bit set
+    let rower = HashMap::from([(FTMS_SERVICE, vec![0x01, 0x10, 0x00])]);
+    assert_eq!(
+        classify(&[FTMS_SERVICE], &rower, &no_company).class,
+        DeviceClass::FitnessMachine
+    );
+    let bike = HashMap::from([(FTMS_SERVICE, vec![0x01, 0x20, 0x00])]);
+    assert_eq!(
+        classify(&[FTMS_SERVICE], &bike, &no_company).class,
+        DeviceClass::SmartTrainer
+    );
+    assert_eq!(
+        classify(&[], &no_data, &no_company).class,
+        DeviceClass::Unknown
+    );
+}
@@ -1,18 +1,293 @@
 /*=======================================================================
  * IMPORTS
  * ====================================================================*/
+// local files
+use crate::bluetooth::cscs::SensorLocation;
+use crate::bluetooth::{
+    control_point_request, read_characteristic, BleParseError, BleRequestError, ByteReader,
+    ControlPointResult, CONTROL_POINT_TIMEOUT,
+};
+
 // external crates
+use btleplug::platform::Peripheral;
 use proc_bitfield::{self, bitfield};
+use std::fmt;
+use std::time::Duration;
 use uuid::{uuid, Uuid};
 
 /*======================================================@=================
  * CONSTANTS
  * ====================================================================*/
 pub const CPS_POWER_MEASUREMENT: Uuid = uuid!("00002a63-0000-1000-8000-00805f9b34fb");
-//pub const CPS_POWER_VECTOR: Uuid = uuid!("00002a64-0000-1000-8000-00805f9b34fb");
+pub const CPS_POWER_VECTOR: Uuid = uuid!("00002a64-0000-1000-8000-00805f9b34fb");
 pub const CPS_POWER_FEATURE: Uuid = uuid!("00002a65-0000-1000-8000-00805f9b34fb");
 pub const CPS_CONTROL_POINT: Uuid = uuid!("00002a66-0000-1000-8000-00805f9b34fb");
 
+/// the meter has to measure with the cranks unloaded, the spec allows up to 30 s
+pub const OFFSET_COMPENSATION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// torque magnitudes in the Cycling Power Vector are 1/32 Nm
+pub const VECTOR_TORQUE_RESOLUTION: f32 = 32.0;
+/// 10 degrees per bin of the pedal stroke plot
+pub const PEDAL_STROKE_BINS: usize = 36;
+/// weight of a new sample in its bin, older revolutions fade out
+const PEDAL_STROKE_SMOOTHING: f32 = 0.3;
+
+/// turns extreme forces into torques, the common crank length, m
+pub const DEFAULT_CRANK_LENGTH: f32 = 0.1725;
+/// accumulated torque is a u16 of 1/32 Nm
+const ACCUMULATED_TORQUE_ROLLOVER: f32 = 65536.0 / 32.0;
+
+/*=======================================================================
+ * ENUMS
+ * ====================================================================*/
+/// Cycling Power Control Point op codes (Section 3.5.1)
+#[allow(dead_code)] // full table from the spec, not every procedure is used yet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpsOpCode {
+    SetCumulativeValue = 0x01,
+    UpdateSensorLocation = 0x02,
+    RequestSupportedSensorLocations = 0x03,
+    SetCrankLength = 0x04,
+    RequestCrankLength = 0x05,
+    SetChainLength = 0x06,
+    RequestChainLength = 0x07,
+    SetChainWeight = 0x08,
+    RequestChainWeight = 0x09,
+    SetSpanLength = 0x0A,
+    RequestSpanLength = 0x0B,
+    StartOffsetCompensation = 0x0C,
+    MaskMeasurementContent = 0x0D,
+    RequestSamplingRate = 0x0E,
+    RequestFactoryCalibrationDate = 0x0F,
+    StartEnhancedOffsetCompensation = 0x10,
+    ResponseCode = 0x20,
+}
+
+/// a Cycling Power Control Point procedure with its parameter
+#[derive(Debug, Clone, PartialEq)]
+pub enum CpsRequest {
+    SetCumulativeValue(u32), // wheel revolutions
+    UpdateSensorLocation(SensorLocation),
+    RequestSupportedSensorLocations,
+    SetCrankLength(f32), // mm, resolution 0.5
+    RequestCrankLength,
+    SetChainLength(u16), // mm
+    RequestChainLength,
+    SetChainWeight(u16), // g
+    RequestChainWeight,
+    SetSpanLength(u16), // mm
+    RequestSpanLength,
+    StartOffsetCompensation,
+    StartEnhancedOffsetCompensation,
+    MaskMeasurementContent(CpsContentMask),
+    RequestFactoryCalibrationDate,
+}
+
+impl CpsRequest {
+    pub fn op_code(&self) -> CpsOpCode {
+        match self {
+            CpsRequest::SetCumulativeValue(_) => CpsOpCode::SetCumulativeValue,
+            CpsRequest::UpdateSensorLocation(_) => CpsOpCode::UpdateSensorLocation,
+            CpsRequest::RequestSupportedSensorLocations => {
+                CpsOpCode::RequestSupportedSensorLocations
+            }
+            CpsRequest::SetCrankLength(_) => CpsOpCode::SetCrankLength,
+            CpsRequest::RequestCrankLength => CpsOpCode::RequestCrankLength,
+            CpsRequest::SetChainLength(_) => CpsOpCode::SetChainLength,
+            CpsRequest::RequestChainLength => CpsOpCode::RequestChainLength,
+            CpsRequest::SetChainWeight(_) => CpsOpCode::SetChainWeight,
+            CpsRequest::RequestChainWeight => CpsOpCode::RequestChainWeight,
+            CpsRequest::SetSpanLength(_) => CpsOpCode::SetSpanLength,
+            CpsRequest::RequestSpanLength => CpsOpCode::RequestSpanLength,
+            CpsRequest::StartOffsetCompensation => CpsOpCode::StartOffsetCompensation,
+            CpsRequest::StartEnhancedOffsetCompensation => {
+                CpsOpCode::StartEnhancedOffsetCompensation
+            }
+            CpsRequest::MaskMeasurementContent(_) => CpsOpCode::MaskMeasurementContent,
+            CpsRequest::RequestFactoryCalibrationDate => CpsOpCode::RequestFactoryCalibrationDate,
+        }
+    }
+
+    /// bytes written to the control point
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = vec![self.op_code() as u8];
+        match self {
+            CpsRequest::SetCumulativeValue(value) => buf.extend_from_slice(&value.to_le_bytes()),
+            CpsRequest::UpdateSensorLocation(location) => buf.push(location.to_u8()),
+            CpsRequest::SetCrankLength(length) => {
+                let half_mm = (length * 2.0).round().clamp(0.0, u16::MAX as f32) as u16;
+                buf.extend_from_slice(&half_mm.to_le_bytes());
+            }
+            CpsRequest::SetChainLength(value)
+            | CpsRequest::SetChainWeight(value)
+            | CpsRequest::SetSpanLength(value) => buf.extend_from_slice(&value.to_le_bytes()),
+            CpsRequest::MaskMeasurementContent(mask) => {
+                buf.extend_from_slice(&mask.0.to_le_bytes())
+            }
+            _ => {}
+        }
+        buf
+    }
+
+    /// whether the Cycling Power Feature says the meter implements this procedure
+    pub fn is_supported(&self, feature: CpsFeature) -> bool {
+        match self {
+            CpsRequest::SetCumulativeValue(_) => feature.wheel_revolution_data_supported(),
+            CpsRequest::UpdateSensorLocation(_) | CpsRequest::RequestSupportedSensorLocations => {
+                feature.multiple_sensor_locations_supported()
+            }
+            CpsRequest::SetCrankLength(_) | CpsRequest::RequestCrankLength => {
+                feature.crank_length_adjustment_supported()
+            }
+            CpsRequest::SetChainLength(_) | CpsRequest::RequestChainLength => {
+                feature.chain_length_adjustment_supported()
+            }
+            CpsRequest::SetChainWeight(_) | CpsRequest::RequestChainWeight => {
+                feature.chain_weight_adjustment_supported()
+            }
+            CpsRequest::SetSpanLength(_) | CpsRequest::RequestSpanLength => {
+                feature.span_length_adjustment_supported()
+            }
+            CpsRequest::StartOffsetCompensation => feature.offset_compensation_supported(),
+            CpsRequest::StartEnhancedOffsetCompensation => {
+                feature.enhanced_offset_compensation_supported()
+            }
+            CpsRequest::MaskMeasurementContent(_) => {
+                feature.cycling_power_measurement_characteristic_content_masking_supported()
+            }
+            CpsRequest::RequestFactoryCalibrationDate => {
+                feature.factory_calibration_date_supported()
+            }
+        }
+    }
+
+    /// how long to wait for the response indication
+    pub fn timeout(&self) -> Duration {
+        match self {
+            CpsRequest::StartOffsetCompensation | CpsRequest::StartEnhancedOffsetCompensation => {
+                OFFSET_COMPENSATION_TIMEOUT
+            }
+            _ => CONTROL_POINT_TIMEOUT,
+        }
+    }
+}
+
+/// Instantaneous Measurement Direction of the Cycling Power Vector
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MeasurementDirection {
+    Unknown,
+    Tangential,
+    Radial,
+    Lateral,
+}
+
+impl MeasurementDirection {
+    pub fn from_u8(value: u8) -> Self {
+        match value {
+            1 => MeasurementDirection::Tangential,
+            2 => MeasurementDirection::Radial,
+            3 => MeasurementDirection::Lateral,
+            _ => MeasurementDirection::Unknown,
+        }
+    }
+}
+
+/// lengths and weights the meter can be asked for and told
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CpsSetting {
+    CrankLength,
+    ChainLength,
+    ChainWeight,
+    SpanLength,
+}
+
+impl CpsSetting {
+    pub const ALL: [CpsSetting; 4] = [
+        CpsSetting::CrankLength,
+        CpsSetting::ChainLength,
+        CpsSetting::ChainWeight,
+        CpsSetting::SpanLength,
+    ];
+
+    /// e.g. "Crank length (mm)"
+    pub fn label(&self) -> &'static str {
+        match self {
+            CpsSetting::CrankLength => "Crank length (mm)",
+            CpsSetting::ChainLength => "Chain length (mm)",
+            CpsSetting::ChainWeight => "Chain weight (g)",
+            CpsSetting::SpanLength => "Span length (mm)",
+        }
+    }
+
+    pub fn request(&self) -> CpsRequest {
+        match self {
+            CpsSetting::CrankLength => CpsRequest::RequestCrankLength,
+            CpsSetting::ChainLength => CpsRequest::RequestChainLength,
+            CpsSetting::ChainWeight => CpsRequest::RequestChainWeight,
+            CpsSetting::SpanLength => CpsRequest::RequestSpanLength,
+        }
+    }
+
+    /// None if value doesn't fit the parameter
+    pub fn set_request(&self, value: f32) -> Option<CpsRequest> {
+        if !(0.0..=u16::MAX as f32).contains(&value) {
+            return None;
+        }
+        let whole = value.round() as u16;
+        Some(match self {
+            CpsSetting::CrankLength => CpsRequest::SetCrankLength(value),
+            CpsSetting::ChainLength => CpsRequest::SetChainLength(whole),
+            CpsSetting::ChainWeight => CpsRequest::SetChainWeight(whole),
+            CpsSetting::SpanLength => CpsRequest::SetSpanLength(whole),
+        })
+    }
+
+    /// the value in a response to request()
+    pub fn value(response: &CpsResponse) -> Option<f32> {
+        match response {
+            CpsResponse::CrankLength(length) => Some(*length),
+            CpsResponse::ChainLength(value)
+            | CpsResponse::ChainWeight(value)
+            | CpsResponse::SpanLength(value) => Some(*value as f32),
+            _ => None,
+        }
+    }
+}
+
+/// decoded answer to a CpsRequest
+#[derive(Debug, Clone, PartialEq)]
+pub enum CpsResponse {
+    Done, // set procedures don't return anything
+    SupportedSensorLocations(Vec<SensorLocation>),
+    CrankLength(f32), // mm
+    ChainLength(u16), // mm
+    ChainWeight(u16), // g
+    SpanLength(u16),  // mm
+    /// raw force (N) or torque (1/32 Nm) measured with the cranks unloaded
+    OffsetCompensation(i16),
+    FactoryCalibrationDate(CalibrationDate),
+}
+
+impl fmt::Display for CpsResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CpsResponse::Done => write!(f, "done"),
+            CpsResponse::SupportedSensorLocations(locations) => {
+                write!(f, "supported locations: {:?}", locations)
+            }
+            CpsResponse::CrankLength(length) => write!(f, "crank length {:.1} mm", length),
+            CpsResponse::ChainLength(length) => write!(f, "chain length {} mm", length),
+            CpsResponse::ChainWeight(weight) => write!(f, "chain weight {} g", weight),
+            CpsResponse::SpanLength(length) => write!(f, "span length {} mm", length),
+            CpsResponse::OffsetCompensation(offset) => write!(f, "offset {}", offset),
+            CpsResponse::FactoryCalibrationDate(date) => {
+                write!(f, "factory calibration {}", date)
+            }
+        }
+    }
+}
+
 /*=======================================================================
  * STRUCTS
  * ====================================================================*/
@@ -20,6 +295,7 @@ pub const CPS_CONTROL_POINT: Uuid = uuid!("00002a66-0000-1000-8000-00805f9b34fb"
 // Cycling Power Feature [https://www.bluetooth.com/specifications/specs/cycling-power-service-1-1/] (Section 4.4)
 // Table 4.5 AND Table 4.6???
 bitfield! {
+    #[derive(Clone, Copy, PartialEq, Eq)]
     pub struct CpsFeature(pub u32): Debug {
         pub pedal_power_balance_supported: bool @ 0,
         pub accumulated_torque_supported: bool @ 1,
@@ -50,6 +326,7 @@ bitfield! {
 // Mask Cycling Power Measurement Characteristic Content Procedure (4.7.2.13)
 
 bitfield! {
+    #[derive(Clone, Copy, PartialEq, Eq)]
     pub struct CpsFlag(pub u16): Debug {
         pub pedal_power_balance_present: bool @ 0,
         pub pedal_power_balance_reference: bool @ 1,
@@ -67,6 +344,691 @@ bitfield! {
     }
 }
 
+// Mask Cycling Power Measurement Characteristic Content parameter (Section 3.5.2.13)
+// a set bit turns the field off
+bitfield! {
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    pub struct CpsContentMask(pub u16): Debug {
+        pub pedal_power_balance: bool @ 0,
+        pub accumulated_torque: bool @ 1,
+        pub wheel_revolution_data: bool @ 2,
+        pub crank_revolution_data: bool @ 3,
+        pub extreme_magnitudes: bool @ 4,
+        pub extreme_angles: bool @ 5,
+        pub top_dead_spot_angle: bool @ 6,
+        pub bottom_dead_spot_angle: bool @ 7,
+        pub accumulated_energy: bool @ 8,
+    }
+}
+
+// Cycling Power Vector flags [https://www.bluetooth.com/specifications/specs/cycling-power-service-1-1/] (Section 3.3)
+bitfield! {
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    pub struct CpsVectorFlag(pub u8): Debug {
+        pub crank_revolution_data_present: bool @ 0,
+        pub first_crank_measurement_angle_present: bool @ 1,
+        pub force_magnitude_array_present: bool @ 2,
+        pub torque_magnitude_array_present: bool @ 3,
+        pub measurement_direction: u8 @ 4..=5,
+    }
+}
+
+/// decoded Cycling Power Vector notification
+/// the magnitudes are sampled at equal intervals starting at the first crank angle
+#[derive(Debug, Clone, PartialEq)]
+pub struct CpsVector {
+    pub flags: CpsVectorFlag,
+    pub crank_revolution_data: Option<CrankRevolutionData>,
+    pub first_crank_measurement_angle: Option<u16>, // degrees
+    pub force_magnitudes: Vec<i16>,                 // N
+    pub torque_magnitudes: Vec<f32>,                // Nm
+    pub direction: MeasurementDirection,
+}
+
+/// force or torque against crank angle, averaged over the last few revolutions
+/// each vector's samples are spread from its first angle to the next vector's first angle
+#[derive(Debug, Clone, PartialEq)]
+pub struct PedalStroke {
+    bins: [Option<f32>; PEDAL_STROKE_BINS],
+    pending: Option<(u16, Vec<f32>)>, // first angle and samples of the last vector
+    pub torque: bool,                 // Nm if set, N otherwise
+    pub direction: MeasurementDirection,
+}
+
+impl Default for PedalStroke {
+    fn default() -> Self {
+        Self {
+            bins: [None; PEDAL_STROKE_BINS],
+            pending: None,
+            torque: false,
+            direction: MeasurementDirection::Unknown,
+        }
+    }
+}
+
+impl PedalStroke {
+    pub fn update(&mut self, vector: &CpsVector) {
+        let torque = !vector.torque_magnitudes.is_empty();
+        let samples: Vec<f32> = match torque {
+            true => vector.torque_magnitudes.clone(),
+            false => vector.force_magnitudes.iter().map(|f| *f as f32).collect(),
+        };
+        // without an angle the samples can't be placed
+        let Some(angle) = vector.first_crank_measurement_angle else {
+            return;
+        };
+        if samples.is_empty() {
+            return;
+        }
+        if torque != self.torque {
+            *self = Self::default();
+            self.torque = torque;
+        }
+        self.direction = vector.direction;
+        if let Some((first_angle, previous)) = self.pending.take() {
+            let span = match (angle as u32 + 360 - first_angle as u32 % 360) % 360 {
+                0 => 360, // a whole revolution between the two vectors
+                span => span,
+            };
+            for (i, value) in previous.iter().enumerate() {
+                let sample_angle =
+                    first_angle as f32 + span as f32 * i as f32 / previous.len() as f32;
+                let bin = (sample_angle.rem_euclid(360.0) / 360.0 * PEDAL_STROKE_BINS as f32)
+                    as usize
+                    % PEDAL_STROKE_BINS;
+                self.bins[bin] = Some(match self.bins[bin] {
+                    Some(old) => old + (value - old) * PEDAL_STROKE_SMOOTHING,
+                    None => *value,
+                });
+            }
+        }
+        self.pending = Some((angle % 360, samples));
+    }
+
+    /// (bin centre angle in degrees from top dead centre, value) for every bin with data
+    pub fn bins(&self) -> impl Iterator<Item = (f32, f32)> + '_ {
+        let width = 360.0 / PEDAL_STROKE_BINS as f32;
+        self.bins
+            .iter()
+            .enumerate()
+            .filter_map(move |(i, value)| Some(((i as f32 + 0.5) * width, (*value)?)))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bins.iter().all(|bin| bin.is_none())
+    }
+}
+
+/// pedal dynamics from one power measurement, all in %
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PedalDynamics {
+    pub left_balance: Option<f32>,
+    pub torque_effectiveness: Option<f32>, // estimated, the Cycling Power Service doesn't send it
+    pub pedal_smoothness: Option<f32>,
+}
+
+impl PedalDynamics {
+    pub fn right_balance(&self) -> Option<f32> {
+        self.left_balance.map(|left| 100.0 - left)
+    }
+}
+
+/// average torque per crank revolution from the accumulated torque, or from power and
+/// cadence if the meter doesn't send it, set against the extreme magnitudes
+#[derive(Debug, Clone, Default)]
+pub struct PedalDynamicsCalculator {
+    last_accumulated: Option<(f32, u16)>, // accumulated torque and crank revolutions
+    average_torque: Option<f32>,          // Nm, over the last revolutions
+}
+
+impl PedalDynamicsCalculator {
+    /// cadence in rpm
+    pub fn update(&mut self, measurement: &CpsMeasurement, cadence: f32) -> PedalDynamics {
+        // without the reference the balance could be either pedal's
+        let left_balance = match measurement.flags.pedal_power_balance_reference() {
+            true => measurement.pedal_power_balance,
+            false => None,
+        };
+        let average = self.average_torque(measurement, cadence);
+        let extremes = match (
+            measurement.maximum_torque_magnitude,
+            measurement.minimum_torque_magnitude,
+            measurement.maximum_force_magnitude,
+            measurement.minimum_force_magnitude,
+        ) {
+            (Some(max), Some(min), _, _) => Some((max, min)),
+            (_, _, Some(max), Some(min)) => Some((
+                max as f32 * DEFAULT_CRANK_LENGTH,
+                min as f32 * DEFAULT_CRANK_LENGTH,
+            )),
+            _ => None,
+        };
+        let (torque_effectiveness, pedal_smoothness) = match (average, extremes) {
+            (Some(average), Some((max, min))) if average > 0.0 && max > 0.0 => (
+                torque_effectiveness(max, min),
+                Some((average / max * 100.0).min(100.0)),
+            ),
+            _ => (None, None),
+        };
+        PedalDynamics {
+            left_balance,
+            torque_effectiveness,
+            pedal_smoothness,
+        }
+    }
+
+    fn average_torque(&mut self, measurement: &CpsMeasurement, cadence: f32) -> Option<f32> {
+        let crank_based = measurement.flags.accumulated_torque_source();
+        if let (true, Some(torque), Some(crank)) = (
+            crank_based,
+            measurement.accumulated_torque,
+            measurement.crank_revolution_data,
+        ) {
+            if let Some((last_torque, last_revolutions)) = self.last_accumulated {
+                let revolutions = crank.cumulative_revolutions.wrapping_sub(last_revolutions);
+                // no new crank event, the last average still holds
+                if revolutions > 0 {
+                    let torque = (torque - last_torque).rem_euclid(ACCUMULATED_TORQUE_ROLLOVER);
+                    self.average_torque = Some(torque / revolutions as f32);
+                }
+            }
+            self.last_accumulated = Some((torque, crank.cumulative_revolutions));
+            return self.average_torque;
+        }
+        if cadence <= 0.0 {
+            return None;
+        }
+        let angular_speed = cadence * 2.0 * std::f32::consts::PI / 60.0; // rad/s
+        Some(measurement.instantaneous_power as f32 / angular_speed)
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// GATT Date Time, as returned by Request Factory Calibration Date
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CalibrationDate {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hours: u8,
+    pub minutes: u8,
+    pub seconds: u8,
+}
+
+impl fmt::Display for CalibrationDate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hours, self.minutes, self.seconds
+        )
+    }
+}
+
+/// decoded Cycling Power Control Point response indication
+#[derive(Debug, Clone, PartialEq)]
+pub struct CpsControlPointResponse {
+    pub request_op_code: u8,
+    pub result: ControlPointResult,
+    pub parameter: Vec<u8>,
+}
+
+/// cumulative wheel revolutions and the time of the last wheel event
+/// CPS reports the event time in 1/2048 s, CSC uses 1/1024 s
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WheelRevolutionData {
+    pub cumulative_revolutions: u32,
+    pub last_event_time: u16,
+}
+
+/// cumulative crank revolutions and the time of the last crank event (1/1024 s)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CrankRevolutionData {
+    pub cumulative_revolutions: u16,
+    pub last_event_time: u16,
+}
+
+/// decoded Cycling Power Measurement notification
+/// optional fields are only filled in when the matching flag is set
+#[derive(Debug, Clone, PartialEq)]
+pub struct CpsMeasurement {
+    pub flags: CpsFlag,
+    pub instantaneous_power: i16,         // W
+    pub pedal_power_balance: Option<f32>, // %, reference depends on flags
+    pub accumulated_torque: Option<f32>,  // Nm
+    pub wheel_revolution_data: Option<WheelRevolutionData>,
+    pub crank_revolution_data: Option<CrankRevolutionData>,
+    pub maximum_force_magnitude: Option<i16>,  // N
+    pub minimum_force_magnitude: Option<i16>,  // N
+    pub maximum_torque_magnitude: Option<f32>, // Nm
+    pub minimum_torque_magnitude: Option<f32>, // Nm
+    pub maximum_angle: Option<u16>,            // degrees
+    pub minimum_angle: Option<u16>,            // degrees
+    pub top_dead_spot_angle: Option<u16>,      // degrees
+    pub bottom_dead_spot_angle: Option<u16>,   // degrees
+    pub accumulated_energy: Option<u16>,       // kJ
+}
+
 /*=======================================================================
  * FUNCTIONS
  * ====================================================================*/
+/// decodes a Cycling Power Measurement notification (Section 3.2)
+/// fields are read in spec order, skipping anything the flags say is absent
+pub fn parse_cps_measurement(buf: &[u8]) -> Result<CpsMeasurement, BleParseError> {
+    let mut reader = ByteReader::new("Cycling Power Measurement", buf);
+    let flags = CpsFlag(reader.read_u16("flags")?);
+    let instantaneous_power = reader.read_i16("instantaneous power")?;
+
+    let mut measurement = CpsMeasurement {
+        flags,
+        instantaneous_power,
+        pedal_power_balance: None,
+        accumulated_torque: None,
+        wheel_revolution_data: None,
+        crank_revolution_data: None,
+        maximum_force_magnitude: None,
+        minimum_force_magnitude: None,
+        maximum_torque_magnitude: None,
+        minimum_torque_magnitude: None,
+        maximum_angle: None,
+        minimum_angle: None,
+        top_dead_spot_angle: None,
+        bottom_dead_spot_angle: None,
+        accumulated_energy: None,
+    };
+
+    if flags.pedal_power_balance_present() {
+        // resolution 1/2 %
+        measurement.pedal_power_balance = Some(reader.read_u8("pedal power balance")? as f32 / 2.0);
+    }
+    if flags.accumulated_torque_present() {
+        // resolution 1/32 Nm
+        measurement.accumulated_torque = Some(reader.read_u16("accumulated torque")? as f32 / 32.0);
+    }
+    if flags.wheel_revolution_data_present() {
+        measurement.wheel_revolution_data = Some(WheelRevolutionData {
+            cumulative_revolutions: reader.read_u32("cumulative wheel revolutions")?,
+            last_event_time: reader.read_u16("last wheel event time")?,
+        });
+    }
+    if flags.crank_revolution_data_present() {
+        measurement.crank_revolution_data = Some(CrankRevolutionData {
+            cumulative_revolutions: reader.read_u16("cumulative crank revolutions")?,
+            last_event_time: reader.read_u16("last crank event time")?,
+        });
+    }
+    if flags.extreme_force_magnitudes_present() {
+        measurement.maximum_force_magnitude = Some(reader.read_i16("maximum force magnitude")?);
+        measurement.minimum_force_magnitude = Some(reader.read_i16("minimum force magnitude")?);
+    }
+    if flags.extreme_torque_magnitudes_present() {
+        // resolution 1/32 Nm
+        measurement.maximum_torque_magnitude =
+            Some(reader.read_i16("maximum torque magnitude")? as f32 / 32.0);
+        measurement.minimum_torque_magnitude =
+            Some(reader.read_i16("minimum torque magnitude")? as f32 / 32.0);
+    }
+    if flags.extreme_angles_present() {
+        // two 12 bit angles packed into 3 bytes, maximum in the low bits
+        let angles = reader.read_u24("extreme angles")?;
+        measurement.maximum_angle = Some((angles & 0x0FFF) as u16);
+        measurement.minimum_angle = Some((angles >> 12) as u16);
+    }
+    if flags.top_dead_spot_angle_present() {
+        measurement.top_dead_spot_angle = Some(reader.read_u16("top dead spot angle")?);
+    }
+    if flags.bottom_dead_spot_angle_present() {
+        measurement.bottom_dead_spot_angle = Some(reader.read_u16("bottom dead spot angle")?);
+    }
+    if flags.accumulated_energy_present() {
+        measurement.accumulated_energy = Some(reader.read_u16("accumulated energy")?);
+    }
+
+    Ok(measurement)
+}
+
+/// decodes a Cycling Power Vector notification (Section 3.3)
+/// the magnitude array takes up the rest of the packet
+pub fn parse_cps_vector(buf: &[u8]) -> Result<CpsVector, BleParseError> {
+    let mut reader = ByteReader::new("Cycling Power Vector", buf);
+    let flags = CpsVectorFlag(reader.read_u8("flags")?);
+    let mut vector = CpsVector {
+        flags,
+        crank_revolution_data: None,
+        first_crank_measurement_angle: None,
+        force_magnitudes: Vec::new(),
+        torque_magnitudes: Vec::new(),
+        direction: MeasurementDirection::from_u8(flags.measurement_direction()),
+    };
+    if flags.crank_revolution_data_present() {
+        vector.crank_revolution_data = Some(CrankRevolutionData {
+            cumulative_revolutions: reader.read_u16("cumulative crank revolutions")?,
+            last_event_time: reader.read_u16("last crank event time")?,
+        });
+    }
+    if flags.first_crank_measurement_angle_present() {
+        vector.first_crank_measurement_angle = Some(reader.read_u16("first crank angle")?);
+    }
+    if flags.force_magnitude_array_present() && flags.torque_magnitude_array_present() {
+        return Err(BleParseError::Invalid {
+            characteristic: "Cycling Power Vector",
+            reason: "both force and torque arrays flagged".to_string(),
+        });
+    }
+    while reader.remaining() > 0 {
+        if flags.force_magnitude_array_present() {
+            vector
+                .force_magnitudes
+                .push(reader.read_i16("force magnitude")?);
+        } else if flags.torque_magnitude_array_present() {
+            let torque = reader.read_i16("torque magnitude")? as f32 / VECTOR_TORQUE_RESOLUTION;
+            vector.torque_magnitudes.push(torque);
+        } else {
+            break;
+        }
+    }
+    Ok(vector)
+}
+
+/// estimated share of the positive torque that isn't undone by pulling back against the
+/// pedal, %. only the extremes are known, so the torque is taken as a sine between them
+pub fn torque_effectiveness(maximum: f32, minimum: f32) -> Option<f32> {
+    let mean = (maximum + minimum) / 2.0;
+    let amplitude = (maximum - minimum) / 2.0;
+    if mean <= 0.0 {
+        return None;
+    }
+    if amplitude <= mean {
+        return Some(100.0); // never negative
+    }
+    // the torque is positive for |angle| < phi
+    let phi = (-mean / amplitude).acos();
+    let positive = (mean * phi + amplitude * phi.sin()) / std::f32::consts::PI;
+    Some((mean / positive * 100.0).min(100.0))
+}
+
+/// decodes the Cycling Power Feature characteristic (Section 3.1)
+pub fn parse_cps_feature(buf: &[u8]) -> Result<CpsFeature, BleParseError> {
+    let mut reader = ByteReader::new("Cycling Power Feature", buf);
+    Ok(CpsFeature(reader.read_u32("feature")?))
+}
+
+pub async fn read_cps_feature(peripheral: &Peripheral) -> Result<CpsFeature, BleRequestError> {
+    let buf = read_characteristic(peripheral, CPS_POWER_FEATURE).await?;
+    Ok(parse_cps_feature(&buf)?)
+}
+
+/// decodes the Response Code indication (Section 3.5.2.1)
+pub fn parse_cps_control_point_response(
+    buf: &[u8],
+) -> Result<CpsControlPointResponse, BleParseError> {
+    let mut reader = ByteReader::new("Cycling Power Control Point", buf);
+    reader.read_u8("response op code")?;
+    let request_op_code = reader.read_u8("request op code")?;
+    let result = ControlPointResult::from_u8(reader.read_u8("response value")?);
+    Ok(CpsControlPointResponse {
+        request_op_code,
+        result,
+        parameter: buf[3..].to_vec(),
+    })
+}
+
+/// turns the parameter of a successful response into the value the request asked for
+pub fn decode_cps_response(
+    request: &CpsRequest,
+    parameter: &[u8],
+) -> Result<CpsResponse, BleParseError> {
+    let mut reader = ByteReader::new("Cycling Power Control Point", parameter);
+    let response = match request {
+        CpsRequest::RequestSupportedSensorLocations => CpsResponse::SupportedSensorLocations(
+            parameter
+                .iter()
+                .map(|l| SensorLocation::from_u8(*l))
+                .collect(),
+        ),
+        CpsRequest::RequestCrankLength => {
+            CpsResponse::CrankLength(reader.read_u16("crank length")? as f32 / 2.0)
+        }
+        CpsRequest::RequestChainLength => {
+            CpsResponse::ChainLength(reader.read_u16("chain length")?)
+        }
+        CpsRequest::RequestChainWeight => {
+            CpsResponse::ChainWeight(reader.read_u16("chain weight")?)
+        }
+        CpsRequest::RequestSpanLength => CpsResponse::SpanLength(reader.read_u16("span length")?),
+        // the enhanced response may carry manufacturer data after the offset, it isn't used
+        CpsRequest::StartOffsetCompensation | CpsRequest::StartEnhancedOffsetCompensation => {
+            CpsResponse::OffsetCompensation(reader.read_i16("offset")?)
+        }
+        CpsRequest::RequestFactoryCalibrationDate => {
+            CpsResponse::FactoryCalibrationDate(CalibrationDate {
+                year: reader.read_u16("year")?,
+                month: reader.read_u8("month")?,
+                day: reader.read_u8("day")?,
+                hours: reader.read_u8("hours")?,
+                minutes: reader.read_u8("minutes")?,
+                seconds: reader.read_u8("seconds")?,
+            })
+        }
+        _ => CpsResponse::Done,
+    };
+    Ok(response)
+}
+
+/// runs a control point procedure, checks the meter accepted it and decodes the answer
+pub async fn cps_request(
+    peripheral: &Peripheral,
+    request: &CpsRequest,
+) -> Result<CpsResponse, BleRequestError> {
+    let buf = control_point_request(
+        peripheral,
+        CPS_CONTROL_POINT,
+        &request.encode(),
+        CpsOpCode::ResponseCode as u8,
+        request.timeout(),
+    )
+    .await?;
+    let response = parse_cps_control_point_response(&buf)?;
+    match response.result {
+        ControlPointResult::Success => Ok(decode_cps_response(request, &response.parameter)?),
+        other => Err(BleRequestError::Rejected(other)),
+    }
+}
+
+#[test]
+fn parses_power_only_measurement() {
+    // power only, 200 W, no optional fields
+    let measurement = parse_cps_measurement(&[0x00, 0x00, 0xC8, 0x00]).unwrap();
+    assert_eq!(measurement.instantaneous_power, 200);
+    assert_eq!(measurement.crank_revolution_data, None);
+    assert_eq!(measurement.wheel_revolution_data, None);
+}
+
+#[test]
+fn parses_measurement_with_wheel_and_crank_data() {
+    // flags 0x0034: accumulated torque, wheel and crank revolution data
+    let buf = [
+        0x34, 0x00, 0x2C, 0x01, 0x40, 0x06, 0x10, 0x27, 0x00, 0x00, 0x00, 0x08, 0x5A, 0x00, 0x00,
+        0x04,
+    ];
+    let measurement = parse_cps_measurement(&buf).unwrap();
+    assert_eq!(measurement.instantaneous_power, 300);
+    assert_eq!(measurement.accumulated_torque, Some(50.0));
+    assert_eq!(
+        measurement.wheel_revolution_data,
+        Some(WheelRevolutionData {
+            cumulative_revolutions: 10000,
+            last_event_time: 2048,
+        })
+    );
+    assert_eq!(
+        measurement.crank_revolution_data,
+        Some(CrankRevolutionData {
+            cumulative_revolutions: 90,
+            last_event_time: 1024,
+        })
+    );
+}
+
+#[test]
+fn parses_measurement_with_pedal_dynamics() {
+    // flags 0x0F41: balance, extreme forces, extreme angles, dead spots, energy
+    let buf = [
+        0x41, 0x0F, 0xFA, 0x00, 0x64, 0x2C, 0x01, 0xF6, 0xFF, 0x5A, 0x00, 0x0F, 0x14, 0x00, 0xC8,
+        0x00, 0x0C, 0x00,
+    ];
+    let measurement = parse_cps_measurement(&buf).unwrap();
+    assert_eq!(measurement.instantaneous_power, 250);
+    assert_eq!(measurement.pedal_power_balance, Some(50.0));
+    assert_eq!(measurement.maximum_force_magnitude, Some(300));
+    assert_eq!(measurement.minimum_force_magnitude, Some(-10));
+    assert_eq!(measurement.maximum_angle, Some(90));
+    assert_eq!(measurement.minimum_angle, Some(240));
+    assert_eq!(measurement.top_dead_spot_angle, Some(20));
+    assert_eq!(measurement.bottom_dead_spot_angle, Some(200));
+    assert_eq!(measurement.accumulated_energy, Some(12));
+}
+
+#[test]
+fn truncated_measurement_reports_missing_field() {
+    // crank data flag set but the event time was cut off
+    let buf = [0x20, 0x00, 0x2C, 0x01, 0x5A, 0x00, 0x00];
+    assert_eq!(
+        parse_cps_measurement(&buf),
+        Err(BleParseError::Truncated {
+            characteristic: "Cycling Power Measurement",
+            field: "last crank event time",
+            needed: 2,
+            available: 1,
+        })
+    );
+}
+
+#[test]
+fn parses_feature_and_rejects_short_reads() {
+    // crank revolution data + offset compensation
+    let feature = parse_cps_feature(&[0x08, 0x10, 0x00, 0x00]).unwrap();
+    assert!(feature.crank_revolution_data_supported());
+    assert!(feature.offset_compensation_supported());
+    assert!(!feature.enhanced_offset_compensation_supported());
+    assert!(parse_cps_feature(&[0x08, 0x10]).is_err());
+}
+
+#[test]
+fn encodes_control_point_requests() {
+    assert_eq!(
+        CpsRequest::SetCrankLength(172.5).encode(),
+        vec![0x04, 0x59, 0x01]
+    );
+    assert_eq!(
+        CpsRequest::UpdateSensorLocation(SensorLocation::LeftCrank).encode(),
+        vec![0x02, 0x05]
+    );
+    let mut mask = CpsContentMask(0);
+    mask.set_accumulated_energy(true);
+    assert_eq!(
+        CpsRequest::MaskMeasurementContent(mask).encode(),
+        vec![0x0D, 0x00, 0x01]
+    );
+    // crank length adjustment only
+    let feature = CpsFeature(1 << 15);
+    assert!(CpsRequest::RequestCrankLength.is_supported(feature));
+    assert!(!CpsRequest::StartOffsetCompensation.is_supported(feature));
+}
+
+#[test]
+fn decodes_control_point_responses() {
+    // response code, request op code, success, crank length 345 half mm
+    let response = parse_cps_control_point_response(&[0x20, 0x05, 0x01, 0x59, 0x01]).unwrap();
+    assert_eq!(response.result, ControlPointResult::Success);
+    assert_eq!(
+        decode_cps_response(&CpsRequest::RequestCrankLength, &response.parameter),
+        Ok(CpsResponse::CrankLength(172.5))
+    );
+    assert_eq!(
+        decode_cps_response(&CpsRequest::StartOffsetCompensation, &[0xF6, 0xFF]),
+        Ok(CpsResponse::OffsetCompensation(-10))
+    );
+    let date = decode_cps_response(
+        &CpsRequest::RequestFactoryCalibrationDate,
+        &[0xE7, 0x07, 0x03, 0x0F, 0x0C, 0x1E, 0x00],
+    )
+    .unwrap();
+    assert_eq!(date.to_string(), "factory calibration 2023-03-15 12:30:00");
+    assert!(decode_cps_response(&CpsRequest::RequestChainLength, &[0x01]).is_err());
+}
+
+#[test]
+fn parses_vector_with_force_array() {
+    // flags 0x17: crank data, first angle, force array, tangential
+    let buf = [
+        0x17, 0x5A, 0x00, 0x00, 0x04, 0x2D, 0x00, 0x64, 0x00, 0xC8, 0x00, 0xF6, 0xFF,
+    ];
+    let vector = parse_cps_vector(&buf).unwrap();
+    assert_eq!(vector.first_crank_measurement_angle, Some(45));
+    assert_eq!(vector.force_magnitudes, vec![100, 200, -10]);
+    assert_eq!(vector.direction, MeasurementDirection::Tangential);
+    assert_eq!(
+        vector.crank_revolution_data,
+        Some(CrankRevolutionData {
+            cumulative_revolutions: 90,
+            last_event_time: 1024,
+        })
+    );
+    // odd byte left over in the array
+    assert!(parse_cps_vector(&[0x04, 0x64, 0x00, 0x01]).is_err());
+}
+
+#[test]
+fn pedal_stroke_spreads_samples_between_vectors() {
+    let vector = |angle, forces: Vec<i16>| CpsVector {
+        flags: CpsVectorFlag(0x06),
+        crank_revolution_data: None,
+        first_crank_measurement_angle: Some(angle),
+        force_magnitudes: forces,
+        torque_magnitudes: Vec::new(),
+        direction: MeasurementDirection::Unknown,
+    };
+    let mut stroke = PedalStroke::default();
+    stroke.update(&vector(0, vec![100, 300]));
+    assert!(stroke.is_empty()); // placed once the next angle is known
+    stroke.update(&vector(180, vec![50]));
+    let bins: Vec<(f32, f32)> = stroke.bins().collect();
+    assert_eq!(bins, vec![(5.0, 100.0), (95.0, 300.0)]);
+}
+
+#[test]
+fn pedal_dynamics_from_accumulated_torque_and_extremes() {
+    let measurement = |accumulated_torque, revolutions| {
+        let mut measurement = parse_cps_measurement(&[0x00, 0x00, 0xC8, 0x00]).unwrap();
+        // balance referenced to the left pedal, crank based torque
+        measurement.flags = CpsFlag(0x00AF);
+        measurement.pedal_power_balance = Some(52.0);
+        measurement.accumulated_torque = Some(accumulated_torque);
+        measurement.crank_revolution_data = Some(CrankRevolutionData {
+            cumulative_revolutions: revolutions,
+            last_event_time: 0,
+        });
+        measurement.maximum_torque_magnitude = Some(60.0);
+        measurement.minimum_torque_magnitude = Some(-10.0);
+        measurement
+    };
+    let mut calculator = PedalDynamicsCalculator::default();
+    let first = calculator.update(&measurement(2040.0, 10), 0.0);
+    assert_eq!(first.left_balance, Some(52.0));
+    assert_eq!(first.right_balance(), Some(48.0));
+    // no average torque yet
+    assert_eq!(first.pedal_smoothness, None);
+
+    // two revolutions at 20 Nm, across the rollover
+    let second = calculator.update(&measurement(32.0, 12), 0.0);
+    assert_eq!(second.pedal_smoothness, Some(20.0 / 60.0 * 100.0));
+    let effectiveness = second.torque_effectiveness.unwrap();
+    assert!(effectiveness > 80.0 && effectiveness < 100.0);
+    assert_eq!(torque_effectiveness(50.0, 10.0), Some(100.0));
+    // as much pulled back as pushed, nothing left over
+    assert_eq!(torque_effectiveness(40.0, -40.0), None);
+}
@@ -1,7 +1,17 @@
 /*=======================================================================
  * IMPORTS
  * ====================================================================*/
+// local files
+use crate::bluetooth::cps::{CpsMeasurement, CrankRevolutionData, WheelRevolutionData};
+use crate::bluetooth::{
+    control_point_request, read_characteristic, BleParseError, BleRequestError, ByteReader,
+    ControlPointResult, CONTROL_POINT_TIMEOUT,
+};
+
 // external crates
+use btleplug::platform::Peripheral;
+use proc_bitfield::{self, bitfield};
+use std::time::{Duration, Instant};
 use uuid::{uuid, Uuid};
 
 /*=======================================================================
@@ -9,3 +19,512 @@ use uuid::{uuid, Uuid};
  * ====================================================================*/
 pub const CSC_MEASUREMENT: Uuid = uuid!("00002a5b-0000-1000-8000-00805f9b34fb");
 pub const CSC_FEATURE: Uuid = uuid!("00002a5c-0000-1000-8000-00805f9b34fb");
+pub const SC_CONTROL_POINT: Uuid = uuid!("00002a55-0000-1000-8000-00805f9b34fb");
+// also used by the Cycling Power Service
+pub const SENSOR_LOCATION: Uuid = uuid!("00002a5d-0000-1000-8000-00805f9b34fb");
+
+// event times are 1/1024 s except CPS wheel events which are 1/2048 s
+pub const CRANK_EVENT_TICKS_PER_SECOND: f32 = 1024.0;
+pub const CPS_WHEEL_EVENT_TICKS_PER_SECOND: f32 = 2048.0;
+pub const CSC_WHEEL_EVENT_TICKS_PER_SECOND: f32 = 1024.0;
+/// 700x25c road tyre, in meters
+pub const DEFAULT_WHEEL_CIRCUMFERENCE: f32 = 2.105;
+/// how long without a new revolution before we assume the rider stopped
+pub const REVOLUTION_TIMEOUT: Duration = Duration::from_secs(3);
+
+/*=======================================================================
+ * ENUMS
+ * ====================================================================*/
+/// Sensor Location characteristic values (GATT assigned numbers)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SensorLocation {
+    Other,
+    TopOfShoe,
+    InShoe,
+    Hip,
+    FrontWheel,
+    LeftCrank,
+    RightCrank,
+    LeftPedal,
+    RightPedal,
+    FrontHub,
+    RearDropout,
+    Chainstay,
+    RearWheel,
+    RearHub,
+    Chest,
+    Spider,
+    ChainRing,
+    Reserved(u8),
+}
+
+impl SensorLocation {
+    pub fn from_u8(value: u8) -> Self {
+        match value {
+            0 => SensorLocation::Other,
+            1 => SensorLocation::TopOfShoe,
+            2 => SensorLocation::InShoe,
+            3 => SensorLocation::Hip,
+            4 => SensorLocation::FrontWheel,
+            5 => SensorLocation::LeftCrank,
+            6 => SensorLocation::RightCrank,
+            7 => SensorLocation::LeftPedal,
+            8 => SensorLocation::RightPedal,
+            9 => SensorLocation::FrontHub,
+            10 => SensorLocation::RearDropout,
+            11 => SensorLocation::Chainstay,
+            12 => SensorLocation::RearWheel,
+            13 => SensorLocation::RearHub,
+            14 => SensorLocation::Chest,
+            15 => SensorLocation::Spider,
+            16 => SensorLocation::ChainRing,
+            other => SensorLocation::Reserved(other),
+        }
+    }
+
+    pub fn to_u8(self) -> u8 {
+        match self {
+            SensorLocation::Other => 0,
+            SensorLocation::TopOfShoe => 1,
+            SensorLocation::InShoe => 2,
+            SensorLocation::Hip => 3,
+            SensorLocation::FrontWheel => 4,
+            SensorLocation::LeftCrank => 5,
+            SensorLocation::RightCrank => 6,
+            SensorLocation::LeftPedal => 7,
+            SensorLocation::RightPedal => 8,
+            SensorLocation::FrontHub => 9,
+            SensorLocation::RearDropout => 10,
+            SensorLocation::Chainstay => 11,
+            SensorLocation::RearWheel => 12,
+            SensorLocation::RearHub => 13,
+            SensorLocation::Chest => 14,
+            SensorLocation::Spider => 15,
+            SensorLocation::ChainRing => 16,
+            SensorLocation::Reserved(value) => value,
+        }
+    }
+}
+
+/// SC Control Point op codes [https://www.bluetooth.com/specifications/specs/cycling-speed-and-cadence-service-1-0/] (Section 3.4)
+#[allow(dead_code)] // full table from the spec, not every procedure is used yet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScOpCode {
+    SetCumulativeValue = 0x01,
+    StartSensorCalibration = 0x02,
+    UpdateSensorLocation = 0x03,
+    RequestSupportedSensorLocations = 0x04,
+    ResponseCode = 0x10,
+}
+
+/*=======================================================================
+ * STRUCTS
+ * ====================================================================*/
+// CSC Feature [https://www.bluetooth.com/specifications/specs/cycling-speed-and-cadence-service-1-0/] (Section 3.2)
+bitfield! {
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    pub struct CscFeature(pub u16): Debug {
+        pub wheel_revolution_data_supported: bool @ 0,
+        pub crank_revolution_data_supported: bool @ 1,
+        pub multiple_sensor_locations_supported: bool @ 2,
+    }
+}
+
+// CSC Measurement flags (Section 3.1)
+bitfield! {
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    pub struct CscFlag(pub u8): Debug {
+        pub wheel_revolution_data_present: bool @ 0,
+        pub crank_revolution_data_present: bool @ 1,
+    }
+}
+
+/// decoded CSC Measurement notification
+/// wheel event time is 1/1024 s here, not 1/2048 s like CPS
+#[derive(Debug, Clone, PartialEq)]
+pub struct CscMeasurement {
+    pub flags: CscFlag,
+    pub wheel_revolution_data: Option<WheelRevolutionData>,
+    pub crank_revolution_data: Option<CrankRevolutionData>,
+}
+
+/// decoded SC Control Point response indication
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScControlPointResponse {
+    pub request_op_code: u8,
+    pub result: ControlPointResult,
+    pub parameter: Vec<u8>,
+}
+
+/// turns consecutive (cumulative revolutions, last event time) samples into revolutions per minute
+/// handles counter and timestamp rollover, repeated events and a timeout back to zero
+#[derive(Debug, Clone)]
+pub struct RevolutionRate {
+    counter_modulus: u64,
+    ticks_per_second: f32,
+    max_rpm: f32,
+    timeout: Duration,
+    last_sample: Option<(u32, u16)>,
+    last_event_at: Option<Instant>,
+    rpm: f32,
+}
+
+impl RevolutionRate {
+    /// counter_bits is the width of the cumulative counter (16 for crank, 32 for wheel)
+    pub fn new(counter_bits: u32, ticks_per_second: f32, max_rpm: f32) -> Self {
+        Self {
+            counter_modulus: 1u64 << counter_bits,
+            ticks_per_second,
+            max_rpm,
+            timeout: REVOLUTION_TIMEOUT,
+            last_sample: None,
+            last_event_at: None,
+            rpm: 0.0,
+        }
+    }
+
+    /// feeds in a new sample, returns the current rate
+    pub fn update(&mut self, revolutions: u32, event_time: u16, now: Instant) -> f32 {
+        if let Some((last_revolutions, last_time)) = self.last_sample {
+            // wrapping math takes care of 16 bit timestamp rollover (every 64 s)
+            let delta_ticks = event_time.wrapping_sub(last_time);
+            let delta_revolutions = (revolutions as u64 + self.counter_modulus
+                - last_revolutions as u64)
+                % self.counter_modulus;
+            if delta_ticks == 0 {
+                // same event repeated while coasting, keep the old rate until it times out
+                return self.rpm(now);
+            }
+            let rpm = delta_revolutions as f32 * 60.0 * self.ticks_per_second / delta_ticks as f32;
+            if rpm <= self.max_rpm {
+                self.rpm = rpm;
+                self.last_event_at = Some(now);
+            } else {
+                // counter reset (sensor reboot, reconnect), start over from this sample
+                self.rpm = 0.0;
+            }
+        }
+        self.last_sample = Some((revolutions, event_time));
+        self.rpm(now)
+    }
+
+    /// current rate, zero if nothing new has happened within the timeout
+    pub fn rpm(&self, now: Instant) -> f32 {
+        match self.last_event_at {
+            Some(event_at) if now.duration_since(event_at) <= self.timeout => self.rpm,
+            _ => 0.0,
+        }
+    }
+
+    /// forget the previous sample, e.g. after switching sensors
+    pub fn reset(&mut self) {
+        self.last_sample = None;
+        self.last_event_at = None;
+        self.rpm = 0.0;
+    }
+}
+
+/// crank cadence in RPM from CPS or CSC crank revolution data
+#[derive(Debug, Clone)]
+pub struct CadenceCalculator {
+    rate: RevolutionRate,
+}
+
+impl Default for CadenceCalculator {
+    fn default() -> Self {
+        Self {
+            rate: RevolutionRate::new(16, CRANK_EVENT_TICKS_PER_SECOND, 250.0),
+        }
+    }
+}
+
+impl CadenceCalculator {
+    pub fn update(&mut self, crank: CrankRevolutionData, now: Instant) -> f32 {
+        self.rate.update(
+            crank.cumulative_revolutions as u32,
+            crank.last_event_time,
+            now,
+        )
+    }
+
+    /// only uses the measurement if the flags say crank data is present
+    pub fn update_from_cps(&mut self, measurement: &CpsMeasurement, now: Instant) -> f32 {
+        if measurement.flags.crank_revolution_data_present() {
+            if let Some(crank) = measurement.crank_revolution_data {
+                return self.update(crank, now);
+            }
+        }
+        self.rate.rpm(now)
+    }
+
+    /// same as update_from_cps for a standalone cadence pod
+    pub fn update_from_csc(&mut self, measurement: &CscMeasurement, now: Instant) -> f32 {
+        if measurement.flags.crank_revolution_data_present() {
+            if let Some(crank) = measurement.crank_revolution_data {
+                return self.update(crank, now);
+            }
+        }
+        self.rate.rpm(now)
+    }
+
+    pub fn reset(&mut self) {
+        self.rate.reset();
+    }
+}
+
+/// wheel speed in km/h from CPS or CSC wheel revolution data
+/// CPS and CSC use different event time resolutions, so use the matching constructor
+#[derive(Debug, Clone)]
+pub struct SpeedCalculator {
+    rate: RevolutionRate,
+    wheel_circumference: f32, // m
+}
+
+impl SpeedCalculator {
+    pub fn for_cps(wheel_circumference: f32) -> Self {
+        Self {
+            rate: RevolutionRate::new(32, CPS_WHEEL_EVENT_TICKS_PER_SECOND, 2000.0),
+            wheel_circumference,
+        }
+    }
+
+    pub fn for_csc(wheel_circumference: f32) -> Self {
+        Self {
+            rate: RevolutionRate::new(32, CSC_WHEEL_EVENT_TICKS_PER_SECOND, 2000.0),
+            wheel_circumference,
+        }
+    }
+
+    pub fn update(&mut self, wheel: WheelRevolutionData, now: Instant) -> f32 {
+        self.rate
+            .update(wheel.cumulative_revolutions, wheel.last_event_time, now);
+        self.speed(now)
+    }
+
+    /// only uses the measurement if the flags say wheel data is present
+    pub fn update_from_cps(&mut self, measurement: &CpsMeasurement, now: Instant) -> f32 {
+        if measurement.flags.wheel_revolution_data_present() {
+            if let Some(wheel) = measurement.wheel_revolution_data {
+                return self.update(wheel, now);
+            }
+        }
+        self.speed(now)
+    }
+
+    /// same as update_from_cps for a standalone speed sensor, use for_csc
+    pub fn update_from_csc(&mut self, measurement: &CscMeasurement, now: Instant) -> f32 {
+        if measurement.flags.wheel_revolution_data_present() {
+            if let Some(wheel) = measurement.wheel_revolution_data {
+                return self.update(wheel, now);
+            }
+        }
+        self.speed(now)
+    }
+
+    pub fn speed(&self, now: Instant) -> f32 {
+        // rev/min * m/rev * 60 min/h / 1000 m/km
+        self.rate.rpm(now) * self.wheel_circumference * 60.0 / 1000.0
+    }
+
+    pub fn reset(&mut self) {
+        self.rate.reset();
+    }
+}
+
+/*=======================================================================
+ * FUNCTIONS
+ * ====================================================================*/
+pub fn parse_csc_feature(buf: &[u8]) -> Result<CscFeature, BleParseError> {
+    let mut reader = ByteReader::new("CSC Feature", buf);
+    Ok(CscFeature(reader.read_u16("feature flags")?))
+}
+
+/// decodes a CSC Measurement notification (Section 3.1)
+pub fn parse_csc_measurement(buf: &[u8]) -> Result<CscMeasurement, BleParseError> {
+    let mut reader = ByteReader::new("CSC Measurement", buf);
+    let flags = CscFlag(reader.read_u8("flags")?);
+    let mut measurement = CscMeasurement {
+        flags,
+        wheel_revolution_data: None,
+        crank_revolution_data: None,
+    };
+    if flags.wheel_revolution_data_present() {
+        measurement.wheel_revolution_data = Some(WheelRevolutionData {
+            cumulative_revolutions: reader.read_u32("cumulative wheel revolutions")?,
+            last_event_time: reader.read_u16("last wheel event time")?,
+        });
+    }
+    if flags.crank_revolution_data_present() {
+        measurement.crank_revolution_data = Some(CrankRevolutionData {
+            cumulative_revolutions: reader.read_u16("cumulative crank revolutions")?,
+            last_event_time: reader.read_u16("last crank event time")?,
+        });
+    }
+    Ok(measurement)
+}
+
+pub fn parse_sensor_location(buf: &[u8]) -> Result<SensorLocation, BleParseError> {
+    let mut reader = ByteReader::new("Sensor Location", buf);
+    Ok(SensorLocation::from_u8(reader.read_u8("sensor location")?))
+}
+
+/// decodes the Response Code indication, parameter holds anything after the result
+pub fn parse_sc_control_point_response(
+    buf: &[u8],
+) -> Result<ScControlPointResponse, BleParseError> {
+    let mut reader = ByteReader::new("SC Control Point", buf);
+    reader.read_u8("response op code")?;
+    let request_op_code = reader.read_u8("request op code")?;
+    let result = ControlPointResult::from_u8(reader.read_u8("response value")?);
+    Ok(ScControlPointResponse {
+        request_op_code,
+        result,
+        parameter: buf[3..].to_vec(),
+    })
+}
+
+pub async fn read_csc_feature(peripheral: &Peripheral) -> Result<CscFeature, BleRequestError> {
+    let buf = read_characteristic(peripheral, CSC_FEATURE).await?;
+    Ok(parse_csc_feature(&buf)?)
+}
+
+pub async fn read_sensor_location(
+    peripheral: &Peripheral,
+) -> Result<SensorLocation, BleRequestError> {
+    let buf = read_characteristic(peripheral, SENSOR_LOCATION).await?;
+    Ok(parse_sensor_location(&buf)?)
+}
+
+/// Set Cumulative Value procedure, resets the wheel revolution counter to value
+pub async fn set_cumulative_wheel_revolutions(
+    peripheral: &Peripheral,
+    value: u32,
+) -> Result<(), BleRequestError> {
+    let mut request = vec![ScOpCode::SetCumulativeValue as u8];
+    request.extend_from_slice(&value.to_le_bytes());
+    let buf = control_point_request(
+        peripheral,
+        SC_CONTROL_POINT,
+        &request,
+        ScOpCode::ResponseCode as u8,
+        CONTROL_POINT_TIMEOUT,
+    )
+    .await?;
+    let response = parse_sc_control_point_response(&buf)?;
+    match response.result {
+        ControlPointResult::Success => Ok(()),
+        other => Err(BleRequestError::Rejected(other)),
+    }
+}
+
+#[test]
+fn cadence_from_consecutive_crank_events() {
+    let start = Instant::now();
+    let mut cadence = CadenceCalculator::default();
+    let crank = |revolutions, time| CrankRevolutionData {
+        cumulative_revolutions: revolutions,
+        last_event_time: time,
+    };
+    assert_eq!(cadence.update(crank(10, 0), start), 0.0);
+    // 2 revolutions in 1 s -> 120 rpm
+    let rpm = cadence.update(crank(12, 1024), start + Duration::from_secs(1));
+    assert_eq!(rpm, 120.0);
+}
+
+#[test]
+fn cadence_handles_counter_and_timestamp_rollover() {
+    let start = Instant::now();
+    let mut cadence = CadenceCalculator::default();
+    let crank = |revolutions, time| CrankRevolutionData {
+        cumulative_revolutions: revolutions,
+        last_event_time: time,
+    };
+    cadence.update(crank(65535, 65024), start);
+    // counter 65535 -> 1 and time 65024 -> 512 are both a wrap, 2 revs in 1 s
+    let rpm = cadence.update(crank(1, 512), start + Duration::from_secs(1));
+    assert_eq!(rpm, 120.0);
+}
+
+#[test]
+fn cadence_holds_on_duplicate_event_then_times_out() {
+    let start = Instant::now();
+    let mut cadence = CadenceCalculator::default();
+    let crank = |revolutions, time| CrankRevolutionData {
+        cumulative_revolutions: revolutions,
+        last_event_time: time,
+    };
+    cadence.update(crank(0, 0), start);
+    cadence.update(crank(1, 683), start + Duration::from_millis(700));
+    // coasting: the sensor keeps repeating the last event
+    let held = cadence.update(crank(1, 683), start + Duration::from_secs(2));
+    assert!((held - 89.96).abs() < 0.01);
+    let stopped = cadence.update(crank(1, 683), start + Duration::from_secs(5));
+    assert_eq!(stopped, 0.0);
+}
+
+#[test]
+fn speed_uses_wheel_circumference() {
+    let start = Instant::now();
+    let mut speed = SpeedCalculator::for_cps(2.0);
+    let wheel = |revolutions, time| WheelRevolutionData {
+        cumulative_revolutions: revolutions,
+        last_event_time: time,
+    };
+    speed.update(wheel(100, 0), start);
+    // 5 revs of 2 m in 1 s (2048 ticks) -> 10 m/s -> 36 km/h
+    let kmh = speed.update(wheel(105, 2048), start + Duration::from_secs(1));
+    assert!((kmh - 36.0).abs() < 0.001);
+}
+
+#[test]
+fn parses_csc_measurement_from_cadence_pod() {
+    // crank only: 1234 revolutions at 0x1A2B
+    let measurement = parse_csc_measurement(&[0x02, 0xD2, 0x04, 0x2B, 0x1A]).unwrap();
+    assert_eq!(measurement.wheel_revolution_data, None);
+    assert_eq!(
+        measurement.crank_revolution_data,
+        Some(CrankRevolutionData {
+            cumulative_revolutions: 1234,
+            last_event_time: 0x1A2B,
+        })
+    );
+}
+
+#[test]
+fn parses_csc_measurement_with_wheel_and_crank() {
+    let buf = [
+        0x03, 0x10, 0x27, 0x00, 0x00, 0x00, 0x04, 0x5A, 0x00, 0x00, 0x08,
+    ];
+    let measurement = parse_csc_measurement(&buf).unwrap();
+    assert_eq!(
+        measurement.wheel_revolution_data,
+        Some(WheelRevolutionData {
+            cumulative_revolutions: 10000,
+            last_event_time: 1024,
+        })
+    );
+    assert_eq!(
+        measurement
+            .crank_revolution_data
+            .unwrap()
+            .cumulative_revolutions,
+        90
+    );
+    assert!(parse_csc_measurement(&buf[..9]).is_err());
+}
+
+#[test]
+fn parses_csc_feature_and_control_point_response() {
+    let feature = parse_csc_feature(&[0x07, 0x00]).unwrap();
+    assert!(feature.wheel_revolution_data_supported());
+    assert!(feature.crank_revolution_data_supported());
+    assert!(feature.multiple_sensor_locations_supported());
+
+    let response = parse_sc_control_point_response(&[0x10, 0x01, 0x03]).unwrap();
+    assert_eq!(response.request_op_code, ScOpCode::SetCumulativeValue as u8);
+    assert_eq!(response.result, ControlPointResult::InvalidParameter);
+    assert_eq!(
+        parse_sensor_location(&[0x06]),
+        Ok(SensorLocation::RightCrank)
+    );
+}
@@ -0,0 +1,159 @@
+// Device Information Service strings and the Battery Service level, read for every connected
+// peripheral so firmware versions and flat batteries are known before a long session.
+
+/*=======================================================================
+ * IMPORTS
+ * ====================================================================*/
+// local files
+use crate::bluetooth::ble_default_services::create_ble_uuid;
+use crate::bluetooth::session::DeviceId;
+use crate::bluetooth::{
+    find_characteristic, read_characteristic, spawn_notification_thread, BleParseError,
+    BleRequestError, ByteReader,
+};
+
+// external crates
+use async_std::task;
+use btleplug::api::{CharPropFlags, Peripheral as Peripheral_api};
+use btleplug::platform::Peripheral;
+use std::sync::mpsc::Sender;
+use uuid::Uuid;
+
+/*=======================================================================
+ * CONSTANTS
+ * ====================================================================*/
+pub const MODEL_NUMBER: Uuid = create_ble_uuid(0x2A24);
+pub const SERIAL_NUMBER: Uuid = create_ble_uuid(0x2A25);
+pub const FIRMWARE_REVISION: Uuid = create_ble_uuid(0x2A26);
+pub const HARDWARE_REVISION: Uuid = create_ble_uuid(0x2A27);
+pub const MANUFACTURER_NAME: Uuid = create_ble_uuid(0x2A29);
+pub const BATTERY_LEVEL: Uuid = create_ble_uuid(0x2A19);
+
+/// % at or below which the rider is warned
+pub const LOW_BATTERY_LEVEL: u8 = 20;
+
+/*=======================================================================
+ * STRUCTS
+ * ====================================================================*/
+/// Device Information Service strings, None for the ones the device doesn't have
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeviceInformation {
+    pub manufacturer: Option<String>,
+    pub model: Option<String>,
+    pub serial: Option<String>,
+    pub firmware_revision: Option<String>,
+    pub hardware_revision: Option<String>,
+}
+
+impl DeviceInformation {
+    /// label and value of every field, for the details panel
+    pub fn fields(&self) -> [(&'static str, &Option<String>); 5] {
+        [
+            ("Manufacturer", &self.manufacturer),
+            ("Model", &self.model),
+            ("Serial", &self.serial),
+            ("Firmware", &self.firmware_revision),
+            ("Hardware", &self.hardware_revision),
+        ]
+    }
+}
+
+/// battery notification from any connected device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatteryLevel {
+    pub device: DeviceId,
+    pub level: u8, // %
+}
+
+/// what the details panel shows for one device
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeviceDetails {
+    pub information: DeviceInformation,
+    pub battery_level: Option<u8>, // %
+    pub low_battery_warned: bool,  // so a draining battery is only reported once
+}
+
+impl DeviceDetails {
+    pub fn is_low_battery(&self) -> bool {
+        self.battery_level
+            .is_some_and(|level| level <= LOW_BATTERY_LEVEL)
+    }
+}
+
+/*=======================================================================
+ * FUNCTIONS
+ * ====================================================================*/
+/// DIS strings are UTF-8, some devices pad them with NULs
+pub fn parse_dis_string(buf: &[u8]) -> String {
+    String::from_utf8_lossy(buf)
+        .trim_end_matches('\0')
+        .trim()
+        .to_string()
+}
+
+pub fn parse_battery_level(buf: &[u8]) -> Result<u8, BleParseError> {
+    let mut reader = ByteReader::new("Battery Level", buf);
+    let level = reader.read_u8("battery level")?;
+    if level > 100 {
+        return Err(BleParseError::Invalid {
+            characteristic: "Battery Level",
+            reason: format!("{}% is more than full", level),
+        });
+    }
+    Ok(level)
+}
+
+/// reads whatever Device Information the peripheral has, missing fields stay None
+pub async fn read_device_information(peripheral: &Peripheral) -> DeviceInformation {
+    let read = |uuid| async move {
+        read_characteristic(peripheral, uuid)
+            .await
+            .ok()
+            .map(|buf| parse_dis_string(&buf))
+    };
+    DeviceInformation {
+        manufacturer: read(MANUFACTURER_NAME).await,
+        model: read(MODEL_NUMBER).await,
+        serial: read(SERIAL_NUMBER).await,
+        firmware_revision: read(FIRMWARE_REVISION).await,
+        hardware_revision: read(HARDWARE_REVISION).await,
+    }
+}
+
+/// reads the battery level and forwards notifications of it, None without a Battery Service
+/// called again after a reconnect, the old notification thread ends with the connection
+pub fn subscribe_battery_level(
+    peripheral: &Peripheral,
+    device: DeviceId,
+    battery_sender: Sender<BatteryLevel>,
+) -> Result<Option<u8>, BleRequestError> {
+    let Some(characteristic) = find_characteristic(peripheral, BATTERY_LEVEL) else {
+        return Ok(None);
+    };
+    let level = task::block_on(peripheral.read(&characteristic))?;
+    let level = parse_battery_level(&level)?;
+    // notify is optional, those devices are only read on connect
+    if !characteristic.properties.contains(CharPropFlags::NOTIFY) {
+        return Ok(Some(level));
+    }
+    task::block_on(peripheral.subscribe(&characteristic))?;
+    spawn_notification_thread(
+        peripheral.clone(),
+        |uuid, value| (uuid == BATTERY_LEVEL).then(|| parse_battery_level(value)),
+        move |level| battery_sender.send(BatteryLevel { device, level }).is_ok(),
+    );
+    Ok(Some(level))
+}
+
+#[test]
+fn parses_battery_level_and_padded_strings() {
+    assert_eq!(parse_battery_level(&[0x55]), Ok(85));
+    assert!(parse_battery_level(&[101]).is_err());
+    assert!(parse_battery_level(&[]).is_err());
+    assert_eq!(parse_dis_string(b"4.2.1\0\0"), "4.2.1");
+    let details = DeviceDetails {
+        battery_level: Some(LOW_BATTERY_LEVEL),
+        ..Default::default()
+    };
+    assert!(details.is_low_battery());
+}
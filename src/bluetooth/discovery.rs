@@ -0,0 +1,186 @@
+// Live device discovery: every advertisement updates the device list as it arrives,
+// instead of scanning blind for a fixed time and returning whatever was found.
+
+/*=======================================================================
+ * IMPORTS
+ * ====================================================================*/
+// local files
+use crate::bluetooth::ble_default_services::create_ble_uuid;
+use crate::bluetooth::classify::{classify, Classification};
+
+// external crates
+use async_std::stream::StreamExt;
+use btleplug::api::{Central, CentralEvent, Peripheral as Peripheral_api, ScanFilter};
+use btleplug::platform::{Adapter, Peripheral, PeripheralId};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+use uuid::{uuid, Uuid};
+
+/*=======================================================================
+ * CONSTANTS
+ * ====================================================================*/
+pub const HEART_RATE_SERVICE: Uuid = create_ble_uuid(0x180D);
+pub const CSC_SERVICE: Uuid = create_ble_uuid(0x1816);
+pub const CPS_SERVICE: Uuid = create_ble_uuid(0x1818);
+pub const FTMS_SERVICE: Uuid = create_ble_uuid(0x1826);
+pub const FEC_SERVICE: Uuid = uuid!("6e40fec1-b5a3-f393-e0a9-e50e24dcca9e");
+
+/// advertising any of these makes a device worth showing with the fitness filter on
+pub const FITNESS_SERVICES: [Uuid; 5] = [
+    FTMS_SERVICE,
+    CPS_SERVICE,
+    FEC_SERVICE,
+    CSC_SERVICE,
+    HEART_RATE_SERVICE,
+];
+
+/// how often the scan thread checks whether it was stopped when nothing is advertising
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/*=======================================================================
+ * STRUCTS
+ * ====================================================================*/
+/// latest advertisement data for one peripheral
+#[derive(Debug, Clone)]
+pub struct DiscoveredDevice {
+    pub peripheral: Peripheral,
+    pub local_name: Option<String>,
+    pub rssi: Option<i16>, // dBm
+    pub services: Vec<Uuid>,
+    pub classification: Classification,
+}
+
+impl DiscoveredDevice {
+    pub fn id(&self) -> PeripheralId {
+        self.peripheral.id()
+    }
+
+    pub fn is_fitness_device(&self) -> bool {
+        is_fitness_device(&self.services)
+    }
+
+    /// "🚲 KICKR CORE 1234  -62 dBm  smart trainer (FTMS+CPS)"
+    pub fn label(&self) -> String {
+        let mut label = format!("{} ", self.classification.class.icon());
+        match &self.local_name {
+            Some(name) => label.push_str(name),
+            None => label.push_str(&self.peripheral.id().to_string()),
+        }
+        if let Some(rssi) = self.rssi {
+            label.push_str(&format!("  {} dBm", rssi));
+        }
+        label.push_str(&format!("  {}", self.classification));
+        label
+    }
+}
+
+/// scan running on its own thread, stopped when this is stopped or dropped
+pub struct Discovery {
+    running: Arc<AtomicBool>,
+}
+
+impl Discovery {
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::Relaxed)
+    }
+
+    pub fn stop(&self) {
+        self.running.store(false, Ordering::Relaxed);
+    }
+}
+
+impl Drop for Discovery {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/*=======================================================================
+ * FUNCTIONS
+ * ====================================================================*/
+pub fn is_fitness_device(services: &[Uuid]) -> bool {
+    services.iter().any(|uuid| FITNESS_SERVICES.contains(uuid))
+}
+
+/// starts scanning and sends a DiscoveredDevice for every advertisement until stopped
+pub fn start_discovery(adapter: Adapter, device_sender: Sender<DiscoveredDevice>) -> Discovery {
+    let running = Arc::new(AtomicBool::new(true));
+    let discovery = Discovery {
+        running: running.clone(),
+    };
+    thread::spawn(move || {
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async move {
+            let mut events = match adapter.events().await {
+                Ok(events) => events,
+                Err(e) => {
+                    println!("Failed to get adapter events: {:?}", e);
+                    running.store(false, Ordering::Relaxed);
+                    return;
+                }
+            };
+            if let Err(e) = adapter.start_scan(ScanFilter::default()).await {
+                println!("Error scanning for peripherals: {:?}", e);
+                running.store(false, Ordering::Relaxed);
+                return;
+            }
+            while running.load(Ordering::Relaxed) {
+                let event =
+                    match async_std::future::timeout(STOP_POLL_INTERVAL, events.next()).await {
+                        Ok(Some(event)) => event,
+                        Ok(None) => break, // adapter went away
+                        Err(_) => continue,
+                    };
+                let id = match event {
+                    CentralEvent::DeviceDiscovered(id)
+                    | CentralEvent::DeviceUpdated(id)
+                    | CentralEvent::ServicesAdvertisement { id, .. } => id,
+                    _ => continue,
+                };
+                let Ok(peripheral) = adapter.peripheral(&id).await else {
+                    continue;
+                };
+                let properties = peripheral
+                    .properties()
+                    .await
+                    .ok()
+                    .flatten()
+                    .unwrap_or_default();
+                let classification = classify(
+                    &properties.services,
+                    &properties.service_data,
+                    &properties.manufacturer_data,
+                );
+                let device = DiscoveredDevice {
+                    local_name: properties.local_name,
+                    rssi: properties.rssi,
+                    services: properties.services,
+                    classification,
+                    peripheral,
+                };
+                if device_sender.send(device).is_err() {
+                    break; // GUI is gone
+                }
+            }
+            running.store(false, Ordering::Relaxed);
+            if let Err(e) = adapter.stop_scan().await {
+                println!("Failed to stop scanning: {:?}", e);
+            }
+        });
+    });
+    discovery
+}
+
+#[test]
+fn fitness_filter_matches_trainer_and_sensor_services() {
+    assert!(is_fitness_device(&[create_ble_uuid(0x180A), FTMS_SERVICE]));
+    assert!(is_fitness_device(&[HEART_RATE_SERVICE]));
+    assert!(is_fitness_device(&[FEC_SERVICE]));
+    // a phone or headphones
+    assert!(!is_fitness_device(&[create_ble_uuid(0x180F)]));
+    assert!(!is_fitness_device(&[]));
+}
@@ -0,0 +1,638 @@
+/*=======================================================================
+ * IMPORTS
+ * ====================================================================*/
+// local files
+use crate::bluetooth::{find_characteristic, BleParseError, BleRequestError};
+
+// external crates
+use btleplug::api::{Peripheral as Peripheral_api, WriteType};
+use btleplug::platform::Peripheral;
+use uuid::{uuid, Uuid};
+
+/*=======================================================================
+ * CONSTANTS
+ * ====================================================================*/
+// Tacx tunnels ANT+ FE-C messages over a custom (Nordic UART style) service, 6e40fec1-...
+pub const FEC_READ: Uuid = uuid!("6e40fec2-b5a3-f393-e0a9-e50e24dcca9e"); // notify
+pub const FEC_WRITE: Uuid = uuid!("6e40fec3-b5a3-f393-e0a9-e50e24dcca9e");
+
+// ANT message framing
+const ANT_SYNC: u8 = 0xA4;
+const ANT_BROADCAST_DATA: u8 = 0x4E;
+const ANT_PAYLOAD_LENGTH: u8 = 0x09; // channel number + 8 data bytes
+const ANT_MESSAGE_LENGTH: usize = 13;
+/// channel number Tacx trainers expect on written messages
+pub const FEC_CHANNEL: u8 = 0x05;
+
+// data page numbers (ANT+ FE-C device profile)
+pub const PAGE_CALIBRATION: u8 = 0x01; // request and response
+pub const PAGE_CALIBRATION_PROGRESS: u8 = 0x02;
+pub const PAGE_GENERAL_FE_DATA: u8 = 0x10; // 16
+pub const PAGE_SPECIFIC_TRAINER_DATA: u8 = 0x19; // 25
+pub const PAGE_BASIC_RESISTANCE: u8 = 0x30; // 48
+pub const PAGE_TARGET_POWER: u8 = 0x31; // 49
+pub const PAGE_TRACK_RESISTANCE: u8 = 0x33; // 51
+pub const PAGE_USER_CONFIGURATION: u8 = 0x37; // 55
+
+/*=======================================================================
+ * ENUMS
+ * ====================================================================*/
+/// FE state reported in the last byte of pages 16 and 25
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeState {
+    AsleepOff,
+    Ready,
+    InUse,
+    FinishedPaused,
+    Reserved(u8),
+}
+
+impl FeState {
+    pub fn from_u8(value: u8) -> Self {
+        match value {
+            1 => FeState::AsleepOff,
+            2 => FeState::Ready,
+            3 => FeState::InUse,
+            4 => FeState::FinishedPaused,
+            other => FeState::Reserved(other),
+        }
+    }
+}
+
+/// page 2 temperature and speed conditions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CalibrationCondition {
+    NotApplicable,
+    TooLow,
+    Ok,
+    TooHigh,
+}
+
+impl CalibrationCondition {
+    /// from the two bits of the condition
+    pub fn from_u8(value: u8) -> Self {
+        match value & 0x03 {
+            1 => CalibrationCondition::TooLow,
+            2 => CalibrationCondition::Ok,
+            3 => CalibrationCondition::TooHigh,
+            _ => CalibrationCondition::NotApplicable,
+        }
+    }
+
+    fn to_u8(self) -> u8 {
+        match self {
+            CalibrationCondition::NotApplicable => 0,
+            CalibrationCondition::TooLow => 1,
+            CalibrationCondition::Ok => 2,
+            CalibrationCondition::TooHigh => 3,
+        }
+    }
+}
+
+/// page 25 target power limits flag
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TargetPowerLimits {
+    AtTargetPower,
+    SpeedTooLow,
+    SpeedTooHigh,
+    Undetermined,
+}
+
+/// an FE-C data page, decoded or ready to be encoded
+#[derive(Debug, Clone, PartialEq)]
+pub enum FecPage {
+    GeneralFeData(GeneralFeData),
+    SpecificTrainerData(SpecificTrainerData),
+    BasicResistance(f32), // % of maximum
+    TargetPower(f32),     // W
+    TrackResistance(TrackResistance),
+    UserConfiguration(UserConfiguration),
+    Calibration(Calibration),
+    CalibrationProgress(CalibrationProgress),
+    /// any page we don't decode, kept as raw bytes
+    Other(u8, [u8; 8]),
+}
+
+/*=======================================================================
+ * STRUCTS
+ * ====================================================================*/
+/// page 16
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GeneralFeData {
+    pub equipment_type: u8, // 25 = trainer
+    pub elapsed_time: f32,  // s, rolls over every 64 s
+    pub distance: u8,       // m, rolls over every 256 m
+    pub speed: f32,         // km/h
+    pub heart_rate: Option<u8>,
+    pub fe_state: FeState,
+    pub lap_toggle: bool,
+}
+
+/// page 25
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpecificTrainerData {
+    pub update_event_count: u8,
+    pub cadence: Option<u8>,              // rpm
+    pub accumulated_power: u16,           // W, rolls over
+    pub instantaneous_power: Option<u16>, // W
+    pub bicycle_power_calibration_required: bool,
+    pub resistance_calibration_required: bool,
+    pub user_configuration_required: bool,
+    pub target_power_limits: TargetPowerLimits,
+    pub fe_state: FeState,
+    pub lap_toggle: bool,
+}
+
+/// page 51, sim mode
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrackResistance {
+    pub grade: f32,       // %, -200 to 200
+    pub crr: Option<f32>, // None lets the trainer use its default (0.004)
+}
+
+/// page 55, the trainer needs this to turn grade into resistance
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UserConfiguration {
+    pub user_weight: f32,    // kg
+    pub bike_weight: f32,    // kg
+    pub wheel_diameter: f32, // m
+    pub gear_ratio: Option<f32>,
+}
+
+/// page 1, sent to request a calibration and returned with its result
+/// the flags are the requested modes going out and the successful ones coming back
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Calibration {
+    pub zero_offset: bool,
+    pub spin_down: bool,
+    pub temperature: Option<f32>, // °C
+    pub zero_offset_value: Option<u16>,
+    pub spin_down_time: Option<u16>, // ms
+}
+
+/// page 2, what the trainer is waiting for while calibrating
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CalibrationProgress {
+    pub zero_offset_pending: bool,
+    pub spin_down_pending: bool,
+    pub temperature_condition: CalibrationCondition,
+    pub speed_condition: CalibrationCondition, // TooLow until the rider is fast enough
+    pub temperature: Option<f32>,              // °C
+    pub target_speed: Option<f32>,             // km/h
+    pub target_spin_down_time: Option<u16>,    // ms
+}
+
+/*=======================================================================
+ * FUNCTIONS
+ * ====================================================================*/
+/// ANT checksum, XOR of every byte before it
+pub fn ant_checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |checksum, byte| checksum ^ byte)
+}
+
+/// wraps an 8 byte page into a broadcast data message for the FE-C write characteristic
+pub fn encode_ant_message(channel: u8, page: [u8; 8]) -> Vec<u8> {
+    let mut message = vec![ANT_SYNC, ANT_PAYLOAD_LENGTH, ANT_BROADCAST_DATA, channel];
+    message.extend_from_slice(&page);
+    message.push(ant_checksum(&message));
+    message
+}
+
+/// checks framing and checksum of a message from the FE-C read characteristic,
+/// returns the 8 byte page
+pub fn decode_ant_message(buf: &[u8]) -> Result<[u8; 8], BleParseError> {
+    let characteristic = "FE-C";
+    if buf.len() < ANT_MESSAGE_LENGTH {
+        return Err(BleParseError::Truncated {
+            characteristic,
+            field: "ANT message",
+            needed: ANT_MESSAGE_LENGTH,
+            available: buf.len(),
+        });
+    }
+    if buf[0] != ANT_SYNC || buf[1] != ANT_PAYLOAD_LENGTH || buf[2] != ANT_BROADCAST_DATA {
+        return Err(BleParseError::Invalid {
+            characteristic,
+            reason: format!("unexpected ANT header {:02X?}", &buf[..3]),
+        });
+    }
+    let checksum = ant_checksum(&buf[..ANT_MESSAGE_LENGTH - 1]);
+    if checksum != buf[ANT_MESSAGE_LENGTH - 1] {
+        return Err(BleParseError::Invalid {
+            characteristic,
+            reason: format!(
+                "checksum {:02X}, expected {:02X}",
+                buf[ANT_MESSAGE_LENGTH - 1],
+                checksum
+            ),
+        });
+    }
+    let mut page = [0u8; 8];
+    page.copy_from_slice(&buf[4..12]);
+    Ok(page)
+}
+
+/// encodes a page to its 8 data bytes, unused bytes are 0xFF (reserved)
+pub fn encode_page(page: &FecPage) -> [u8; 8] {
+    match page {
+        FecPage::BasicResistance(percent) => {
+            // resolution 0.5 %
+            let resistance = (percent * 2.0).round().clamp(0.0, 200.0) as u8;
+            [
+                PAGE_BASIC_RESISTANCE,
+                0xFF,
+                0xFF,
+                0xFF,
+                0xFF,
+                0xFF,
+                0xFF,
+                resistance,
+            ]
+        }
+        FecPage::TargetPower(power) => {
+            // resolution 0.25 W
+            let quarter_watts = (power * 4.0).round().clamp(0.0, 4000.0 * 4.0) as u16;
+            let [lsb, msb] = quarter_watts.to_le_bytes();
+            [PAGE_TARGET_POWER, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, lsb, msb]
+        }
+        FecPage::TrackResistance(resistance) => {
+            // grade is offset by 200 % with 0.01 % resolution, crr is 5e-5 units
+            let grade = ((resistance.grade.clamp(-200.0, 200.0) + 200.0) * 100.0).round() as u16;
+            let [grade_lsb, grade_msb] = grade.to_le_bytes();
+            let crr = match resistance.crr {
+                Some(crr) => (crr / 0.00005).round().clamp(0.0, 254.0) as u8,
+                None => 0xFF,
+            };
+            [
+                PAGE_TRACK_RESISTANCE,
+                0xFF,
+                0xFF,
+                0xFF,
+                0xFF,
+                grade_lsb,
+                grade_msb,
+                crr,
+            ]
+        }
+        FecPage::UserConfiguration(config) => {
+            let [weight_lsb, weight_msb] =
+                ((config.user_weight * 100.0).round() as u16).to_le_bytes();
+            // bike weight is 12 bits in 0.05 kg, split across bytes 4 and 5
+            let bike_weight = ((config.bike_weight / 0.05).round() as u16).min(0x0FFF);
+            // wheel diameter in cm plus an extra mm offset
+            let diameter_mm = (config.wheel_diameter * 1000.0).round() as u16;
+            let diameter_cm = (diameter_mm / 10).min(254) as u8;
+            let diameter_offset = (diameter_mm % 10) as u8;
+            let gear_ratio = match config.gear_ratio {
+                Some(ratio) => (ratio / 0.03).round().clamp(1.0, 255.0) as u8,
+                None => 0x00,
+            };
+            [
+                PAGE_USER_CONFIGURATION,
+                weight_lsb,
+                weight_msb,
+                0xFF,
+                diameter_offset | (((bike_weight & 0x0F) as u8) << 4),
+                (bike_weight >> 4) as u8,
+                diameter_cm,
+                gear_ratio,
+            ]
+        }
+        FecPage::GeneralFeData(data) => {
+            let [speed_lsb, speed_msb] = ((data.speed / 3.6 * 1000.0).round() as u16).to_le_bytes();
+            [
+                PAGE_GENERAL_FE_DATA,
+                data.equipment_type & 0x1F,
+                (data.elapsed_time * 4.0).round() as u8,
+                data.distance,
+                speed_lsb,
+                speed_msb,
+                data.heart_rate.unwrap_or(0xFF),
+                (fe_state_to_u8(data.fe_state) << 4) | ((data.lap_toggle as u8) << 7),
+            ]
+        }
+        FecPage::SpecificTrainerData(data) => {
+            let [accumulated_lsb, accumulated_msb] = data.accumulated_power.to_le_bytes();
+            let power = data.instantaneous_power.unwrap_or(0x0FFF) & 0x0FFF;
+            let status = (data.bicycle_power_calibration_required as u8)
+                | ((data.resistance_calibration_required as u8) << 1)
+                | ((data.user_configuration_required as u8) << 2);
+            let limits = match data.target_power_limits {
+                TargetPowerLimits::AtTargetPower => 0,
+                TargetPowerLimits::SpeedTooLow => 1,
+                TargetPowerLimits::SpeedTooHigh => 2,
+                TargetPowerLimits::Undetermined => 3,
+            };
+            [
+                PAGE_SPECIFIC_TRAINER_DATA,
+                data.update_event_count,
+                data.cadence.unwrap_or(0xFF),
+                accumulated_lsb,
+                accumulated_msb,
+                (power & 0xFF) as u8,
+                ((power >> 8) as u8) | (status << 4),
+                limits | (fe_state_to_u8(data.fe_state) << 4) | ((data.lap_toggle as u8) << 7),
+            ]
+        }
+        FecPage::Calibration(calibration) => {
+            let [offset_lsb, offset_msb] = calibration
+                .zero_offset_value
+                .unwrap_or(0xFFFF)
+                .to_le_bytes();
+            let [time_lsb, time_msb] = calibration.spin_down_time.unwrap_or(0xFFFF).to_le_bytes();
+            [
+                PAGE_CALIBRATION,
+                ((calibration.zero_offset as u8) << 6) | ((calibration.spin_down as u8) << 7),
+                encode_temperature(calibration.temperature),
+                offset_lsb,
+                offset_msb,
+                time_lsb,
+                time_msb,
+                0xFF,
+            ]
+        }
+        FecPage::CalibrationProgress(progress) => {
+            // 0.001 m/s
+            let speed = match progress.target_speed {
+                Some(speed) => (speed / 3.6 * 1000.0).round().clamp(0.0, 65534.0) as u16,
+                None => 0xFFFF,
+            };
+            let [speed_lsb, speed_msb] = speed.to_le_bytes();
+            let [time_lsb, time_msb] = progress
+                .target_spin_down_time
+                .unwrap_or(0xFFFF)
+                .to_le_bytes();
+            [
+                PAGE_CALIBRATION_PROGRESS,
+                ((progress.zero_offset_pending as u8) << 6)
+                    | ((progress.spin_down_pending as u8) << 7),
+                (progress.temperature_condition.to_u8() << 4)
+                    | (progress.speed_condition.to_u8() << 6),
+                encode_temperature(progress.temperature),
+                speed_lsb,
+                speed_msb,
+                time_lsb,
+                time_msb,
+            ]
+        }
+        FecPage::Other(_, bytes) => *bytes,
+    }
+}
+
+/// 0.5 °C offset by -25 °C, 0xFF when unknown
+fn encode_temperature(temperature: Option<f32>) -> u8 {
+    match temperature {
+        Some(temperature) => ((temperature + 25.0) * 2.0).round().clamp(0.0, 254.0) as u8,
+        None => 0xFF,
+    }
+}
+
+fn decode_temperature(value: u8) -> Option<f32> {
+    match value {
+        0xFF => None,
+        value => Some(value as f32 / 2.0 - 25.0),
+    }
+}
+
+/// 0xFFFF marks a 16 bit field as invalid
+fn decode_u16(lsb: u8, msb: u8) -> Option<u16> {
+    match u16::from_le_bytes([lsb, msb]) {
+        0xFFFF => None,
+        value => Some(value),
+    }
+}
+
+fn fe_state_to_u8(state: FeState) -> u8 {
+    match state {
+        FeState::AsleepOff => 1,
+        FeState::Ready => 2,
+        FeState::InUse => 3,
+        FeState::FinishedPaused => 4,
+        FeState::Reserved(value) => value & 0x07,
+    }
+}
+
+/// decodes the 8 data bytes of a page
+pub fn decode_page(page: [u8; 8]) -> FecPage {
+    match page[0] {
+        PAGE_GENERAL_FE_DATA => FecPage::GeneralFeData(GeneralFeData {
+            equipment_type: page[1] & 0x1F,
+            elapsed_time: page[2] as f32 / 4.0,
+            distance: page[3],
+            // 0.001 m/s -> km/h
+            speed: u16::from_le_bytes([page[4], page[5]]) as f32 * 3.6 / 1000.0,
+            heart_rate: match page[6] {
+                0xFF => None,
+                heart_rate => Some(heart_rate),
+            },
+            fe_state: FeState::from_u8((page[7] >> 4) & 0x07),
+            lap_toggle: page[7] & 0x80 != 0,
+        }),
+        PAGE_SPECIFIC_TRAINER_DATA => {
+            let power = u16::from_le_bytes([page[5], page[6] & 0x0F]);
+            let status = page[6] >> 4;
+            FecPage::SpecificTrainerData(SpecificTrainerData {
+                update_event_count: page[1],
+                cadence: match page[2] {
+                    0xFF => None,
+                    cadence => Some(cadence),
+                },
+                accumulated_power: u16::from_le_bytes([page[3], page[4]]),
+                instantaneous_power: match power {
+                    0x0FFF => None,
+                    power => Some(power),
+                },
+                bicycle_power_calibration_required: status & 0x01 != 0,
+                resistance_calibration_required: status & 0x02 != 0,
+                user_configuration_required: status & 0x04 != 0,
+                target_power_limits: match page[7] & 0x03 {
+                    0 => TargetPowerLimits::AtTargetPower,
+                    1 => TargetPowerLimits::SpeedTooLow,
+                    2 => TargetPowerLimits::SpeedTooHigh,
+                    _ => TargetPowerLimits::Undetermined,
+                },
+                fe_state: FeState::from_u8((page[7] >> 4) & 0x07),
+                lap_toggle: page[7] & 0x80 != 0,
+            })
+        }
+        PAGE_BASIC_RESISTANCE => FecPage::BasicResistance(page[7] as f32 / 2.0),
+        PAGE_TARGET_POWER => {
+            FecPage::TargetPower(u16::from_le_bytes([page[6], page[7]]) as f32 / 4.0)
+        }
+        PAGE_TRACK_RESISTANCE => FecPage::TrackResistance(TrackResistance {
+            grade: u16::from_le_bytes([page[5], page[6]]) as f32 / 100.0 - 200.0,
+            crr: match page[7] {
+                0xFF => None,
+                crr => Some(crr as f32 * 0.00005),
+            },
+        }),
+        PAGE_USER_CONFIGURATION => FecPage::UserConfiguration(UserConfiguration {
+            user_weight: u16::from_le_bytes([page[1], page[2]]) as f32 / 100.0,
+            bike_weight: (((page[5] as u16) << 4) | (page[4] >> 4) as u16) as f32 * 0.05,
+            wheel_diameter: (page[6] as f32 * 10.0 + (page[4] & 0x0F) as f32) / 1000.0,
+            gear_ratio: match page[7] {
+                0x00 => None,
+                ratio => Some(ratio as f32 * 0.03),
+            },
+        }),
+        PAGE_CALIBRATION => FecPage::Calibration(Calibration {
+            zero_offset: page[1] & 0x40 != 0,
+            spin_down: page[1] & 0x80 != 0,
+            temperature: decode_temperature(page[2]),
+            zero_offset_value: decode_u16(page[3], page[4]),
+            spin_down_time: decode_u16(page[5], page[6]),
+        }),
+        PAGE_CALIBRATION_PROGRESS => FecPage::CalibrationProgress(CalibrationProgress {
+            zero_offset_pending: page[1] & 0x40 != 0,
+            spin_down_pending: page[1] & 0x80 != 0,
+            temperature_condition: CalibrationCondition::from_u8(page[2] >> 4),
+            speed_condition: CalibrationCondition::from_u8(page[2] >> 6),
+            temperature: decode_temperature(page[3]),
+            // 0.001 m/s -> km/h
+            target_speed: decode_u16(page[4], page[5]).map(|speed| speed as f32 * 3.6 / 1000.0),
+            target_spin_down_time: decode_u16(page[6], page[7]),
+        }),
+        other => FecPage::Other(other, page),
+    }
+}
+
+/// decodes a notification from the FE-C read characteristic
+pub fn parse_fec_notification(buf: &[u8]) -> Result<FecPage, BleParseError> {
+    Ok(decode_page(decode_ant_message(buf)?))
+}
+
+/// sends a page (resistance, target power, track resistance, user configuration) to the trainer
+pub async fn fec_send_page(peripheral: &Peripheral, page: &FecPage) -> Result<(), BleRequestError> {
+    let write_char = find_characteristic(peripheral, FEC_WRITE)
+        .ok_or(BleRequestError::MissingCharacteristic(FEC_WRITE))?;
+    let message = encode_ant_message(FEC_CHANNEL, encode_page(page));
+    peripheral
+        .write(&write_char, &message, WriteType::WithResponse)
+        .await?;
+    Ok(())
+}
+
+#[test]
+fn target_power_message_has_checksum() {
+    let message = encode_ant_message(FEC_CHANNEL, encode_page(&FecPage::TargetPower(200.0)));
+    assert_eq!(
+        message,
+        vec![0xA4, 0x09, 0x4E, 0x05, 0x31, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x20, 0x03, 0x0B]
+    );
+    assert_eq!(
+        parse_fec_notification(&message),
+        Ok(FecPage::TargetPower(200.0))
+    );
+}
+
+#[test]
+fn rejects_bad_checksum_and_short_messages() {
+    let mut message = encode_ant_message(FEC_CHANNEL, encode_page(&FecPage::TargetPower(150.0)));
+    message[12] ^= 0x01;
+    assert!(matches!(
+        decode_ant_message(&message),
+        Err(BleParseError::Invalid { .. })
+    ));
+    assert!(matches!(
+        decode_ant_message(&message[..8]),
+        Err(BleParseError::Truncated { .. })
+    ));
+}
+
+#[test]
+fn decodes_general_fe_data() {
+    // trainer, 10 s, 100 m, 10 m/s, 140 bpm, distance enabled, in use
+    let page = [0x10, 0x19, 0x28, 0x64, 0x10, 0x27, 0x8C, 0x34];
+    let FecPage::GeneralFeData(data) = decode_page(page) else {
+        panic!("wrong page");
+    };
+    assert_eq!(data.equipment_type, 25);
+    assert_eq!(data.elapsed_time, 10.0);
+    assert_eq!(data.distance, 100);
+    assert!((data.speed - 36.0).abs() < 0.001);
+    assert_eq!(data.heart_rate, Some(140));
+    assert_eq!(data.fe_state, FeState::InUse);
+}
+
+#[test]
+fn decodes_specific_trainer_data() {
+    // 90 rpm, 250 W, power calibration required, at target power, in use
+    let page = [0x19, 0x05, 0x5A, 0x10, 0x27, 0xFA, 0x10, 0x30];
+    let FecPage::SpecificTrainerData(data) = decode_page(page) else {
+        panic!("wrong page");
+    };
+    assert_eq!(data.cadence, Some(90));
+    assert_eq!(data.accumulated_power, 10000);
+    assert_eq!(data.instantaneous_power, Some(250));
+    assert!(data.bicycle_power_calibration_required);
+    assert!(!data.user_configuration_required);
+    assert_eq!(data.target_power_limits, TargetPowerLimits::AtTargetPower);
+    assert_eq!(data.fe_state, FeState::InUse);
+    assert_eq!(encode_page(&FecPage::SpecificTrainerData(data)), page);
+}
+
+#[test]
+fn track_resistance_and_user_configuration_round_trip() {
+    let resistance = FecPage::TrackResistance(TrackResistance {
+        grade: -2.5,
+        crr: None,
+    });
+    // (−2.5 + 200) / 0.01 = 19750 = 0x4D26
+    assert_eq!(
+        encode_page(&resistance),
+        [0x33, 0xFF, 0xFF, 0xFF, 0xFF, 0x26, 0x4D, 0xFF]
+    );
+    assert_eq!(decode_page(encode_page(&resistance)), resistance);
+
+    let config = UserConfiguration {
+        user_weight: 75.0,
+        bike_weight: 8.5,
+        wheel_diameter: 0.672,
+        gear_ratio: None,
+    };
+    let FecPage::UserConfiguration(decoded) =
+        decode_page(encode_page(&FecPage::UserConfiguration(config)))
+    else {
+        panic!("wrong page");
+    };
+    assert_eq!(decoded.user_weight, 75.0);
+    assert!((decoded.bike_weight - 8.5).abs() < 0.001);
+    assert!((decoded.wheel_diameter - 0.672).abs() < 0.0001);
+    assert_eq!(decoded.gear_ratio, None);
+}
+
+#[test]
+fn encodes_spin_down_request_and_decodes_progress() {
+    let request = FecPage::Calibration(Calibration {
+        spin_down: true,
+        ..Default::default()
+    });
+    assert_eq!(
+        encode_page(&request),
+        [0x01, 0x80, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]
+    );
+    // spin down pending, temperature ok, speed too low, 20 °C, 10 m/s, 3 s
+    let page = [0x02, 0x80, 0x60, 0x5A, 0x10, 0x27, 0xB8, 0x0B];
+    let FecPage::CalibrationProgress(progress) = decode_page(page) else {
+        panic!("wrong page");
+    };
+    assert!(progress.spin_down_pending);
+    assert_eq!(progress.temperature_condition, CalibrationCondition::Ok);
+    assert_eq!(progress.speed_condition, CalibrationCondition::TooLow);
+    assert_eq!(progress.temperature, Some(20.0));
+    assert!((progress.target_speed.unwrap() - 36.0).abs() < 0.001);
+    assert_eq!(progress.target_spin_down_time, Some(3000));
+    assert_eq!(encode_page(&FecPage::CalibrationProgress(progress)), page);
+    // spin down succeeded in 2.5 s, no zero offset
+    let response = decode_page([0x01, 0x80, 0x5A, 0xFF, 0xFF, 0xC4, 0x09, 0xFF]);
+    assert_eq!(
+        response,
+        FecPage::Calibration(Calibration {
+            zero_offset: false,
+            spin_down: true,
+            temperature: Some(20.0),
+            zero_offset_value: None,
+            spin_down_time: Some(2500),
+        })
+    );
+}
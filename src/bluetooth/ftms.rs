@@ -0,0 +1,971 @@
+/*=======================================================================
+ * IMPORTS
+ * ====================================================================*/
+// local files
+use crate::bluetooth::{
+    control_point_request, read_characteristic, BleParseError, BleRequestError, ByteReader,
+    ControlPointResult, CONTROL_POINT_TIMEOUT,
+};
+
+// external crates
+use btleplug::platform::Peripheral;
+use proc_bitfield::{self, bitfield};
+use std::fmt;
+use uuid::{uuid, Uuid};
+
+/*=======================================================================
+ * CONSTANTS
+ * ====================================================================*/
+pub const FTMS_INDOOR_BIKE_DATA: Uuid = uuid!("00002ad2-0000-1000-8000-00805f9b34fb");
+pub const FTMS_CONTROL_POINT: Uuid = uuid!("00002ad9-0000-1000-8000-00805f9b34fb");
+pub const FTMS_FEATURE: Uuid = uuid!("00002acc-0000-1000-8000-00805f9b34fb");
+pub const FTMS_SUPPORTED_SPEED_RANGE: Uuid = uuid!("00002ad4-0000-1000-8000-00805f9b34fb");
+pub const FTMS_SUPPORTED_INCLINATION_RANGE: Uuid = uuid!("00002ad5-0000-1000-8000-00805f9b34fb");
+pub const FTMS_SUPPORTED_RESISTANCE_RANGE: Uuid = uuid!("00002ad6-0000-1000-8000-00805f9b34fb");
+pub const FTMS_SUPPORTED_POWER_RANGE: Uuid = uuid!("00002ad8-0000-1000-8000-00805f9b34fb");
+pub const FTMS_TRAINING_STATUS: Uuid = uuid!("00002ad3-0000-1000-8000-00805f9b34fb");
+pub const FTMS_MACHINE_STATUS: Uuid = uuid!("00002ada-0000-1000-8000-00805f9b34fb");
+
+/// highest Set Target Resistance Level, a u8 with 0.1 resolution
+pub const MAX_RESISTANCE_LEVEL: f32 = 25.5;
+
+/*=======================================================================
+ * ENUMS
+ * ====================================================================*/
+/// Fitness Machine Control Point op codes (Section 4.16.1)
+#[allow(dead_code)] // full table from the spec, not every procedure is used yet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FtmsOpCode {
+    RequestControl = 0x00,
+    Reset = 0x01,
+    SetTargetSpeed = 0x02,
+    SetTargetInclination = 0x03,
+    SetTargetResistanceLevel = 0x04,
+    SetTargetPower = 0x05,
+    SetTargetHeartRate = 0x06,
+    StartOrResume = 0x07,
+    StopOrPause = 0x08,
+    SetIndoorBikeSimulationParameters = 0x11,
+    SetWheelCircumference = 0x12,
+    SpinDownControl = 0x13,
+    SetTargetedCadence = 0x14,
+    ResponseCode = 0x80,
+}
+
+/// parameter of the Stop or Pause procedure
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopOrPause {
+    Stop = 0x01,
+    Pause = 0x02,
+}
+
+impl StopOrPause {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0x01 => Some(StopOrPause::Stop),
+            0x02 => Some(StopOrPause::Pause),
+            _ => None,
+        }
+    }
+}
+
+/// parameter of the Spin Down Control procedure
+#[allow(dead_code)] // ignore is only sent by apps that skip the spin down
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpinDownControl {
+    Start = 0x01,
+    Ignore = 0x02,
+}
+
+/// Spin Down Status parameter of the Fitness Machine Status (Section 4.17)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpinDownStatus {
+    Requested,
+    Success,
+    Error,
+    StopPedaling,
+}
+
+impl SpinDownStatus {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0x01 => Some(SpinDownStatus::Requested),
+            0x02 => Some(SpinDownStatus::Success),
+            0x03 => Some(SpinDownStatus::Error),
+            0x04 => Some(SpinDownStatus::StopPedaling),
+            _ => None,
+        }
+    }
+}
+
+/// Fitness Machine Status notification (Section 4.17)
+/// treadmill and heart rate zone targets are only named by their op code
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MachineStatus {
+    Reset,
+    StoppedOrPaused(StopOrPause),
+    StoppedBySafetyKey,
+    StartedOrResumed,
+    TargetSpeedChanged(f32),      // km/h
+    TargetInclineChanged(f32),    // %
+    TargetResistanceChanged(f32), // unitless
+    TargetPowerChanged(i16),      // W
+    TargetHeartRateChanged(u8),   // bpm
+    TargetCadenceChanged(f32),    // rpm
+    SimulationChanged(IndoorBikeSimulation),
+    WheelCircumferenceChanged(f32), // mm
+    SpinDown(SpinDownStatus),
+    OtherTargetChanged(u8), // op code
+    ControlPermissionLost,
+}
+
+impl MachineStatus {
+    /// the trainer echoes every target, including the ones we sent
+    pub fn is_target_change(&self) -> bool {
+        matches!(
+            self,
+            MachineStatus::TargetSpeedChanged(_)
+                | MachineStatus::TargetInclineChanged(_)
+                | MachineStatus::TargetResistanceChanged(_)
+                | MachineStatus::TargetPowerChanged(_)
+                | MachineStatus::TargetHeartRateChanged(_)
+                | MachineStatus::TargetCadenceChanged(_)
+                | MachineStatus::SimulationChanged(_)
+                | MachineStatus::WheelCircumferenceChanged(_)
+                | MachineStatus::OtherTargetChanged(_)
+        )
+    }
+}
+
+impl fmt::Display for MachineStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MachineStatus::Reset => write!(f, "Trainer was reset"),
+            MachineStatus::StoppedOrPaused(StopOrPause::Stop) => write!(f, "Trainer stopped"),
+            MachineStatus::StoppedOrPaused(StopOrPause::Pause) => write!(f, "Trainer paused"),
+            MachineStatus::StoppedBySafetyKey => write!(f, "Trainer stopped by the safety key"),
+            MachineStatus::StartedOrResumed => write!(f, "Trainer started"),
+            MachineStatus::TargetSpeedChanged(speed) => {
+                write!(f, "Trainer target speed: {:.2} km/h", speed)
+            }
+            MachineStatus::TargetInclineChanged(incline) => {
+                write!(f, "Trainer target incline: {:.1} %", incline)
+            }
+            MachineStatus::TargetResistanceChanged(level) => {
+                write!(f, "Trainer target resistance: {:.1}", level)
+            }
+            MachineStatus::TargetPowerChanged(power) => {
+                write!(f, "Trainer target power: {} W", power)
+            }
+            MachineStatus::TargetHeartRateChanged(heart_rate) => {
+                write!(f, "Trainer target heart rate: {} bpm", heart_rate)
+            }
+            MachineStatus::TargetCadenceChanged(cadence) => {
+                write!(f, "Trainer target cadence: {:.1} rpm", cadence)
+            }
+            MachineStatus::SimulationChanged(simulation) => write!(
+                f,
+                "Trainer simulation: grade {:.2} %, wind {:.1} m/s",
+                simulation.grade, simulation.wind_speed
+            ),
+            MachineStatus::WheelCircumferenceChanged(circumference) => {
+                write!(f, "Trainer wheel circumference: {:.1} mm", circumference)
+            }
+            MachineStatus::SpinDown(SpinDownStatus::Requested) => {
+                write!(f, "Trainer asks for a spin down")
+            }
+            MachineStatus::SpinDown(SpinDownStatus::Success) => write!(f, "Spin down succeeded"),
+            MachineStatus::SpinDown(SpinDownStatus::Error) => write!(f, "Spin down failed"),
+            MachineStatus::SpinDown(SpinDownStatus::StopPedaling) => {
+                write!(f, "Spin down: stop pedalling")
+            }
+            MachineStatus::OtherTargetChanged(op_code) => {
+                write!(f, "Trainer target changed (op code 0x{:02X})", op_code)
+            }
+            MachineStatus::ControlPermissionLost => {
+                write!(f, "Another app took control of the trainer")
+            }
+        }
+    }
+}
+
+/// Training Status field (Section 4.10.1.2)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrainingStatusCode {
+    Other,
+    Idle,
+    WarmingUp,
+    LowIntensityInterval,
+    HighIntensityInterval,
+    RecoveryInterval,
+    Isometric,
+    HeartRateControl,
+    FitnessTest,
+    SpeedTooLow,
+    SpeedTooHigh,
+    CoolDown,
+    WattControl,
+    ManualMode,
+    PreWorkout,
+    PostWorkout,
+}
+
+impl TrainingStatusCode {
+    pub fn from_u8(value: u8) -> Self {
+        match value {
+            0x01 => TrainingStatusCode::Idle,
+            0x02 => TrainingStatusCode::WarmingUp,
+            0x03 => TrainingStatusCode::LowIntensityInterval,
+            0x04 => TrainingStatusCode::HighIntensityInterval,
+            0x05 => TrainingStatusCode::RecoveryInterval,
+            0x06 => TrainingStatusCode::Isometric,
+            0x07 => TrainingStatusCode::HeartRateControl,
+            0x08 => TrainingStatusCode::FitnessTest,
+            0x09 => TrainingStatusCode::SpeedTooLow,
+            0x0A => TrainingStatusCode::SpeedTooHigh,
+            0x0B => TrainingStatusCode::CoolDown,
+            0x0C => TrainingStatusCode::WattControl,
+            0x0D => TrainingStatusCode::ManualMode,
+            0x0E => TrainingStatusCode::PreWorkout,
+            0x0F => TrainingStatusCode::PostWorkout,
+            _ => TrainingStatusCode::Other, // 0x00 and anything reserved
+        }
+    }
+}
+
+impl fmt::Display for TrainingStatusCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            TrainingStatusCode::Other => "other",
+            TrainingStatusCode::Idle => "idle",
+            TrainingStatusCode::WarmingUp => "warming up",
+            TrainingStatusCode::LowIntensityInterval => "low intensity interval",
+            TrainingStatusCode::HighIntensityInterval => "high intensity interval",
+            TrainingStatusCode::RecoveryInterval => "recovery interval",
+            TrainingStatusCode::Isometric => "isometric",
+            TrainingStatusCode::HeartRateControl => "heart rate control",
+            TrainingStatusCode::FitnessTest => "fitness test",
+            TrainingStatusCode::SpeedTooLow => "speed below the control region",
+            TrainingStatusCode::SpeedTooHigh => "speed above the control region",
+            TrainingStatusCode::CoolDown => "cool down",
+            TrainingStatusCode::WattControl => "watt control",
+            TrainingStatusCode::ManualMode => "manual mode",
+            TrainingStatusCode::PreWorkout => "pre-workout",
+            TrainingStatusCode::PostWorkout => "post-workout",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// status notifications of an FTMS trainer, on one channel
+#[derive(Debug, Clone, PartialEq)]
+pub enum FtmsStatus {
+    Machine(MachineStatus),
+    Training(TrainingStatus),
+}
+
+impl fmt::Display for FtmsStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FtmsStatus::Machine(status) => write!(f, "{}", status),
+            FtmsStatus::Training(status) => write!(f, "{}", status),
+        }
+    }
+}
+
+/// Set Indoor Bike Simulation Parameters (Section 4.16.2.18)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IndoorBikeSimulation {
+    pub wind_speed: f32, // m/s, positive is a headwind
+    pub grade: f32,      // %
+    pub crr: f32,        // coefficient of rolling resistance
+    pub cw: f32,         // wind resistance coefficient, kg/m
+}
+
+/// a Fitness Machine Control Point procedure with its parameter
+#[derive(Debug, Clone, PartialEq)]
+pub enum FtmsRequest {
+    RequestControl,
+    Reset,
+    SetTargetResistanceLevel(f32), // unitless, resolution 0.1
+    SetTargetPower(i16),           // W
+    StartOrResume,
+    StopOrPause(StopOrPause),
+    SetIndoorBikeSimulation(IndoorBikeSimulation),
+    SpinDownControl(SpinDownControl),
+}
+
+impl FtmsRequest {
+    pub fn op_code(&self) -> FtmsOpCode {
+        match self {
+            FtmsRequest::RequestControl => FtmsOpCode::RequestControl,
+            FtmsRequest::Reset => FtmsOpCode::Reset,
+            FtmsRequest::SetTargetResistanceLevel(_) => FtmsOpCode::SetTargetResistanceLevel,
+            FtmsRequest::SetTargetPower(_) => FtmsOpCode::SetTargetPower,
+            FtmsRequest::StartOrResume => FtmsOpCode::StartOrResume,
+            FtmsRequest::StopOrPause(_) => FtmsOpCode::StopOrPause,
+            FtmsRequest::SetIndoorBikeSimulation(_) => {
+                FtmsOpCode::SetIndoorBikeSimulationParameters
+            }
+            FtmsRequest::SpinDownControl(_) => FtmsOpCode::SpinDownControl,
+        }
+    }
+
+    /// bytes written to the control point
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = vec![self.op_code() as u8];
+        match self {
+            FtmsRequest::SetTargetResistanceLevel(level) => {
+                buf.push((level * 10.0).round().clamp(0.0, 255.0) as u8);
+            }
+            FtmsRequest::SetTargetPower(power) => buf.extend_from_slice(&power.to_le_bytes()),
+            FtmsRequest::StopOrPause(parameter) => buf.push(*parameter as u8),
+            FtmsRequest::SpinDownControl(parameter) => buf.push(*parameter as u8),
+            FtmsRequest::SetIndoorBikeSimulation(simulation) => {
+                // resolutions: wind 0.001 m/s, grade 0.01 %, crr 0.0001, cw 0.01 kg/m
+                let wind_speed = (simulation.wind_speed * 1000.0).round() as i16;
+                let grade = (simulation.grade * 100.0).round() as i16;
+                buf.extend_from_slice(&wind_speed.to_le_bytes());
+                buf.extend_from_slice(&grade.to_le_bytes());
+                buf.push((simulation.crr * 10000.0).round().clamp(0.0, 255.0) as u8);
+                buf.push((simulation.cw * 100.0).round().clamp(0.0, 255.0) as u8);
+            }
+            FtmsRequest::RequestControl | FtmsRequest::Reset | FtmsRequest::StartOrResume => {}
+        }
+        buf
+    }
+}
+
+/*=======================================================================
+ * STRUCTS
+ * ====================================================================*/
+// Indoor Bike Data [https://www.bluetooth.com/specifications/specs/fitness-machine-service-1-0/] (Section 4.9)
+// NOTE: bit 0 is inverted, instantaneous speed is present when it is NOT set
+bitfield! {
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    pub struct IndoorBikeDataFlag(pub u16): Debug {
+        pub more_data: bool @ 0,
+        pub average_speed_present: bool @ 1,
+        pub instantaneous_cadence_present: bool @ 2,
+        pub average_cadence_present: bool @ 3,
+        pub total_distance_present: bool @ 4,
+        pub resistance_level_present: bool @ 5,
+        pub instantaneous_power_present: bool @ 6,
+        pub average_power_present: bool @ 7,
+        pub expended_energy_present: bool @ 8,
+        pub heart_rate_present: bool @ 9,
+        pub metabolic_equivalent_present: bool @ 10,
+        pub elapsed_time_present: bool @ 11,
+        pub remaining_time_present: bool @ 12,
+    }
+}
+
+/// decoded Indoor Bike Data notification
+/// optional fields are only filled in when the matching flag is set
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndoorBikeData {
+    pub flags: IndoorBikeDataFlag,
+    pub instantaneous_speed: Option<f32>,   // km/h
+    pub average_speed: Option<f32>,         // km/h
+    pub instantaneous_cadence: Option<f32>, // rpm
+    pub average_cadence: Option<f32>,       // rpm
+    pub total_distance: Option<u32>,        // m
+    pub resistance_level: Option<i16>,      // unitless
+    pub instantaneous_power: Option<i16>,   // W
+    pub average_power: Option<i16>,         // W
+    pub total_energy: Option<u16>,          // kcal
+    pub energy_per_hour: Option<u16>,       // kcal
+    pub energy_per_minute: Option<u8>,      // kcal
+    pub heart_rate: Option<u8>,             // bpm
+    pub metabolic_equivalent: Option<f32>,
+    pub elapsed_time: Option<u16>,   // s
+    pub remaining_time: Option<u16>, // s
+}
+
+// Fitness Machine Features field of the Fitness Machine Feature characteristic (Section 4.3.1.1)
+bitfield! {
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    pub struct FitnessMachineFeature(pub u32): Debug {
+        pub average_speed_supported: bool @ 0,
+        pub cadence_supported: bool @ 1,
+        pub total_distance_supported: bool @ 2,
+        pub inclination_supported: bool @ 3,
+        pub elevation_gain_supported: bool @ 4,
+        pub pace_supported: bool @ 5,
+        pub step_count_supported: bool @ 6,
+        pub resistance_level_supported: bool @ 7,
+        pub stride_count_supported: bool @ 8,
+        pub expended_energy_supported: bool @ 9,
+        pub heart_rate_measurement_supported: bool @ 10,
+        pub metabolic_equivalent_supported: bool @ 11,
+        pub elapsed_time_supported: bool @ 12,
+        pub remaining_time_supported: bool @ 13,
+        pub power_measurement_supported: bool @ 14,
+        pub force_on_belt_and_power_output_supported: bool @ 15,
+        pub user_data_retention_supported: bool @ 16,
+    }
+}
+
+// Target Setting Features field of the Fitness Machine Feature characteristic (Section 4.3.1.2)
+bitfield! {
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    pub struct TargetSettingFeature(pub u32): Debug {
+        pub speed_target_supported: bool @ 0,
+        pub inclination_target_supported: bool @ 1,
+        pub resistance_target_supported: bool @ 2,
+        pub power_target_supported: bool @ 3,
+        pub heart_rate_target_supported: bool @ 4,
+        pub targeted_expended_energy_supported: bool @ 5,
+        pub targeted_step_number_supported: bool @ 6,
+        pub targeted_stride_number_supported: bool @ 7,
+        pub targeted_distance_supported: bool @ 8,
+        pub targeted_training_time_supported: bool @ 9,
+        pub targeted_two_heart_rate_zones_supported: bool @ 10,
+        pub targeted_three_heart_rate_zones_supported: bool @ 11,
+        pub targeted_five_heart_rate_zones_supported: bool @ 12,
+        pub indoor_bike_simulation_supported: bool @ 13,
+        pub wheel_circumference_supported: bool @ 14,
+        pub spin_down_control_supported: bool @ 15,
+        pub targeted_cadence_supported: bool @ 16,
+    }
+}
+
+// Training Status flags (Section 4.10.1.1)
+bitfield! {
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    pub struct TrainingStatusFlag(pub u8): Debug {
+        pub training_status_string_present: bool @ 0,
+        pub extended_string_present: bool @ 1,
+    }
+}
+
+/// Training Status notification (Section 4.10)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrainingStatus {
+    pub flags: TrainingStatusFlag,
+    pub status: TrainingStatusCode,
+    pub text: Option<String>, // some trainers describe the status
+}
+
+impl fmt::Display for TrainingStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Training status: {}", self.status)?;
+        if let Some(text) = &self.text {
+            write!(f, " ({})", text)?;
+        }
+        Ok(())
+    }
+}
+
+/// Fitness Machine Feature characteristic (Section 4.3)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FtmsFeature {
+    pub machine: FitnessMachineFeature,
+    pub target_setting: TargetSettingFeature,
+}
+
+/// one of the Supported ... Range characteristics, in the unit of the matching target
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SupportedRange {
+    pub minimum: f32,
+    pub maximum: f32,
+    pub increment: f32, // smallest step the machine takes
+}
+
+impl SupportedRange {
+    pub fn contains(&self, value: f32) -> bool {
+        (self.minimum..=self.maximum).contains(&value)
+    }
+
+    /// nearest value the machine can be set to
+    pub fn clamp(&self, value: f32) -> f32 {
+        let value = value.clamp(self.minimum, self.maximum);
+        if self.increment <= 0.0 {
+            return value;
+        }
+        let steps = ((value - self.minimum) / self.increment).round();
+        // rounding up can step past a maximum that isn't on the grid
+        (self.minimum + steps * self.increment).min(self.maximum)
+    }
+}
+
+/// ranges the machine reports, None for the ones it doesn't have
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SupportedRanges {
+    pub speed: Option<SupportedRange>,       // km/h
+    pub inclination: Option<SupportedRange>, // %
+    pub resistance: Option<SupportedRange>,  // unitless
+    pub power: Option<SupportedRange>,       // W
+}
+
+/// everything read from an FTMS trainer before targets are sent to it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FtmsCapabilities {
+    pub feature: FtmsFeature,
+    pub ranges: SupportedRanges,
+}
+
+/// decoded Fitness Machine Control Point response indication
+#[derive(Debug, Clone, PartialEq)]
+pub struct FtmsControlPointResponse {
+    pub request_op_code: u8,
+    pub result: ControlPointResult,
+    pub parameter: Vec<u8>,
+}
+
+/*=======================================================================
+ * FUNCTIONS
+ * ====================================================================*/
+/// decodes an Indoor Bike Data notification (Section 4.9.1)
+pub fn parse_indoor_bike_data(buf: &[u8]) -> Result<IndoorBikeData, BleParseError> {
+    let mut reader = ByteReader::new("Indoor Bike Data", buf);
+    let flags = IndoorBikeDataFlag(reader.read_u16("flags")?);
+
+    let mut data = IndoorBikeData {
+        flags,
+        instantaneous_speed: None,
+        average_speed: None,
+        instantaneous_cadence: None,
+        average_cadence: None,
+        total_distance: None,
+        resistance_level: None,
+        instantaneous_power: None,
+        average_power: None,
+        total_energy: None,
+        energy_per_hour: None,
+        energy_per_minute: None,
+        heart_rate: None,
+        metabolic_equivalent: None,
+        elapsed_time: None,
+        remaining_time: None,
+    };
+
+    if !flags.more_data() {
+        // resolution 0.01 km/h
+        data.instantaneous_speed = Some(reader.read_u16("instantaneous speed")? as f32 / 100.0);
+    }
+    if flags.average_speed_present() {
+        data.average_speed = Some(reader.read_u16("average speed")? as f32 / 100.0);
+    }
+    if flags.instantaneous_cadence_present() {
+        // resolution 0.5 rpm
+        data.instantaneous_cadence = Some(reader.read_u16("instantaneous cadence")? as f32 / 2.0);
+    }
+    if flags.average_cadence_present() {
+        data.average_cadence = Some(reader.read_u16("average cadence")? as f32 / 2.0);
+    }
+    if flags.total_distance_present() {
+        data.total_distance = Some(reader.read_u24("total distance")?);
+    }
+    if flags.resistance_level_present() {
+        data.resistance_level = Some(reader.read_i16("resistance level")?);
+    }
+    if flags.instantaneous_power_present() {
+        data.instantaneous_power = Some(reader.read_i16("instantaneous power")?);
+    }
+    if flags.average_power_present() {
+        data.average_power = Some(reader.read_i16("average power")?);
+    }
+    if flags.expended_energy_present() {
+        data.total_energy = Some(reader.read_u16("total energy")?);
+        data.energy_per_hour = Some(reader.read_u16("energy per hour")?);
+        data.energy_per_minute = Some(reader.read_u8("energy per minute")?);
+    }
+    if flags.heart_rate_present() {
+        data.heart_rate = Some(reader.read_u8("heart rate")?);
+    }
+    if flags.metabolic_equivalent_present() {
+        // resolution 0.1
+        data.metabolic_equivalent = Some(reader.read_u8("metabolic equivalent")? as f32 / 10.0);
+    }
+    if flags.elapsed_time_present() {
+        data.elapsed_time = Some(reader.read_u16("elapsed time")?);
+    }
+    if flags.remaining_time_present() {
+        data.remaining_time = Some(reader.read_u16("remaining time")?);
+    }
+
+    Ok(data)
+}
+
+/// decodes a Fitness Machine Status notification (Section 4.17)
+pub fn parse_machine_status(buf: &[u8]) -> Result<MachineStatus, BleParseError> {
+    let mut reader = ByteReader::new("Fitness Machine Status", buf);
+    let op_code = reader.read_u8("op code")?;
+    let invalid = |reason: String| BleParseError::Invalid {
+        characteristic: "Fitness Machine Status",
+        reason,
+    };
+    let status = match op_code {
+        0x01 => MachineStatus::Reset,
+        0x02 => {
+            let parameter = reader.read_u8("stop or pause")?;
+            MachineStatus::StoppedOrPaused(
+                StopOrPause::from_u8(parameter)
+                    .ok_or_else(|| invalid(format!("stop or pause value {}", parameter)))?,
+            )
+        }
+        0x03 => MachineStatus::StoppedBySafetyKey,
+        0x04 => MachineStatus::StartedOrResumed,
+        // resolution 0.01 km/h
+        0x05 => MachineStatus::TargetSpeedChanged(reader.read_u16("target speed")? as f32 / 100.0),
+        // resolution 0.1 %
+        0x06 => {
+            MachineStatus::TargetInclineChanged(reader.read_i16("target incline")? as f32 / 10.0)
+        }
+        // resolution 0.1
+        0x07 => MachineStatus::TargetResistanceChanged(
+            reader.read_u8("target resistance level")? as f32 / 10.0,
+        ),
+        0x08 => MachineStatus::TargetPowerChanged(reader.read_i16("target power")?),
+        0x09 => MachineStatus::TargetHeartRateChanged(reader.read_u8("target heart rate")?),
+        0x0A..=0x11 => MachineStatus::OtherTargetChanged(op_code),
+        0x12 => {
+            // same resolutions as the control point procedure
+            let wind_speed = reader.read_i16("wind speed")? as f32 / 1000.0;
+            let grade = reader.read_i16("grade")? as f32 / 100.0;
+            let crr = reader.read_u8("crr")? as f32 / 10000.0;
+            let cw = reader.read_u8("cw")? as f32 / 100.0;
+            MachineStatus::SimulationChanged(IndoorBikeSimulation {
+                wind_speed,
+                grade,
+                crr,
+                cw,
+            })
+        }
+        // resolution 0.1 mm
+        0x13 => MachineStatus::WheelCircumferenceChanged(
+            reader.read_u16("wheel circumference")? as f32 / 10.0,
+        ),
+        0x14 => {
+            let parameter = reader.read_u8("spin down status")?;
+            MachineStatus::SpinDown(
+                SpinDownStatus::from_u8(parameter)
+                    .ok_or_else(|| invalid(format!("spin down status {}", parameter)))?,
+            )
+        }
+        // resolution 0.5 rpm
+        0x15 => {
+            MachineStatus::TargetCadenceChanged(reader.read_u16("target cadence")? as f32 / 2.0)
+        }
+        0xFF => MachineStatus::ControlPermissionLost,
+        other => return Err(invalid(format!("unknown op code 0x{:02X}", other))),
+    };
+    Ok(status)
+}
+
+/// decodes a Training Status notification (Section 4.10)
+pub fn parse_training_status(buf: &[u8]) -> Result<TrainingStatus, BleParseError> {
+    let mut reader = ByteReader::new("Training Status", buf);
+    let flags = TrainingStatusFlag(reader.read_u8("flags")?);
+    let status = TrainingStatusCode::from_u8(reader.read_u8("training status")?);
+    // the string takes up the rest of the packet, the extended part arrives as a long read
+    let text = match flags.training_status_string_present() && buf.len() > 2 {
+        true => Some(
+            String::from_utf8_lossy(&buf[2..])
+                .trim_end_matches('\0')
+                .to_string(),
+        ),
+        false => None,
+    };
+    Ok(TrainingStatus {
+        flags,
+        status,
+        text,
+    })
+}
+
+/// decodes the Fitness Machine Feature characteristic (Section 4.3)
+pub fn parse_ftms_feature(buf: &[u8]) -> Result<FtmsFeature, BleParseError> {
+    let mut reader = ByteReader::new("Fitness Machine Feature", buf);
+    Ok(FtmsFeature {
+        machine: FitnessMachineFeature(reader.read_u32("fitness machine features")?),
+        target_setting: TargetSettingFeature(reader.read_u32("target setting features")?),
+    })
+}
+
+/// Supported Speed Range (Section 4.13), km/h with a resolution of 0.01
+pub fn parse_supported_speed_range(buf: &[u8]) -> Result<SupportedRange, BleParseError> {
+    let mut reader = ByteReader::new("Supported Speed Range", buf);
+    let range = SupportedRange {
+        minimum: reader.read_u16("minimum speed")? as f32 / 100.0,
+        maximum: reader.read_u16("maximum speed")? as f32 / 100.0,
+        increment: reader.read_u16("minimum increment")? as f32 / 100.0,
+    };
+    checked_range("Supported Speed Range", " km/h", range)
+}
+
+/// Supported Inclination Range (Section 4.14), % with a resolution of 0.1
+pub fn parse_supported_inclination_range(buf: &[u8]) -> Result<SupportedRange, BleParseError> {
+    let mut reader = ByteReader::new("Supported Inclination Range", buf);
+    let range = SupportedRange {
+        minimum: reader.read_i16("minimum inclination")? as f32 / 10.0,
+        maximum: reader.read_i16("maximum inclination")? as f32 / 10.0,
+        increment: reader.read_u16("minimum increment")? as f32 / 10.0,
+    };
+    checked_range("Supported Inclination Range", " %", range)
+}
+
+/// Supported Resistance Level Range (Section 4.15), unitless with a resolution of 0.1
+pub fn parse_supported_resistance_range(buf: &[u8]) -> Result<SupportedRange, BleParseError> {
+    let mut reader = ByteReader::new("Supported Resistance Level Range", buf);
+    let range = SupportedRange {
+        minimum: reader.read_i16("minimum resistance level")? as f32 / 10.0,
+        maximum: reader.read_i16("maximum resistance level")? as f32 / 10.0,
+        increment: reader.read_u16("minimum increment")? as f32 / 10.0,
+    };
+    checked_range("Supported Resistance Level Range", "", range)
+}
+
+/// Supported Power Range (Section 4.19), W
+pub fn parse_supported_power_range(buf: &[u8]) -> Result<SupportedRange, BleParseError> {
+    let mut reader = ByteReader::new("Supported Power Range", buf);
+    let range = SupportedRange {
+        minimum: reader.read_i16("minimum power")? as f32,
+        maximum: reader.read_i16("maximum power")? as f32,
+        increment: reader.read_u16("minimum increment")? as f32,
+    };
+    checked_range("Supported Power Range", " W", range)
+}
+
+/// an inverted range can't be clamped to, so it's an error like any other bad value
+fn checked_range(
+    characteristic: &'static str,
+    unit: &str,
+    range: SupportedRange,
+) -> Result<SupportedRange, BleParseError> {
+    if range.maximum < range.minimum {
+        return Err(BleParseError::Invalid {
+            characteristic,
+            reason: format!(
+                "maximum {}{} below minimum {}{}",
+                range.maximum, unit, range.minimum, unit
+            ),
+        });
+    }
+    Ok(range)
+}
+
+/// reads the feature and whichever ranges the trainer has
+/// the feature is mandatory, a range that can't be read is left out
+pub async fn read_ftms_capabilities(
+    peripheral: &Peripheral,
+) -> Result<FtmsCapabilities, BleRequestError> {
+    let feature = parse_ftms_feature(&read_characteristic(peripheral, FTMS_FEATURE).await?)?;
+    let read = |uuid, parse: fn(&[u8]) -> Result<SupportedRange, BleParseError>| async move {
+        let buf = read_characteristic(peripheral, uuid).await.ok()?;
+        parse(&buf).ok()
+    };
+    Ok(FtmsCapabilities {
+        feature,
+        ranges: SupportedRanges {
+            speed: read(FTMS_SUPPORTED_SPEED_RANGE, parse_supported_speed_range).await,
+            inclination: read(
+                FTMS_SUPPORTED_INCLINATION_RANGE,
+                parse_supported_inclination_range,
+            )
+            .await,
+            resistance: read(
+                FTMS_SUPPORTED_RESISTANCE_RANGE,
+                parse_supported_resistance_range,
+            )
+            .await,
+            power: read(FTMS_SUPPORTED_POWER_RANGE, parse_supported_power_range).await,
+        },
+    })
+}
+
+/// decodes the Response Code indication (Section 4.16.2.22)
+pub fn parse_ftms_control_point_response(
+    buf: &[u8],
+) -> Result<FtmsControlPointResponse, BleParseError> {
+    let mut reader = ByteReader::new("Fitness Machine Control Point", buf);
+    reader.read_u8("response op code")?;
+    let request_op_code = reader.read_u8("request op code")?;
+    let result = ControlPointResult::from_u8(reader.read_u8("result code")?);
+    Ok(FtmsControlPointResponse {
+        request_op_code,
+        result,
+        parameter: buf[3..].to_vec(),
+    })
+}
+
+/// target speed low and high in km/h, the response parameter of a started spin down
+pub fn parse_spin_down_response(parameter: &[u8]) -> Result<(f32, f32), BleParseError> {
+    let mut reader = ByteReader::new("Fitness Machine Control Point", parameter);
+    let low = reader.read_u16("target speed low")? as f32 / 100.0;
+    let high = reader.read_u16("target speed high")? as f32 / 100.0;
+    Ok((low, high))
+}
+
+/// runs a control point procedure and checks the trainer accepted it
+pub async fn ftms_request(
+    peripheral: &Peripheral,
+    request: &FtmsRequest,
+) -> Result<FtmsControlPointResponse, BleRequestError> {
+    let buf = control_point_request(
+        peripheral,
+        FTMS_CONTROL_POINT,
+        &request.encode(),
+        FtmsOpCode::ResponseCode as u8,
+        CONTROL_POINT_TIMEOUT,
+    )
+    .await?;
+    let response = parse_ftms_control_point_response(&buf)?;
+    match response.result {
+        ControlPointResult::Success => Ok(response),
+        other => Err(BleRequestError::Rejected(other)),
+    }
+}
+
+#[test]
+fn parses_typical_trainer_indoor_bike_data() {
+    // flags 0x0044: speed (bit 0 clear), cadence and power
+    // 32.5 km/h, 90 rpm, 215 W
+    let buf = [0x44, 0x00, 0xB2, 0x0C, 0xB4, 0x00, 0xD7, 0x00];
+    let data = parse_indoor_bike_data(&buf).unwrap();
+    assert_eq!(data.instantaneous_speed, Some(32.5));
+    assert_eq!(data.instantaneous_cadence, Some(90.0));
+    assert_eq!(data.instantaneous_power, Some(215));
+    assert_eq!(data.heart_rate, None);
+}
+
+#[test]
+fn parses_indoor_bike_data_with_more_data_and_trailing_fields() {
+    // flags 0x0B31: more data (no speed), distance, resistance, energy, heart rate, elapsed time
+    let buf = [
+        0x31, 0x0B, 0xE8, 0x03, 0x00, 0x0A, 0x00, 0x78, 0x00, 0x58, 0x02, 0x0A, 0x8C, 0x10, 0x0E,
+    ];
+    let data = parse_indoor_bike_data(&buf).unwrap();
+    assert_eq!(data.instantaneous_speed, None);
+    assert_eq!(data.total_distance, Some(1000));
+    assert_eq!(data.resistance_level, Some(10));
+    assert_eq!(data.total_energy, Some(120));
+    assert_eq!(data.energy_per_hour, Some(600));
+    assert_eq!(data.energy_per_minute, Some(10));
+    assert_eq!(data.heart_rate, Some(140));
+    assert_eq!(data.elapsed_time, Some(3600));
+}
+
+#[test]
+fn truncated_indoor_bike_data_is_an_error() {
+    // power flag set but only one byte of power
+    let buf = [0x41, 0x00, 0xD7];
+    assert!(matches!(
+        parse_indoor_bike_data(&buf),
+        Err(BleParseError::Truncated {
+            field: "instantaneous power",
+            ..
+        })
+    ));
+}
+
+#[test]
+fn encodes_erg_requests() {
+    assert_eq!(FtmsRequest::RequestControl.encode(), vec![0x00]);
+    assert_eq!(
+        FtmsRequest::SetTargetPower(250).encode(),
+        vec![0x05, 0xFA, 0x00]
+    );
+    assert_eq!(
+        FtmsRequest::SetTargetResistanceLevel(12.5).encode(),
+        vec![0x04, 0x7D]
+    );
+    assert_eq!(
+        FtmsRequest::StopOrPause(StopOrPause::Pause).encode(),
+        vec![0x08, 0x02]
+    );
+}
+
+#[test]
+fn encodes_simulation_parameters() {
+    let request = FtmsRequest::SetIndoorBikeSimulation(IndoorBikeSimulation {
+        wind_speed: -1.5,
+        grade: 4.25,
+        crr: 0.004,
+        cw: 0.51,
+    });
+    // -1500 = 0xFA24, 425 = 0x01A9, crr 40, cw 51
+    assert_eq!(
+        request.encode(),
+        vec![0x11, 0x24, 0xFA, 0xA9, 0x01, 0x28, 0x33]
+    );
+}
+
+#[test]
+fn parses_ftms_control_point_response() {
+    let response = parse_ftms_control_point_response(&[0x80, 0x05, 0x05]).unwrap();
+    assert_eq!(response.request_op_code, FtmsOpCode::SetTargetPower as u8);
+    assert_eq!(response.result, ControlPointResult::ControlNotPermitted);
+    assert!(parse_ftms_control_point_response(&[0x80, 0x05]).is_err());
+    // spin down started, speed up to between 30 and 34.5 km/h
+    assert_eq!(
+        FtmsRequest::SpinDownControl(SpinDownControl::Start).encode(),
+        vec![0x13, 0x01]
+    );
+    let response =
+        parse_ftms_control_point_response(&[0x80, 0x13, 0x01, 0xB8, 0x0B, 0x7A, 0x0D]).unwrap();
+    assert_eq!(response.result, ControlPointResult::Success);
+    assert_eq!(
+        parse_spin_down_response(&response.parameter),
+        Ok((30.0, 34.5))
+    );
+    assert!(parse_spin_down_response(&[0xB8, 0x0B]).is_err());
+}
+
+#[test]
+fn parses_feature_and_supported_ranges() {
+    // cadence, power measurement; power, resistance and simulation targets
+    let feature = parse_ftms_feature(&[0x02, 0x40, 0x00, 0x00, 0x0C, 0x20, 0x00, 0x00]).unwrap();
+    assert!(feature.machine.power_measurement_supported());
+    assert!(feature.target_setting.power_target_supported());
+    assert!(feature.target_setting.indoor_bike_simulation_supported());
+    assert!(!feature.target_setting.spin_down_control_supported());
+    assert!(parse_ftms_feature(&[0x02, 0x40, 0x00, 0x00]).is_err());
+
+    // 0-2000 W in 1 W steps, then a trainer with 5 W steps
+    let power = parse_supported_power_range(&[0x00, 0x00, 0xD0, 0x07, 0x01, 0x00]).unwrap();
+    assert_eq!(power.maximum, 2000.0);
+    assert_eq!(power.clamp(2500.0), 2000.0);
+    let coarse = parse_supported_power_range(&[0x19, 0x00, 0xE8, 0x03, 0x05, 0x00]).unwrap();
+    assert_eq!(coarse.clamp(153.0), 155.0);
+    assert_eq!(coarse.clamp(10.0), 25.0);
+    assert!(!coarse.contains(1100.0));
+    let grade = parse_supported_inclination_range(&[0x9C, 0xFF, 0xC8, 0x00, 0x05, 0x00]).unwrap();
+    assert_eq!(
+        (grade.minimum, grade.maximum, grade.increment),
+        (-10.0, 20.0, 0.5)
+    );
+    // maximum 5.0 below minimum 10.0
+    assert!(parse_supported_resistance_range(&[0x64, 0x00, 0x32, 0x00, 0x01, 0x00]).is_err());
+}
+
+#[test]
+fn parses_machine_and_training_status() {
+    assert_eq!(
+        parse_machine_status(&[0x08, 0xC8, 0x00]),
+        Ok(MachineStatus::TargetPowerChanged(200))
+    );
+    assert_eq!(
+        parse_machine_status(&[0xFF]),
+        Ok(MachineStatus::ControlPermissionLost)
+    );
+    assert_eq!(
+        parse_machine_status(&[0x02, 0x02]),
+        Ok(MachineStatus::StoppedOrPaused(StopOrPause::Pause))
+    );
+    assert_eq!(
+        parse_machine_status(&[0x14, 0x04]).unwrap().to_string(),
+        "Spin down: stop pedalling"
+    );
+    assert!(parse_machine_status(&[0x02, 0x07]).is_err());
+    assert!(parse_machine_status(&[0x08, 0xC8]).is_err());
+    assert!(parse_machine_status(&[0x12, 0, 0, 0x2C, 0x01, 40, 51])
+        .unwrap()
+        .is_target_change());
+
+    let status = parse_training_status(&[0x01, 0x0C, b'E', b'R', b'G']).unwrap();
+    assert_eq!(status.status, TrainingStatusCode::WattControl);
+    assert_eq!(status.to_string(), "Training status: watt control (ERG)");
+    assert_eq!(parse_training_status(&[0x00, 0x01]).unwrap().text, None);
+}
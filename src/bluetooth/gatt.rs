@@ -0,0 +1,187 @@
+// Helpers for the GATT explorer tab: names, properties and hex formatting for raw
+// reads/writes/notifications, for debugging trainers we don't support yet.
+
+/*=======================================================================
+ * IMPORTS
+ * ====================================================================*/
+// local files
+use crate::bluetooth::ble_default_services::{attribute_name, lookup_attribute};
+use crate::bluetooth::session::DeviceId;
+use crate::bluetooth::spawn_notification_thread;
+
+// external crates
+use btleplug::api::CharPropFlags;
+use btleplug::platform::Peripheral;
+use std::collections::{HashSet, VecDeque};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use uuid::Uuid;
+
+/*=======================================================================
+ * CONSTANTS
+ * ====================================================================*/
+/// oldest lines are dropped after this many
+pub const NOTIFICATION_LOG_LENGTH: usize = 200;
+
+/*=======================================================================
+ * STRUCTS
+ * ====================================================================*/
+/// raw notification received while the explorer is subscribed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawNotification {
+    pub device: DeviceId,
+    pub uuid: Uuid,
+    pub value: Vec<u8>,
+}
+
+/// scrolling hex dump of notifications, newest last
+#[derive(Debug, Clone)]
+pub struct NotificationLog {
+    lines: VecDeque<String>,
+    started: Instant,
+}
+
+impl Default for NotificationLog {
+    fn default() -> Self {
+        Self {
+            lines: VecDeque::new(),
+            started: Instant::now(),
+        }
+    }
+}
+
+impl NotificationLog {
+    /// device_name is whatever the GUI calls notification.device
+    pub fn push(&mut self, notification: &RawNotification, device_name: &str, at: Instant) {
+        if self.lines.len() == NOTIFICATION_LOG_LENGTH {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(format!(
+            "{:>8.3} s  {}  {}  {}",
+            at.duration_since(self.started).as_secs_f32(),
+            device_name,
+            attribute_name(notification.uuid),
+            format_value(notification.uuid, &notification.value)
+        ));
+    }
+
+    pub fn lines(&self) -> impl Iterator<Item = &String> {
+        self.lines.iter()
+    }
+
+    pub fn clear(&mut self) {
+        self.lines.clear();
+    }
+}
+
+/*=======================================================================
+ * FUNCTIONS
+ * ====================================================================*/
+/// e.g. "read, notify"
+pub fn property_labels(properties: CharPropFlags) -> String {
+    let labels = [
+        (CharPropFlags::BROADCAST, "broadcast"),
+        (CharPropFlags::READ, "read"),
+        (
+            CharPropFlags::WRITE_WITHOUT_RESPONSE,
+            "write without response",
+        ),
+        (CharPropFlags::WRITE, "write"),
+        (CharPropFlags::NOTIFY, "notify"),
+        (CharPropFlags::INDICATE, "indicate"),
+        (CharPropFlags::AUTHENTICATED_SIGNED_WRITES, "signed write"),
+        (CharPropFlags::EXTENDED_PROPERTIES, "extended properties"),
+    ];
+    labels
+        .iter()
+        .filter(|(flag, _)| properties.contains(*flag))
+        .map(|(_, label)| *label)
+        .collect::<Vec<&str>>()
+        .join(", ")
+}
+
+/// space separated upper case bytes, e.g. "05 2A 00"
+pub fn to_hex(buf: &[u8]) -> String {
+    buf.iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// hex dump, plus the decoded value when the registry knows a simple type for the uuid
+pub fn format_value(uuid: Uuid, buf: &[u8]) -> String {
+    let decoded =
+        lookup_attribute(uuid).and_then(|attribute| attribute.data_type.format_value(buf));
+    match decoded {
+        Some(decoded) => format!("{}  \"{}\"", to_hex(buf), decoded),
+        None => to_hex(buf),
+    }
+}
+
+/// accepts "05 2a00", "0x05,0x2A,0x00" and anything in between
+pub fn parse_hex(text: &str) -> Result<Vec<u8>, String> {
+    let mut digits = String::new();
+    for token in text.split(|c: char| c.is_whitespace() || c == ',') {
+        let token = token
+            .strip_prefix("0x")
+            .or(token.strip_prefix("0X"))
+            .unwrap_or(token);
+        digits.push_str(token);
+    }
+    // from_str_radix would also take a sign, "+1" isn't a byte
+    if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("\"{}\" is not hex", text));
+    }
+    if !digits.len().is_multiple_of(2) {
+        return Err(format!("odd number of hex digits in \"{}\"", text));
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&digits[i..i + 2], 16)
+                .map_err(|_| format!("\"{}\" is not a hex byte", &digits[i..i + 2]))
+        })
+        .collect()
+}
+
+/// forwards notifications for the characteristics in watched until the device disconnects
+pub fn spawn_notification_logger(
+    peripheral: Peripheral,
+    device: DeviceId,
+    watched: Arc<Mutex<HashSet<(DeviceId, Uuid)>>>,
+    notification_sender: Sender<RawNotification>,
+) {
+    spawn_notification_thread(
+        peripheral,
+        move |uuid, value| {
+            let is_watched = watched
+                .lock()
+                .map(|watched| watched.contains(&(device, uuid)))
+                .unwrap_or(false);
+            is_watched.then(|| {
+                Ok(RawNotification {
+                    device,
+                    uuid,
+                    value: value.to_vec(),
+                })
+            })
+        },
+        move |notification| notification_sender.send(notification).is_ok(),
+    );
+}
+
+#[test]
+fn hex_round_trips() {
+    assert_eq!(to_hex(&[0x05, 0x2A, 0x00]), "05 2A 00");
+    assert_eq!(parse_hex("05 2a00"), Ok(vec![0x05, 0x2A, 0x00]));
+    assert_eq!(parse_hex("0x05, 0x2A"), Ok(vec![0x05, 0x2A]));
+    assert_eq!(parse_hex(""), Ok(vec![]));
+    assert!(parse_hex("052").is_err());
+    assert!(parse_hex("zz").is_err());
+    assert!(parse_hex("+1").is_err());
+    assert_eq!(
+        property_labels(CharPropFlags::WRITE | CharPropFlags::INDICATE),
+        "write, indicate"
+    );
+}
@@ -0,0 +1,159 @@
+/*=======================================================================
+ * IMPORTS
+ * ====================================================================*/
+// local files
+use crate::bluetooth::{read_characteristic, BleParseError, BleRequestError, ByteReader};
+
+// external crates
+use btleplug::platform::Peripheral;
+use proc_bitfield::{self, bitfield};
+use std::time::Duration;
+use uuid::{uuid, Uuid};
+
+/*=======================================================================
+ * CONSTANTS
+ * ====================================================================*/
+pub const HEART_RATE_MEASUREMENT: Uuid = uuid!("00002a37-0000-1000-8000-00805f9b34fb");
+pub const BODY_SENSOR_LOCATION: Uuid = uuid!("00002a38-0000-1000-8000-00805f9b34fb");
+
+/// RR intervals are 1/1024 s
+pub const RR_INTERVAL_TICKS_PER_SECOND: f32 = 1024.0;
+/// straps send about once a second, drop the value if it goes quiet for longer than this
+pub const HEART_RATE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/*=======================================================================
+ * ENUMS
+ * ====================================================================*/
+/// Body Sensor Location characteristic values
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodySensorLocation {
+    Other,
+    Chest,
+    Wrist,
+    Finger,
+    Hand,
+    EarLobe,
+    Foot,
+    Reserved(u8),
+}
+
+impl BodySensorLocation {
+    pub fn from_u8(value: u8) -> Self {
+        match value {
+            0 => BodySensorLocation::Other,
+            1 => BodySensorLocation::Chest,
+            2 => BodySensorLocation::Wrist,
+            3 => BodySensorLocation::Finger,
+            4 => BodySensorLocation::Hand,
+            5 => BodySensorLocation::EarLobe,
+            6 => BodySensorLocation::Foot,
+            other => BodySensorLocation::Reserved(other),
+        }
+    }
+}
+
+/*=======================================================================
+ * STRUCTS
+ * ====================================================================*/
+// Heart Rate Measurement flags [https://www.bluetooth.com/specifications/specs/heart-rate-service-1-0/] (Section 3.1.1.1)
+bitfield! {
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    pub struct HeartRateFlag(pub u8): Debug {
+        pub heart_rate_is_u16: bool @ 0,
+        pub sensor_contact_detected: bool @ 1,
+        pub sensor_contact_supported: bool @ 2,
+        pub energy_expended_present: bool @ 3,
+        pub rr_interval_present: bool @ 4,
+    }
+}
+
+/// decoded Heart Rate Measurement notification
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeartRateMeasurement {
+    pub flags: HeartRateFlag,
+    pub heart_rate: u16, // bpm
+    /// None if the strap can't tell whether it's touching skin
+    pub sensor_contact: Option<bool>,
+    pub energy_expended: Option<u16>, // kJ
+    pub rr_intervals: Vec<f32>,       // s, oldest first
+}
+
+/*=======================================================================
+ * FUNCTIONS
+ * ====================================================================*/
+/// decodes a Heart Rate Measurement notification (Section 3.1)
+/// every remaining byte pair is an RR interval when the flag is set
+pub fn parse_heart_rate_measurement(buf: &[u8]) -> Result<HeartRateMeasurement, BleParseError> {
+    let mut reader = ByteReader::new("Heart Rate Measurement", buf);
+    let flags = HeartRateFlag(reader.read_u8("flags")?);
+    let heart_rate = if flags.heart_rate_is_u16() {
+        reader.read_u16("heart rate")?
+    } else {
+        reader.read_u8("heart rate")? as u16
+    };
+    let sensor_contact = match flags.sensor_contact_supported() {
+        true => Some(flags.sensor_contact_detected()),
+        false => None,
+    };
+    let energy_expended = match flags.energy_expended_present() {
+        true => Some(reader.read_u16("energy expended")?),
+        false => None,
+    };
+    let mut rr_intervals = Vec::new();
+    if flags.rr_interval_present() {
+        while reader.remaining() >= 2 {
+            rr_intervals
+                .push(reader.read_u16("rr interval")? as f32 / RR_INTERVAL_TICKS_PER_SECOND);
+        }
+    }
+    Ok(HeartRateMeasurement {
+        flags,
+        heart_rate,
+        sensor_contact,
+        energy_expended,
+        rr_intervals,
+    })
+}
+
+pub fn parse_body_sensor_location(buf: &[u8]) -> Result<BodySensorLocation, BleParseError> {
+    let mut reader = ByteReader::new("Body Sensor Location", buf);
+    Ok(BodySensorLocation::from_u8(
+        reader.read_u8("body sensor location")?,
+    ))
+}
+
+pub async fn read_body_sensor_location(
+    peripheral: &Peripheral,
+) -> Result<BodySensorLocation, BleRequestError> {
+    let buf = read_characteristic(peripheral, BODY_SENSOR_LOCATION).await?;
+    Ok(parse_body_sensor_location(&buf)?)
+}
+
+#[test]
+fn parses_8_bit_heart_rate_with_contact() {
+    // captured from a chest strap: contact supported and detected, 72 bpm
+    let measurement = parse_heart_rate_measurement(&[0x06, 0x48]).unwrap();
+    assert_eq!(measurement.heart_rate, 72);
+    assert_eq!(measurement.sensor_contact, Some(true));
+    assert_eq!(measurement.energy_expended, None);
+    assert!(measurement.rr_intervals.is_empty());
+}
+
+#[test]
+fn parses_16_bit_heart_rate_energy_and_rr_intervals() {
+    // 16 bit HR 150, no contact support, 300 kJ, RR 410/1024 and 420/1024 s
+    let buf = [0x19, 0x96, 0x00, 0x2C, 0x01, 0x9A, 0x01, 0xA4, 0x01];
+    let measurement = parse_heart_rate_measurement(&buf).unwrap();
+    assert_eq!(measurement.heart_rate, 150);
+    assert_eq!(measurement.sensor_contact, None);
+    assert_eq!(measurement.energy_expended, Some(300));
+    assert_eq!(
+        measurement.rr_intervals,
+        vec![410.0 / 1024.0, 420.0 / 1024.0]
+    );
+    assert_eq!(
+        parse_body_sensor_location(&[0x01]),
+        Ok(BodySensorLocation::Chest)
+    );
+    assert!(parse_heart_rate_measurement(&[0x01, 0x96]).is_err());
+}
@@ -0,0 +1,461 @@
+// Several peripherals connected at once (trainer, pedal power meter, cadence pod, HR strap).
+// Every sensor gets its own notification thread, and each metric is read from the device
+// the rider assigned to it, falling back to another device when that one goes quiet.
+
+/*=======================================================================
+ * IMPORTS
+ * ====================================================================*/
+// local files
+use crate::bluetooth::classify::{classify_connected, Classification};
+use crate::bluetooth::cps::{
+    parse_cps_measurement, parse_cps_vector, CpsVector, PedalDynamicsCalculator, PedalStroke,
+    CPS_POWER_MEASUREMENT, CPS_POWER_VECTOR,
+};
+use crate::bluetooth::cscs::*;
+use crate::bluetooth::fec::FecPage;
+use crate::bluetooth::hrs::*;
+use crate::bluetooth::trainer::TrainerData;
+use crate::bluetooth::{find_characteristic, spawn_notification_thread, BleRequestError};
+
+// external crates
+use async_std::task;
+use btleplug::api::Peripheral as Peripheral_api;
+use btleplug::platform::Peripheral;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
+
+/*=======================================================================
+ * CONSTANTS
+ * ====================================================================*/
+pub type DeviceId = usize;
+
+/// the controllable trainer always has this id, sensors count up from 1
+pub const TRAINER_DEVICE: DeviceId = 0;
+
+/*=======================================================================
+ * ENUMS
+ * ====================================================================*/
+/// live values that can come from more than one device
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Metric {
+    Power,
+    Cadence,
+    Speed,
+    HeartRate,
+    LeftBalance,         // % of the power from the left pedal
+    TorqueEffectiveness, // %
+    PedalSmoothness,     // %
+}
+
+impl Metric {
+    pub const ALL: [Metric; 7] = [
+        Metric::Power,
+        Metric::Cadence,
+        Metric::Speed,
+        Metric::HeartRate,
+        Metric::LeftBalance,
+        Metric::TorqueEffectiveness,
+        Metric::PedalSmoothness,
+    ];
+
+    /// how long a device can stay quiet before the fallback takes over
+    pub fn timeout(&self) -> Duration {
+        match self {
+            Metric::HeartRate => HEART_RATE_TIMEOUT,
+            _ => REVOLUTION_TIMEOUT,
+        }
+    }
+}
+
+impl fmt::Display for Metric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Metric::Power => write!(f, "Power"),
+            Metric::Cadence => write!(f, "Cadence"),
+            Metric::Speed => write!(f, "Speed"),
+            Metric::HeartRate => write!(f, "Heart rate"),
+            Metric::LeftBalance => write!(f, "L/R balance"),
+            Metric::TorqueEffectiveness => write!(f, "Torque effectiveness (estimate)"),
+            Metric::PedalSmoothness => write!(f, "Pedal smoothness"),
+        }
+    }
+}
+
+/// one decoded notification from any connected device
+/// pedal power meters send the same CPS data a CPS-only trainer does
+#[derive(Debug, Clone, PartialEq)]
+pub enum SensorData {
+    Trainer(TrainerData),
+    Csc(CscMeasurement),
+    HeartRate(HeartRateMeasurement),
+    PowerVector(CpsVector),
+}
+
+/*=======================================================================
+ * STRUCTS
+ * ====================================================================*/
+/// sensor notifications share one channel, tagged with the device they came from
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceEvent {
+    pub device: DeviceId,
+    pub data: SensorData,
+}
+
+/// turns one device's notifications into metric values
+/// every device needs its own, revolution counters can't be mixed between devices
+#[derive(Debug, Clone)]
+pub struct MetricDecoder {
+    cadence_calculator: CadenceCalculator,
+    cps_speed_calculator: SpeedCalculator,
+    csc_speed_calculator: SpeedCalculator,
+    pedal_dynamics_calculator: PedalDynamicsCalculator,
+}
+
+impl Default for MetricDecoder {
+    fn default() -> Self {
+        Self {
+            cadence_calculator: CadenceCalculator::default(),
+            cps_speed_calculator: SpeedCalculator::for_cps(DEFAULT_WHEEL_CIRCUMFERENCE),
+            csc_speed_calculator: SpeedCalculator::for_csc(DEFAULT_WHEEL_CIRCUMFERENCE),
+            pedal_dynamics_calculator: PedalDynamicsCalculator::default(),
+        }
+    }
+}
+
+impl MetricDecoder {
+    /// values carried by one notification, fields the device didn't send are left out
+    pub fn decode(&mut self, data: &SensorData, now: Instant) -> Vec<(Metric, f32)> {
+        let mut values = Vec::new();
+        match data {
+            SensorData::Trainer(TrainerData::Cps(measurement)) => {
+                values.push((Metric::Power, measurement.instantaneous_power as f32));
+                let cadence = self.cadence_calculator.update_from_cps(measurement, now);
+                if measurement.flags.crank_revolution_data_present() {
+                    values.push((Metric::Cadence, cadence));
+                }
+                let speed = self.cps_speed_calculator.update_from_cps(measurement, now);
+                if measurement.flags.wheel_revolution_data_present() {
+                    values.push((Metric::Speed, speed));
+                }
+                let pedal = self.pedal_dynamics_calculator.update(measurement, cadence);
+                for (metric, value) in [
+                    (Metric::LeftBalance, pedal.left_balance),
+                    (Metric::TorqueEffectiveness, pedal.torque_effectiveness),
+                    (Metric::PedalSmoothness, pedal.pedal_smoothness),
+                ] {
+                    if let Some(value) = value {
+                        values.push((metric, value));
+                    }
+                }
+            }
+            SensorData::Trainer(TrainerData::IndoorBike(data)) => {
+                if let Some(power) = data.instantaneous_power {
+                    values.push((Metric::Power, power as f32));
+                }
+                if let Some(cadence) = data.instantaneous_cadence {
+                    values.push((Metric::Cadence, cadence));
+                }
+                if let Some(speed) = data.instantaneous_speed {
+                    values.push((Metric::Speed, speed));
+                }
+                if let Some(heart_rate) = data.heart_rate {
+                    values.push((Metric::HeartRate, heart_rate as f32));
+                }
+            }
+            SensorData::Trainer(TrainerData::Fec(FecPage::GeneralFeData(data))) => {
+                values.push((Metric::Speed, data.speed));
+                if let Some(heart_rate) = data.heart_rate {
+                    values.push((Metric::HeartRate, heart_rate as f32));
+                }
+            }
+            SensorData::Trainer(TrainerData::Fec(FecPage::SpecificTrainerData(data))) => {
+                if let Some(power) = data.instantaneous_power {
+                    values.push((Metric::Power, power as f32));
+                }
+                if let Some(cadence) = data.cadence {
+                    values.push((Metric::Cadence, cadence as f32));
+                }
+            }
+            SensorData::Trainer(TrainerData::Fec(_)) => {}
+            SensorData::Csc(measurement) => {
+                let cadence = self.cadence_calculator.update_from_csc(measurement, now);
+                if measurement.flags.crank_revolution_data_present() {
+                    values.push((Metric::Cadence, cadence));
+                }
+                let speed = self.csc_speed_calculator.update_from_csc(measurement, now);
+                if measurement.flags.wheel_revolution_data_present() {
+                    values.push((Metric::Speed, speed));
+                }
+            }
+            SensorData::HeartRate(measurement) => {
+                // a strap that lost skin contact reports garbage, let the fallback take over
+                if measurement.sensor_contact != Some(false) {
+                    values.push((Metric::HeartRate, measurement.heart_rate as f32));
+                }
+            }
+            SensorData::PowerVector(_) => {} // drawn as the pedal stroke, no metric in it
+        }
+        values
+    }
+
+    /// old samples would produce a bogus first rate after resubscribing
+    pub fn reset(&mut self) {
+        self.cadence_calculator.reset();
+        self.cps_speed_calculator.reset();
+        self.csc_speed_calculator.reset();
+        self.pedal_dynamics_calculator.reset();
+    }
+}
+
+/// which devices a metric is read from, None lets any device that is sending fill in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SourceAssignment {
+    pub primary: Option<DeviceId>,
+    pub fallback: Option<DeviceId>,
+}
+
+/// latest value of every metric from every device, and who should be listened to
+#[derive(Debug, Clone, Default)]
+pub struct MetricSources {
+    assignments: HashMap<Metric, SourceAssignment>,
+    readings: HashMap<(DeviceId, Metric), (f32, Instant)>,
+}
+
+impl MetricSources {
+    pub fn assignment(&self, metric: Metric) -> SourceAssignment {
+        self.assignments.get(&metric).copied().unwrap_or_default()
+    }
+
+    pub fn assignment_mut(&mut self, metric: Metric) -> &mut SourceAssignment {
+        self.assignments.entry(metric).or_default()
+    }
+
+    /// makes the device primary for the metrics nobody picked a primary for yet
+    pub fn pre_assign(&mut self, device: DeviceId, metrics: &[Metric]) {
+        for metric in metrics {
+            let assignment = self.assignment_mut(*metric);
+            if assignment.primary.is_none() {
+                assignment.primary = Some(device);
+            }
+        }
+    }
+
+    pub fn update(&mut self, device: DeviceId, metric: Metric, value: f32, at: Instant) {
+        self.readings.insert((device, metric), (value, at));
+    }
+
+    /// forgets a disconnected device, assignments to it go back to automatic
+    pub fn remove_device(&mut self, device: DeviceId) {
+        self.readings.retain(|(id, _), _| *id != device);
+        for assignment in self.assignments.values_mut() {
+            if assignment.primary == Some(device) {
+                assignment.primary = None;
+            }
+            if assignment.fallback == Some(device) {
+                assignment.fallback = None;
+            }
+        }
+    }
+
+    /// the device's own latest value, None if it hasn't sent one within the metric's timeout
+    pub fn fresh(&self, device: DeviceId, metric: Metric, now: Instant) -> Option<f32> {
+        match self.readings.get(&(device, metric)) {
+            Some((value, at)) if now.duration_since(*at) <= metric.timeout() => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// primary if it is still sending, then the fallback, then any other device (trainer first)
+    pub fn current(&self, metric: Metric, now: Instant) -> Option<(DeviceId, f32)> {
+        let assignment = self.assignment(metric);
+        for device in [assignment.primary, assignment.fallback]
+            .into_iter()
+            .flatten()
+        {
+            if let Some(value) = self.fresh(device, metric, now) {
+                return Some((device, value));
+            }
+        }
+        let mut others: Vec<DeviceId> = self
+            .readings
+            .keys()
+            .filter(|(_, m)| *m == metric)
+            .map(|(device, _)| *device)
+            .collect();
+        others.sort();
+        others
+            .into_iter()
+            .find_map(|device| Some((device, self.fresh(device, metric, now)?)))
+    }
+}
+
+/// a connected sensor, its notifications arrive as DeviceEvents
+pub struct SessionDevice {
+    pub id: DeviceId,
+    pub name: String,
+    pub peripheral: Peripheral,
+    pub classification: Classification,
+    pub csc_feature: Option<CscFeature>,
+    pub csc_sensor_location: Option<SensorLocation>,
+    pub hr_sensor_location: Option<BodySensorLocation>,
+    pub heart_rate_measurement: Option<HeartRateMeasurement>, // latest, for the details
+    pub pedal_stroke: PedalStroke,
+    pub decoder: MetricDecoder,
+}
+
+/*=======================================================================
+ * FUNCTIONS
+ * ====================================================================*/
+/// connects a sensor, reads what it is and starts forwarding its notifications
+pub fn connect_sensor(
+    peripheral: Peripheral,
+    id: DeviceId,
+    name: String,
+    advertised: Option<&Classification>,
+    event_sender: Sender<DeviceEvent>,
+) -> Result<SessionDevice, BleRequestError> {
+    task::block_on(peripheral.connect())?;
+    task::block_on(peripheral.discover_services())?;
+    let mut device = SessionDevice {
+        id,
+        name,
+        peripheral: peripheral.clone(),
+        classification: classify_connected(&peripheral, advertised),
+        csc_feature: None,
+        csc_sensor_location: None,
+        hr_sensor_location: None,
+        heart_rate_measurement: None,
+        pedal_stroke: PedalStroke::default(),
+        decoder: MetricDecoder::default(),
+    };
+    subscribe_measurements(&peripheral)?;
+    if find_characteristic(&peripheral, CSC_MEASUREMENT).is_some() {
+        match task::block_on(read_csc_feature(&peripheral)) {
+            Ok(feature) => device.csc_feature = Some(feature),
+            Err(e) => println!("Failed to read CSC Feature: {}", e),
+        }
+        // sensor locations are optional, only there if the sensor can be moved around
+        device.csc_sensor_location = task::block_on(read_sensor_location(&peripheral)).ok();
+    }
+    if find_characteristic(&peripheral, HEART_RATE_MEASUREMENT).is_some() {
+        device.hr_sensor_location = task::block_on(read_body_sensor_location(&peripheral)).ok();
+    }
+    spawn_sensor_thread(peripheral, id, event_sender);
+    Ok(device)
+}
+
+/// subscribes again after the supervisor reconnected the sensor
+/// the old notification thread ended with the connection, so a new one is started
+pub fn resubscribe_sensor(
+    device: &mut SessionDevice,
+    event_sender: Sender<DeviceEvent>,
+) -> Result<(), BleRequestError> {
+    subscribe_measurements(&device.peripheral)?;
+    device.decoder.reset();
+    spawn_sensor_thread(device.peripheral.clone(), device.id, event_sender);
+    Ok(())
+}
+
+/// subscribes to every CPS, CSC and HRS measurement the device has
+/// the power vector comes along when there is one, it is no use on its own
+fn subscribe_measurements(peripheral: &Peripheral) -> Result<(), BleRequestError> {
+    let mut subscribed = false;
+    for uuid in [
+        CPS_POWER_MEASUREMENT,
+        CSC_MEASUREMENT,
+        HEART_RATE_MEASUREMENT,
+    ] {
+        if let Some(characteristic) = find_characteristic(peripheral, uuid) {
+            task::block_on(peripheral.subscribe(&characteristic))?;
+            subscribed = true;
+        }
+    }
+    if let Some(characteristic) = find_characteristic(peripheral, CPS_POWER_VECTOR) {
+        task::block_on(peripheral.subscribe(&characteristic))?;
+    }
+    match subscribed {
+        true => Ok(()),
+        false => Err(BleRequestError::NotSupported(
+            "power, cadence, speed or heart rate data",
+        )),
+    }
+}
+
+/// decodes one device's notifications until it disconnects or nobody is listening
+fn spawn_sensor_thread(peripheral: Peripheral, id: DeviceId, event_sender: Sender<DeviceEvent>) {
+    spawn_notification_thread(
+        peripheral,
+        |uuid, value| match uuid {
+            CPS_POWER_MEASUREMENT => {
+                Some(parse_cps_measurement(value).map(|m| SensorData::Trainer(TrainerData::Cps(m))))
+            }
+            CPS_POWER_VECTOR => Some(parse_cps_vector(value).map(SensorData::PowerVector)),
+            CSC_MEASUREMENT => Some(parse_csc_measurement(value).map(SensorData::Csc)),
+            HEART_RATE_MEASUREMENT => {
+                Some(parse_heart_rate_measurement(value).map(SensorData::HeartRate))
+            }
+            _ => None,
+        },
+        move |data| event_sender.send(DeviceEvent { device: id, data }).is_ok(),
+    );
+}
+
+#[test]
+fn falls_back_when_the_primary_goes_quiet() {
+    let start = Instant::now();
+    let mut sources = MetricSources::default();
+    // trainer and pedals both send power, the rider wants the pedals
+    sources.update(TRAINER_DEVICE, Metric::Power, 200.0, start);
+    sources.update(1, Metric::Power, 210.0, start);
+    assert_eq!(
+        sources.current(Metric::Power, start),
+        Some((TRAINER_DEVICE, 200.0))
+    );
+    *sources.assignment_mut(Metric::Power) = SourceAssignment {
+        primary: Some(1),
+        fallback: Some(TRAINER_DEVICE),
+    };
+    assert_eq!(sources.current(Metric::Power, start), Some((1, 210.0)));
+    // pedals stop sending, the trainer keeps going
+    let later = start + Duration::from_secs(5);
+    sources.update(TRAINER_DEVICE, Metric::Power, 190.0, later);
+    assert_eq!(
+        sources.current(Metric::Power, later),
+        Some((TRAINER_DEVICE, 190.0))
+    );
+    // nothing fresh at all
+    assert_eq!(
+        sources.current(Metric::Power, later + Duration::from_secs(5)),
+        None
+    );
+    sources.remove_device(1);
+    assert_eq!(sources.assignment(Metric::Power).primary, None);
+    // a second power meter doesn't take over from one the rider picked
+    sources.pre_assign(2, &[Metric::Power, Metric::Cadence]);
+    sources.pre_assign(3, &[Metric::Power]);
+    assert_eq!(sources.assignment(Metric::Power).primary, Some(2));
+    assert_eq!(sources.assignment(Metric::Cadence).primary, Some(2));
+}
+
+#[test]
+fn decoder_only_reports_fields_the_device_sent() {
+    let mut decoder = MetricDecoder::default();
+    let no_contact = HeartRateMeasurement {
+        flags: HeartRateFlag(0x04),
+        heart_rate: 0,
+        sensor_contact: Some(false),
+        energy_expended: None,
+        rr_intervals: Vec::new(),
+    };
+    let now = Instant::now();
+    assert!(decoder
+        .decode(&SensorData::HeartRate(no_contact), now)
+        .is_empty());
+    // crank only cadence pod
+    let csc = parse_csc_measurement(&[0x02, 0xD2, 0x04, 0x2B, 0x1A]).unwrap();
+    let values = decoder.decode(&SensorData::Csc(csc), now);
+    assert_eq!(values.len(), 1);
+    assert_eq!(values[0].0, Metric::Cadence);
+}
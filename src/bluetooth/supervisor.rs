@@ -0,0 +1,211 @@
+// Watches the adapter for disconnects of the devices we care about and reconnects them in
+// the background, so a dropout mid-workout costs a few seconds of data instead of the ride.
+
+/*=======================================================================
+ * IMPORTS
+ * ====================================================================*/
+// local files
+use crate::bluetooth::session::DeviceId;
+
+// external crates
+use async_std::stream::StreamExt;
+use async_std::task;
+use btleplug::api::{Central, CentralEvent, Peripheral as Peripheral_api};
+use btleplug::platform::{Adapter, Peripheral, PeripheralId};
+use std::collections::HashMap;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::runtime::Runtime;
+
+/*=======================================================================
+ * ENUMS
+ * ====================================================================*/
+/// sent to the GUI, which re-subscribes and restores trainer state on Reconnected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionEvent {
+    Disconnected { device: DeviceId },
+    Reconnected { device: DeviceId, gap: Duration },
+}
+
+/*=======================================================================
+ * STRUCTS
+ * ====================================================================*/
+/// exponential backoff between reconnect attempts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_millis(500),
+            max: Duration::from_secs(30),
+        }
+    }
+}
+
+impl Backoff {
+    /// wait before the given attempt, starting at 0
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.min(16));
+        self.initial.saturating_mul(factor).min(self.max)
+    }
+}
+
+struct WatchedDevice {
+    device: DeviceId,
+    peripheral: Peripheral,
+    reconnecting: bool,
+}
+
+/// devices the supervisor keeps connected, shared between the GUI and the supervisor
+/// anything the user disconnected on purpose has to be unwatched first
+#[derive(Clone, Default)]
+pub struct WatchList {
+    devices: Arc<Mutex<HashMap<PeripheralId, WatchedDevice>>>,
+}
+
+impl WatchList {
+    pub fn watch(&self, device: DeviceId, peripheral: Peripheral) {
+        self.unwatch(device);
+        if let Ok(mut devices) = self.devices.lock() {
+            devices.insert(
+                peripheral.id(),
+                WatchedDevice {
+                    device,
+                    peripheral,
+                    reconnecting: false,
+                },
+            );
+        }
+    }
+
+    pub fn unwatch(&self, device: DeviceId) {
+        if let Ok(mut devices) = self.devices.lock() {
+            devices.retain(|_, watched| watched.device != device);
+        }
+    }
+
+    fn is_watched(&self, device: DeviceId) -> bool {
+        match self.devices.lock() {
+            Ok(devices) => devices.values().any(|watched| watched.device == device),
+            Err(_) => false,
+        }
+    }
+
+    /// marks the device as reconnecting, None if it isn't watched or is already reconnecting
+    fn start_reconnect(&self, id: &PeripheralId) -> Option<(DeviceId, Peripheral)> {
+        let mut devices = self.devices.lock().ok()?;
+        let watched = devices.get_mut(id)?;
+        if watched.reconnecting {
+            return None;
+        }
+        watched.reconnecting = true;
+        Some((watched.device, watched.peripheral.clone()))
+    }
+
+    fn finish_reconnect(&self, device: DeviceId) {
+        if let Ok(mut devices) = self.devices.lock() {
+            for watched in devices.values_mut() {
+                if watched.device == device {
+                    watched.reconnecting = false;
+                }
+            }
+        }
+    }
+}
+
+/*=======================================================================
+ * FUNCTIONS
+ * ====================================================================*/
+/// listens to the adapter's events for as long as the GUI is around
+pub fn spawn_supervisor(
+    adapter: Adapter,
+    watch_list: WatchList,
+    backoff: Backoff,
+    event_sender: Sender<ConnectionEvent>,
+) {
+    thread::spawn(move || {
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async move {
+            let mut events = match adapter.events().await {
+                Ok(events) => events,
+                Err(e) => {
+                    println!("Failed to get adapter events, reconnects disabled: {:?}", e);
+                    return;
+                }
+            };
+            while let Some(event) = events.next().await {
+                let CentralEvent::DeviceDisconnected(id) = event else {
+                    continue;
+                };
+                let Some((device, peripheral)) = watch_list.start_reconnect(&id) else {
+                    continue;
+                };
+                if event_sender
+                    .send(ConnectionEvent::Disconnected { device })
+                    .is_err()
+                {
+                    break; // GUI is gone
+                }
+                spawn_reconnect(
+                    device,
+                    peripheral,
+                    watch_list.clone(),
+                    backoff,
+                    event_sender.clone(),
+                );
+            }
+        });
+    });
+}
+
+/// keeps trying until the device is back or the user stops watching it
+fn spawn_reconnect(
+    device: DeviceId,
+    peripheral: Peripheral,
+    watch_list: WatchList,
+    backoff: Backoff,
+    event_sender: Sender<ConnectionEvent>,
+) {
+    thread::spawn(move || {
+        let dropped_at = Instant::now();
+        let mut attempt = 0;
+        while watch_list.is_watched(device) {
+            thread::sleep(backoff.delay(attempt));
+            let result = task::block_on(async {
+                if !peripheral.is_connected().await? {
+                    peripheral.connect().await?;
+                }
+                peripheral.discover_services().await
+            });
+            match result {
+                Ok(()) => {
+                    watch_list.finish_reconnect(device);
+                    let gap = dropped_at.elapsed();
+                    let _ = event_sender.send(ConnectionEvent::Reconnected { device, gap });
+                    return;
+                }
+                Err(e) => println!("Reconnect attempt {} failed: {:?}", attempt + 1, e),
+            }
+            attempt += 1;
+        }
+    });
+}
+
+#[test]
+fn backoff_doubles_up_to_the_limit() {
+    let backoff = Backoff {
+        initial: Duration::from_secs(1),
+        max: Duration::from_secs(10),
+    };
+    assert_eq!(backoff.delay(0), Duration::from_secs(1));
+    assert_eq!(backoff.delay(1), Duration::from_secs(2));
+    assert_eq!(backoff.delay(3), Duration::from_secs(8));
+    assert_eq!(backoff.delay(4), Duration::from_secs(10));
+    assert_eq!(backoff.delay(100), Duration::from_secs(10));
+}
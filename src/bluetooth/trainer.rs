@@ -0,0 +1,735 @@
+// One interface over the different ways a trainer can be controlled over BLE, so the
+// GUI and the workout runner don't care which protocol the connected trainer speaks.
+
+/*=======================================================================
+ * IMPORTS
+ * ====================================================================*/
+// local files
+use crate::bluetooth::cps::{parse_cps_measurement, CpsMeasurement, CPS_POWER_MEASUREMENT};
+use crate::bluetooth::cscs::DEFAULT_WHEEL_CIRCUMFERENCE;
+use crate::bluetooth::fec::*;
+use crate::bluetooth::ftms::*;
+use crate::bluetooth::wahoo::{wahoo_command, WahooCommand, WAHOO_TRAINER_CONTROL};
+use crate::bluetooth::{
+    find_characteristic, spawn_notification_thread, BleParseError, BleRequestError,
+};
+use crate::simulation::RiderSettings;
+
+// external crates
+use async_std::task;
+use btleplug::api::Peripheral as Peripheral_api;
+use btleplug::platform::Peripheral;
+use std::fmt;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
+use uuid::Uuid;
+
+/*=======================================================================
+ * CONSTANTS
+ * ====================================================================*/
+/// a spin down is given up when the trainer says nothing for this long
+pub const SPIN_DOWN_TIMEOUT: Duration = Duration::from_secs(120);
+
+/*=======================================================================
+ * ENUMS
+ * ====================================================================*/
+/// trainer backends, best first
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrainerKind {
+    Ftms,
+    Wahoo,
+    Fec,
+    CpsOnly,
+    /// software trainer, see virtual_trainer
+    Virtual,
+}
+
+impl fmt::Display for TrainerKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrainerKind::Ftms => write!(f, "FTMS"),
+            TrainerKind::Wahoo => write!(f, "Wahoo"),
+            TrainerKind::Fec => write!(f, "FE-C over BLE"),
+            TrainerKind::CpsOnly => write!(f, "power meter only"),
+            TrainerKind::Virtual => write!(f, "virtual"),
+        }
+    }
+}
+
+/// one decoded notification from a trainer's data characteristic
+#[derive(Debug, Clone, PartialEq)]
+pub enum TrainerData {
+    Cps(CpsMeasurement),
+    IndoorBike(IndoorBikeData),
+    Fec(FecPage),
+}
+
+/// one step of a spin down calibration, the last one is Done or Failed
+#[derive(Debug, Clone, PartialEq)]
+pub enum SpinDownEvent {
+    /// speed up to between low and high, km/h
+    TargetSpeed(f32, f32),
+    /// target speed reached, coast until the flywheel stops
+    StopPedaling,
+    Done(SpinDownResult),
+    Failed(String),
+}
+
+/*=======================================================================
+ * STRUCTS
+ * ====================================================================*/
+/// everyone listening to the one status notification thread, None while it isn't running
+type StatusListeners = Arc<Mutex<Option<Vec<Sender<FtmsStatus>>>>>;
+
+/// owned by the status thread, a thread that ends with the connection leaves no listeners
+/// behind so the next one starts a new thread
+struct StatusThread {
+    listeners: StatusListeners,
+    stopped: bool, // already cleared the listeners itself
+}
+
+impl Drop for StatusThread {
+    fn drop(&mut self) {
+        if !self.stopped {
+            *self
+                .listeners
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner) = None;
+        }
+    }
+}
+
+/// what a trainer backend can be asked to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TrainerCapabilities {
+    pub target_power: bool,
+    pub resistance: bool,
+    pub simulation: bool,
+    pub spin_down: bool,
+}
+
+/// what the trainer reported at the end of a spin down, FTMS only says it worked
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct SpinDownResult {
+    pub spin_down_time: Option<f32>, // s
+    pub zero_offset: Option<u16>,
+    pub temperature: Option<f32>, // °C
+}
+
+/// power meter or trainer we can only read from
+pub struct CpsTrainer {
+    peripheral: Peripheral,
+}
+
+pub struct FtmsTrainer {
+    peripheral: Peripheral,
+    // None if the feature couldn't be read, everything is tried then
+    ftms_capabilities: Option<FtmsCapabilities>,
+    status_listeners: StatusListeners,
+}
+
+/// Tacx trainer taking FE-C pages over BLE
+pub struct FecTrainer {
+    peripheral: Peripheral,
+}
+
+/// older Wahoo KICKR with the proprietary control characteristic
+pub struct WahooTrainer {
+    peripheral: Peripheral,
+    // sim mode needs the total mass, which only arrives with start
+    total_weight: Mutex<f32>,
+}
+
+/*=======================================================================
+ * TRAITS
+ * ====================================================================*/
+/// a connected trainer, methods block until the trainer has answered
+/// anything the backend can't do returns BleRequestError::NotSupported
+pub trait Trainer: Send + Sync {
+    fn kind(&self) -> TrainerKind;
+
+    fn capabilities(&self) -> TrainerCapabilities;
+
+    /// ranges the trainer reported, targets outside them should be clamped first
+    fn supported_ranges(&self) -> SupportedRanges {
+        SupportedRanges::default()
+    }
+
+    /// subscribes to the trainer's data characteristic, each notification is decoded
+    /// and sent on the returned channel until the trainer disconnects
+    fn measurements(&self) -> Result<Receiver<TrainerData>, BleRequestError>;
+
+    /// status events the trainer reports on its own, e.g. another app taking control
+    fn status(&self) -> Result<Receiver<FtmsStatus>, BleRequestError> {
+        Err(BleRequestError::NotSupported("status notifications"))
+    }
+
+    /// takes control of the trainer before targets are sent
+    fn start(&self, _settings: &RiderSettings) -> Result<(), BleRequestError> {
+        Ok(())
+    }
+
+    fn pause(&self) -> Result<(), BleRequestError> {
+        Ok(())
+    }
+
+    fn resume(&self) -> Result<(), BleRequestError> {
+        Ok(())
+    }
+
+    /// hands control of the trainer back
+    fn stop(&self) -> Result<(), BleRequestError> {
+        Ok(())
+    }
+
+    fn set_target_power(&self, _watts: i16) -> Result<(), BleRequestError> {
+        Err(BleRequestError::NotSupported("ERG mode"))
+    }
+
+    /// resistance as % of the trainer's range
+    fn set_resistance(&self, _percent: f32) -> Result<(), BleRequestError> {
+        Err(BleRequestError::NotSupported("resistance mode"))
+    }
+
+    fn set_simulation(&self, _parameters: IndoorBikeSimulation) -> Result<(), BleRequestError> {
+        Err(BleRequestError::NotSupported("sim mode"))
+    }
+
+    /// starts a spin down calibration, its progress arrives on the returned channel
+    fn spin_down(&self) -> Result<Receiver<SpinDownEvent>, BleRequestError> {
+        Err(BleRequestError::NotSupported("spin down"))
+    }
+}
+
+impl Trainer for CpsTrainer {
+    fn kind(&self) -> TrainerKind {
+        TrainerKind::CpsOnly
+    }
+
+    fn capabilities(&self) -> TrainerCapabilities {
+        TrainerCapabilities::default()
+    }
+
+    fn measurements(&self) -> Result<Receiver<TrainerData>, BleRequestError> {
+        subscribe_data(&self.peripheral, CPS_POWER_MEASUREMENT, |buf| {
+            Ok(TrainerData::Cps(parse_cps_measurement(buf)?))
+        })
+    }
+}
+
+impl Trainer for FtmsTrainer {
+    fn kind(&self) -> TrainerKind {
+        TrainerKind::Ftms
+    }
+
+    fn capabilities(&self) -> TrainerCapabilities {
+        match self.ftms_capabilities {
+            Some(ftms) => TrainerCapabilities {
+                target_power: ftms.feature.target_setting.power_target_supported(),
+                resistance: ftms.feature.target_setting.resistance_target_supported(),
+                simulation: ftms
+                    .feature
+                    .target_setting
+                    .indoor_bike_simulation_supported(),
+                spin_down: ftms.feature.target_setting.spin_down_control_supported(),
+            },
+            None => TrainerCapabilities {
+                target_power: true,
+                resistance: true,
+                simulation: true,
+                spin_down: true,
+            },
+        }
+    }
+
+    fn supported_ranges(&self) -> SupportedRanges {
+        self.ftms_capabilities
+            .map(|ftms| ftms.ranges)
+            .unwrap_or_default()
+    }
+
+    fn measurements(&self) -> Result<Receiver<TrainerData>, BleRequestError> {
+        subscribe_data(&self.peripheral, FTMS_INDOOR_BIKE_DATA, |buf| {
+            Ok(TrainerData::IndoorBike(parse_indoor_bike_data(buf)?))
+        })
+    }
+
+    fn status(&self) -> Result<Receiver<FtmsStatus>, BleRequestError> {
+        listen_status(&self.peripheral, &self.status_listeners)
+    }
+
+    fn start(&self, _settings: &RiderSettings) -> Result<(), BleRequestError> {
+        // the trainer ignores targets until we own the control point and it is started
+        self.request(&FtmsRequest::RequestControl)?;
+        self.request(&FtmsRequest::StartOrResume)
+    }
+
+    fn pause(&self) -> Result<(), BleRequestError> {
+        self.request(&FtmsRequest::StopOrPause(StopOrPause::Pause))
+    }
+
+    fn resume(&self) -> Result<(), BleRequestError> {
+        self.request(&FtmsRequest::StartOrResume)
+    }
+
+    fn stop(&self) -> Result<(), BleRequestError> {
+        // stop, then reset which hands control of the trainer back
+        self.request(&FtmsRequest::StopOrPause(StopOrPause::Stop))?;
+        self.request(&FtmsRequest::Reset)
+    }
+
+    fn set_target_power(&self, watts: i16) -> Result<(), BleRequestError> {
+        self.request(&FtmsRequest::SetTargetPower(watts))
+    }
+
+    fn set_resistance(&self, percent: f32) -> Result<(), BleRequestError> {
+        let level = resistance_level(percent, self.supported_ranges().resistance);
+        self.request(&FtmsRequest::SetTargetResistanceLevel(level))
+    }
+
+    fn set_simulation(&self, parameters: IndoorBikeSimulation) -> Result<(), BleRequestError> {
+        self.request(&FtmsRequest::SetIndoorBikeSimulation(parameters))
+    }
+
+    fn spin_down(&self) -> Result<Receiver<SpinDownEvent>, BleRequestError> {
+        let peripheral = self.peripheral.clone();
+        let status_listeners = self.status_listeners.clone();
+        Ok(spin_down_events(
+            move || {
+                // listen first, the trainer may ask to stop pedaling right after the response
+                let status = listen_status(&peripheral, &status_listeners)?;
+                task::block_on(ftms_request(&peripheral, &FtmsRequest::RequestControl))?;
+                let response = task::block_on(ftms_request(
+                    &peripheral,
+                    &FtmsRequest::SpinDownControl(SpinDownControl::Start),
+                ))?;
+                let (low, high) = parse_spin_down_response(&response.parameter)?;
+                Ok((status, Some(SpinDownEvent::TargetSpeed(low, high))))
+            },
+            |status| match status {
+                FtmsStatus::Machine(MachineStatus::SpinDown(SpinDownStatus::StopPedaling)) => {
+                    Some(SpinDownEvent::StopPedaling)
+                }
+                FtmsStatus::Machine(MachineStatus::SpinDown(SpinDownStatus::Success)) => {
+                    Some(SpinDownEvent::Done(SpinDownResult::default()))
+                }
+                FtmsStatus::Machine(MachineStatus::SpinDown(SpinDownStatus::Error)) => Some(
+                    SpinDownEvent::Failed("the trainer reported a spin down error".to_string()),
+                ),
+                FtmsStatus::Machine(MachineStatus::ControlPermissionLost) => Some(
+                    SpinDownEvent::Failed("another app took control of the trainer".to_string()),
+                ),
+                _ => None,
+            },
+        ))
+    }
+}
+
+impl FtmsTrainer {
+    fn request(&self, request: &FtmsRequest) -> Result<(), BleRequestError> {
+        task::block_on(ftms_request(&self.peripheral, request))?;
+        Ok(())
+    }
+}
+
+impl Trainer for FecTrainer {
+    fn kind(&self) -> TrainerKind {
+        TrainerKind::Fec
+    }
+
+    fn capabilities(&self) -> TrainerCapabilities {
+        TrainerCapabilities {
+            target_power: true,
+            resistance: true,
+            simulation: true,
+            spin_down: true,
+        }
+    }
+
+    fn measurements(&self) -> Result<Receiver<TrainerData>, BleRequestError> {
+        subscribe_data(&self.peripheral, FEC_READ, parse_fec_data)
+    }
+
+    fn start(&self, settings: &RiderSettings) -> Result<(), BleRequestError> {
+        // FE-C has no control procedure, but sim mode needs the rider's mass
+        let config = UserConfiguration {
+            user_weight: settings.rider_weight,
+            bike_weight: settings.bike_weight,
+            wheel_diameter: DEFAULT_WHEEL_CIRCUMFERENCE / std::f32::consts::PI,
+            gear_ratio: None,
+        };
+        self.send(&FecPage::UserConfiguration(config))
+    }
+
+    fn set_target_power(&self, watts: i16) -> Result<(), BleRequestError> {
+        self.send(&FecPage::TargetPower(watts as f32))
+    }
+
+    fn set_resistance(&self, percent: f32) -> Result<(), BleRequestError> {
+        self.send(&FecPage::BasicResistance(percent))
+    }
+
+    fn set_simulation(&self, parameters: IndoorBikeSimulation) -> Result<(), BleRequestError> {
+        // page 51 has no wind, Tacx trainers use their own drag model
+        let resistance = TrackResistance {
+            grade: parameters.grade,
+            crr: Some(parameters.crr),
+        };
+        self.send(&FecPage::TrackResistance(resistance))
+    }
+
+    fn spin_down(&self) -> Result<Receiver<SpinDownEvent>, BleRequestError> {
+        let peripheral = self.peripheral.clone();
+        let request = FecPage::Calibration(Calibration {
+            spin_down: true,
+            ..Default::default()
+        });
+        // the trainer asks for speed until it is fast enough, then for coasting
+        let start = move || {
+            let pages = subscribe_data(&peripheral, FEC_READ, parse_fec_data)?;
+            task::block_on(fec_send_page(&peripheral, &request))?;
+            Ok((pages, None))
+        };
+        Ok(spin_down_events(start, |data| match data {
+            TrainerData::Fec(FecPage::CalibrationProgress(progress)) => {
+                match (progress.speed_condition, progress.target_speed) {
+                    (CalibrationCondition::Ok, _) => Some(SpinDownEvent::StopPedaling),
+                    (_, Some(speed)) => Some(SpinDownEvent::TargetSpeed(speed, speed)),
+                    _ => None,
+                }
+            }
+            TrainerData::Fec(FecPage::Calibration(result)) if result.spin_down => {
+                Some(SpinDownEvent::Done(SpinDownResult {
+                    spin_down_time: result.spin_down_time.map(|ms| ms as f32 / 1000.0),
+                    zero_offset: result.zero_offset_value,
+                    temperature: result.temperature,
+                }))
+            }
+            TrainerData::Fec(FecPage::Calibration(_)) => Some(SpinDownEvent::Failed(
+                "the trainer reported the spin down failed".to_string(),
+            )),
+            _ => None,
+        }))
+    }
+}
+
+impl FecTrainer {
+    fn send(&self, page: &FecPage) -> Result<(), BleRequestError> {
+        task::block_on(fec_send_page(&self.peripheral, page))
+    }
+}
+
+impl Trainer for WahooTrainer {
+    fn kind(&self) -> TrainerKind {
+        TrainerKind::Wahoo
+    }
+
+    fn capabilities(&self) -> TrainerCapabilities {
+        TrainerCapabilities {
+            target_power: true,
+            resistance: true,
+            simulation: true,
+            spin_down: false,
+        }
+    }
+
+    fn measurements(&self) -> Result<Receiver<TrainerData>, BleRequestError> {
+        // power comes from the regular CPS characteristic
+        subscribe_data(&self.peripheral, CPS_POWER_MEASUREMENT, |buf| {
+            Ok(TrainerData::Cps(parse_cps_measurement(buf)?))
+        })
+    }
+
+    fn start(&self, settings: &RiderSettings) -> Result<(), BleRequestError> {
+        if let Ok(mut total_weight) = self.total_weight.lock() {
+            *total_weight = settings.rider_weight + settings.bike_weight;
+        }
+        self.command(&WahooCommand::Unlock)
+    }
+
+    fn set_target_power(&self, watts: i16) -> Result<(), BleRequestError> {
+        self.command(&WahooCommand::SetErgMode(watts.max(0) as u16))
+    }
+
+    fn set_resistance(&self, percent: f32) -> Result<(), BleRequestError> {
+        self.command(&WahooCommand::SetResistanceMode(percent))
+    }
+
+    fn set_simulation(&self, parameters: IndoorBikeSimulation) -> Result<(), BleRequestError> {
+        let weight = self.total_weight.lock().map(|w| *w).unwrap_or(84.0);
+        // sim mode has to be (re)entered in case the trainer was in ERG
+        self.command(&WahooCommand::SetSimMode {
+            weight,
+            crr: parameters.crr,
+            cw: parameters.cw,
+        })?;
+        self.command(&WahooCommand::SetWindSpeed(parameters.wind_speed))?;
+        self.command(&WahooCommand::SetGrade(parameters.grade))
+    }
+}
+
+impl WahooTrainer {
+    fn command(&self, command: &WahooCommand) -> Result<(), BleRequestError> {
+        task::block_on(wahoo_command(&self.peripheral, command))
+    }
+}
+
+/*=======================================================================
+ * FUNCTIONS
+ * ====================================================================*/
+/// picks the best backend for the discovered characteristics
+pub fn choose_trainer_kind(characteristics: &[Uuid]) -> Option<TrainerKind> {
+    let has = |uuid: Uuid| characteristics.contains(&uuid);
+    if has(FTMS_CONTROL_POINT) && has(FTMS_INDOOR_BIKE_DATA) {
+        Some(TrainerKind::Ftms)
+    } else if has(WAHOO_TRAINER_CONTROL) && has(CPS_POWER_MEASUREMENT) {
+        Some(TrainerKind::Wahoo)
+    } else if has(FEC_WRITE) && has(FEC_READ) {
+        Some(TrainerKind::Fec)
+    } else if has(CPS_POWER_MEASUREMENT) {
+        Some(TrainerKind::CpsOnly)
+    } else {
+        None
+    }
+}
+
+/// wraps a connected peripheral in the best backend it supports
+/// services have to be discovered first, None if it isn't a trainer or power meter
+pub fn create_trainer(peripheral: &Peripheral) -> Option<Box<dyn Trainer>> {
+    let characteristics: Vec<Uuid> = peripheral
+        .characteristics()
+        .iter()
+        .map(|characteristic| characteristic.uuid)
+        .collect();
+    let peripheral = peripheral.clone();
+    let trainer: Box<dyn Trainer> = match choose_trainer_kind(&characteristics)? {
+        TrainerKind::Ftms => {
+            let ftms_capabilities = match task::block_on(read_ftms_capabilities(&peripheral)) {
+                Ok(capabilities) => Some(capabilities),
+                Err(e) => {
+                    println!("Failed to read FTMS features: {}", e);
+                    None
+                }
+            };
+            Box::new(FtmsTrainer {
+                peripheral,
+                ftms_capabilities,
+                status_listeners: StatusListeners::default(),
+            })
+        }
+        TrainerKind::Wahoo => Box::new(WahooTrainer {
+            peripheral,
+            total_weight: Mutex::new(84.0),
+        }),
+        TrainerKind::Fec => Box::new(FecTrainer { peripheral }),
+        TrainerKind::CpsOnly => Box::new(CpsTrainer { peripheral }),
+        TrainerKind::Virtual => return None, // never chosen for a real peripheral
+    };
+    Some(trainer)
+}
+
+/// FTMS resistance level for a % of the trainer's range
+/// without a reported range the whole level the request can carry is used
+/// a range past what the request can carry is cut down to it
+fn resistance_level(percent: f32, range: Option<SupportedRange>) -> f32 {
+    let range = match range {
+        Some(range) => SupportedRange {
+            minimum: range.minimum.clamp(0.0, MAX_RESISTANCE_LEVEL),
+            maximum: range.maximum.clamp(0.0, MAX_RESISTANCE_LEVEL),
+            ..range
+        },
+        None => SupportedRange {
+            minimum: 0.0,
+            maximum: MAX_RESISTANCE_LEVEL,
+            increment: 0.1,
+        },
+    };
+    range.clamp(range.minimum + (range.maximum - range.minimum) * percent / 100.0)
+}
+
+/// adds a listener for Fitness Machine Status and, if the trainer has it, Training Status
+/// both arrive on the same channel, one thread forwards them to every listener
+fn listen_status(
+    peripheral: &Peripheral,
+    listeners: &StatusListeners,
+) -> Result<Receiver<FtmsStatus>, BleRequestError> {
+    // subscribed every time, a reconnected trainer has forgotten the old subscription
+    let machine_status = find_characteristic(peripheral, FTMS_MACHINE_STATUS)
+        .ok_or(BleRequestError::MissingCharacteristic(FTMS_MACHINE_STATUS))?;
+    task::block_on(peripheral.subscribe(&machine_status))?;
+    if let Some(training_status) = find_characteristic(peripheral, FTMS_TRAINING_STATUS) {
+        task::block_on(peripheral.subscribe(&training_status))?;
+    }
+    let (sender, receiver) = mpsc::channel();
+    let mut senders = listeners.lock().unwrap_or_else(PoisonError::into_inner);
+    match senders.as_mut() {
+        Some(senders) => senders.push(sender),
+        None => {
+            *senders = Some(vec![sender]);
+            spawn_status_thread(peripheral.clone(), listeners.clone());
+        }
+    }
+    Ok(receiver)
+}
+
+/// decodes status notifications until nobody is listening or the connection is gone
+fn spawn_status_thread(peripheral: Peripheral, listeners: StatusListeners) {
+    let mut thread = StatusThread {
+        listeners,
+        stopped: false,
+    };
+    spawn_notification_thread(
+        peripheral,
+        |uuid, value| match uuid {
+            FTMS_MACHINE_STATUS => Some(parse_machine_status(value).map(FtmsStatus::Machine)),
+            FTMS_TRAINING_STATUS => Some(parse_training_status(value).map(FtmsStatus::Training)),
+            _ => None,
+        },
+        move |status| {
+            let mut senders = thread
+                .listeners
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            let senders_left = senders.get_or_insert_with(Vec::new);
+            senders_left.retain(|sender| sender.send(status.clone()).is_ok());
+            if senders_left.is_empty() {
+                // cleared under the lock, a new listener starts a new thread
+                *senders = None;
+                drop(senders);
+                thread.stopped = true;
+                return false;
+            }
+            true
+        },
+    );
+}
+
+/// FE-C pages from the read characteristic
+fn parse_fec_data(buf: &[u8]) -> Result<TrainerData, BleParseError> {
+    Ok(TrainerData::Fec(parse_fec_notification(buf)?))
+}
+
+/// starts a spin down and turns what the trainer reports into spin down events, all on a
+/// separate thread
+/// start subscribes and sends the request, giving what to listen to and a first event
+/// repeats are dropped and the thread ends after Done, Failed or SPIN_DOWN_TIMEOUT
+/// without an event
+fn spin_down_events<T: Send + 'static>(
+    start: impl FnOnce() -> Result<(Receiver<T>, Option<SpinDownEvent>), BleRequestError>
+        + Send
+        + 'static,
+    step: fn(T) -> Option<SpinDownEvent>,
+) -> Receiver<SpinDownEvent> {
+    let (sender, events) = mpsc::channel();
+    thread::spawn(move || {
+        let (receiver, mut last) = match start() {
+            Ok(started) => started,
+            Err(e) => {
+                let _ = sender.send(SpinDownEvent::Failed(e.to_string()));
+                return;
+            }
+        };
+        if let Some(first) = &last {
+            if sender.send(first.clone()).is_err() {
+                return;
+            }
+        }
+        loop {
+            let event = match receiver.recv_timeout(SPIN_DOWN_TIMEOUT) {
+                Ok(data) => match step(data) {
+                    Some(event) if last.as_ref() != Some(&event) => event,
+                    _ => continue,
+                },
+                Err(RecvTimeoutError::Timeout) => SpinDownEvent::Failed(format!(
+                    "no answer from the trainer for {} s",
+                    SPIN_DOWN_TIMEOUT.as_secs()
+                )),
+                Err(RecvTimeoutError::Disconnected) => {
+                    SpinDownEvent::Failed("the trainer disconnected".to_string())
+                }
+            };
+            let finished = matches!(event, SpinDownEvent::Done(_) | SpinDownEvent::Failed(_));
+            if sender.send(event.clone()).is_err() || finished {
+                break;
+            }
+            last = Some(event);
+        }
+    });
+    events
+}
+
+/// subscribes to a characteristic and decodes its notifications on a separate thread
+fn subscribe_data(
+    peripheral: &Peripheral,
+    uuid: Uuid,
+    parse: fn(&[u8]) -> Result<TrainerData, BleParseError>,
+) -> Result<Receiver<TrainerData>, BleRequestError> {
+    let characteristic = find_characteristic(peripheral, uuid)
+        .ok_or(BleRequestError::MissingCharacteristic(uuid))?;
+    task::block_on(peripheral.subscribe(&characteristic))?;
+    let (sender, receiver) = mpsc::channel();
+    spawn_notification_thread(
+        peripheral.clone(),
+        move |data_uuid, value| (data_uuid == uuid).then(|| parse(value)),
+        move |decoded| sender.send(decoded).is_ok(),
+    );
+    Ok(receiver)
+}
+
+#[test]
+fn factory_prefers_ftms_then_wahoo_then_fec() {
+    use crate::bluetooth::cps::CPS_CONTROL_POINT;
+    let cps = [CPS_POWER_MEASUREMENT, CPS_CONTROL_POINT];
+    assert_eq!(choose_trainer_kind(&cps), Some(TrainerKind::CpsOnly));
+    let wahoo = [CPS_POWER_MEASUREMENT, WAHOO_TRAINER_CONTROL];
+    assert_eq!(choose_trainer_kind(&wahoo), Some(TrainerKind::Wahoo));
+    let tacx = [CPS_POWER_MEASUREMENT, FEC_READ, FEC_WRITE];
+    assert_eq!(choose_trainer_kind(&tacx), Some(TrainerKind::Fec));
+    let everything = [
+        CPS_POWER_MEASUREMENT,
+        FEC_READ,
+        FEC_WRITE,
+        FTMS_INDOOR_BIKE_DATA,
+        FTMS_CONTROL_POINT,
+    ];
+    assert_eq!(choose_trainer_kind(&everything), Some(TrainerKind::Ftms));
+    // half an FTMS implementation isn't enough to control the trainer
+    assert_eq!(choose_trainer_kind(&[FTMS_INDOOR_BIKE_DATA]), None);
+}
+
+#[test]
+fn resistance_percent_fills_the_level_range() {
+    let level = |percent| resistance_level(percent, None);
+    assert!((level(50.0) - 12.8).abs() < 0.001); // 12.75 snapped to 0.1 steps
+    assert!((level(100.0) - MAX_RESISTANCE_LEVEL).abs() < 0.001);
+    assert_eq!(
+        FtmsRequest::SetTargetResistanceLevel(level(100.0)).encode(),
+        vec![0x04, 0xFF]
+    );
+    let range = SupportedRange {
+        minimum: 0.0,
+        maximum: 20.0,
+        increment: 1.0,
+    };
+    assert_eq!(resistance_level(50.0, Some(range)), 10.0);
+    assert_eq!(
+        FtmsRequest::SetTargetResistanceLevel(resistance_level(50.0, Some(range))).encode(),
+        vec![0x04, 100]
+    );
+    // a maximum of 100 doesn't fit the request, it's cut to the largest level that does
+    let range = SupportedRange {
+        minimum: 0.0,
+        maximum: 100.0,
+        increment: 0.1,
+    };
+    assert_eq!(
+        FtmsRequest::SetTargetResistanceLevel(resistance_level(50.0, Some(range))).encode(),
+        vec![0x04, 128]
+    );
+    assert_eq!(
+        FtmsRequest::SetTargetResistanceLevel(resistance_level(100.0, Some(range))).encode(),
+        vec![0x04, 0xFF]
+    );
+}
@@ -0,0 +1,413 @@
+// A trainer that only exists in software, for working on the app without a Bluetooth
+// adapter. It behaves like an FTMS trainer with a rider pedalling on it.
+
+/*=======================================================================
+ * IMPORTS
+ * ====================================================================*/
+// local files
+use crate::bluetooth::ftms::{IndoorBikeData, IndoorBikeDataFlag, IndoorBikeSimulation};
+use crate::bluetooth::trainer::{Trainer, TrainerCapabilities, TrainerData, TrainerKind};
+use crate::bluetooth::BleRequestError;
+use crate::simulation::RiderSettings;
+
+// external crates
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::Duration;
+
+/*=======================================================================
+ * CONSTANTS
+ * ====================================================================*/
+const GRAVITY: f32 = 9.81; // m/s²
+/// power at 100 % resistance for each rpm of cadence
+const WATTS_PER_RPM_AT_FULL_RESISTANCE: f32 = 5.0;
+/// how quickly power and speed follow a change, s
+const POWER_TIME_CONSTANT: f32 = 1.0;
+const SPEED_TIME_CONSTANT: f32 = 3.0;
+const MAX_SPEED: f32 = 30.0; // m/s
+
+/*=======================================================================
+ * ENUMS
+ * ====================================================================*/
+/// what the trainer was last asked to do
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VirtualMode {
+    /// no target, the rider rides at their cruise power
+    FreeRide,
+    Erg(f32),        // W
+    Resistance(f32), // % of maximum
+    Simulation(IndoorBikeSimulation),
+}
+
+/*=======================================================================
+ * STRUCTS
+ * ====================================================================*/
+/// how the simulated rider pedals
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RiderModel {
+    pub cruise_power: f32, // W, ridden when the trainer doesn't hold a target
+    pub cadence: f32,      // rpm
+    pub mass: f32,         // kg, rider and bike
+}
+
+impl Default for RiderModel {
+    fn default() -> Self {
+        Self {
+            cruise_power: 180.0,
+            cadence: 90.0,
+            mass: 84.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VirtualTrainerSettings {
+    pub rider: RiderModel,
+    pub noise: f32, // W, power jumps up to this much either side of the model
+    /// chance that a sample starts a dropout, 0 to 1
+    pub dropout_chance: f32,
+    pub dropout_samples: u32, // samples missing per dropout
+    pub sample_interval: Duration,
+}
+
+impl Default for VirtualTrainerSettings {
+    fn default() -> Self {
+        Self {
+            rider: RiderModel::default(),
+            noise: 0.0,
+            dropout_chance: 0.0,
+            dropout_samples: 8,
+            sample_interval: Duration::from_millis(250),
+        }
+    }
+}
+
+/// the rider/trainer physics, stepped by the sample thread or directly from tests
+#[derive(Debug, Clone)]
+pub struct VirtualTrainerModel {
+    pub settings: VirtualTrainerSettings,
+    pub mode: VirtualMode,
+    power: f32,    // W
+    speed: f32,    // m/s
+    distance: f32, // m
+    elapsed: f32,  // s
+    dropout_remaining: u32,
+    random: XorShift,
+}
+
+/// small deterministic random number generator so noise doesn't need a crate
+#[derive(Debug, Clone)]
+struct XorShift(u32);
+
+impl XorShift {
+    /// uniform in [0, 1)
+    fn next(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        (self.0 >> 8) as f32 / (1 << 24) as f32
+    }
+}
+
+/// the Trainer backend wrapping the model
+pub struct VirtualTrainer {
+    model: Arc<Mutex<VirtualTrainerModel>>,
+    // bumped by every measurements() call, older sample threads see it and stop
+    subscription: Arc<AtomicUsize>,
+}
+
+impl VirtualTrainerModel {
+    pub fn new(settings: VirtualTrainerSettings) -> Self {
+        Self {
+            settings,
+            mode: VirtualMode::FreeRide,
+            power: 0.0,
+            speed: 0.0,
+            distance: 0.0,
+            elapsed: 0.0,
+            dropout_remaining: 0,
+            random: XorShift(0x2545_F491),
+        }
+    }
+
+    /// advances the model, None while a dropout is swallowing samples
+    pub fn step(&mut self, elapsed: Duration) -> Option<IndoorBikeData> {
+        let dt = elapsed.as_secs_f32();
+        let rider = self.settings.rider;
+        let target_power = match self.mode {
+            VirtualMode::FreeRide | VirtualMode::Simulation(_) => rider.cruise_power,
+            // the trainer changes resistance until the rider is at the target
+            VirtualMode::Erg(watts) => watts,
+            VirtualMode::Resistance(percent) => {
+                percent.clamp(0.0, 100.0) / 100.0 * WATTS_PER_RPM_AT_FULL_RESISTANCE * rider.cadence
+            }
+        };
+        self.power += (target_power - self.power) * (dt / POWER_TIME_CONSTANT).min(1.0);
+        let road = match self.mode {
+            VirtualMode::Simulation(parameters) => parameters,
+            _ => flat_road(),
+        };
+        let target_speed = speed_for_power(self.power, rider.mass, &road);
+        self.speed += (target_speed - self.speed) * (dt / SPEED_TIME_CONSTANT).min(1.0);
+        self.distance += self.speed * dt;
+        self.elapsed += dt;
+
+        if self.dropout_remaining > 0 {
+            self.dropout_remaining -= 1;
+            return None;
+        }
+        if self.random.next() < self.settings.dropout_chance {
+            self.dropout_remaining = self.settings.dropout_samples.saturating_sub(1);
+            return None;
+        }
+        let noise = (self.random.next() * 2.0 - 1.0) * self.settings.noise;
+        Some(self.indoor_bike_data((self.power + noise).max(0.0)))
+    }
+
+    /// km/h
+    pub fn speed(&self) -> f32 {
+        self.speed * 3.6
+    }
+
+    fn indoor_bike_data(&self, power: f32) -> IndoorBikeData {
+        // speed (bit 0 clear), cadence, distance, power and elapsed time
+        let mut flags = IndoorBikeDataFlag(0);
+        flags.set_instantaneous_cadence_present(true);
+        flags.set_total_distance_present(true);
+        flags.set_instantaneous_power_present(true);
+        flags.set_elapsed_time_present(true);
+        flags.set_resistance_level_present(matches!(self.mode, VirtualMode::Resistance(_)));
+        IndoorBikeData {
+            flags,
+            instantaneous_speed: Some(self.speed()),
+            average_speed: None,
+            instantaneous_cadence: Some(self.settings.rider.cadence),
+            average_cadence: None,
+            total_distance: Some(self.distance as u32),
+            resistance_level: match self.mode {
+                VirtualMode::Resistance(percent) => Some(percent.round() as i16),
+                _ => None,
+            },
+            instantaneous_power: Some(power.round() as i16),
+            average_power: None,
+            total_energy: None,
+            energy_per_hour: None,
+            energy_per_minute: None,
+            heart_rate: None,
+            metabolic_equivalent: None,
+            elapsed_time: Some(self.elapsed as u16),
+            remaining_time: None,
+        }
+    }
+}
+
+impl VirtualTrainer {
+    pub fn new(settings: VirtualTrainerSettings) -> Self {
+        Self {
+            model: Arc::new(Mutex::new(VirtualTrainerModel::new(settings))),
+            subscription: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub fn mode(&self) -> VirtualMode {
+        self.model
+            .lock()
+            .map(|model| model.mode)
+            .unwrap_or(VirtualMode::FreeRide)
+    }
+
+    fn set_mode(&self, mode: VirtualMode) -> Result<(), BleRequestError> {
+        if let Ok(mut model) = self.model.lock() {
+            model.mode = mode;
+        }
+        Ok(())
+    }
+}
+
+impl Trainer for VirtualTrainer {
+    fn kind(&self) -> TrainerKind {
+        TrainerKind::Virtual
+    }
+
+    fn capabilities(&self) -> TrainerCapabilities {
+        TrainerCapabilities {
+            target_power: true,
+            resistance: true,
+            simulation: true,
+            spin_down: false,
+        }
+    }
+
+    /// a new subscription replaces the previous one
+    /// during a dropout nothing is sent, so a dropped receiver alone wouldn't end the thread
+    fn measurements(&self) -> Result<Receiver<TrainerData>, BleRequestError> {
+        let (sender, receiver) = mpsc::channel();
+        let model = Arc::downgrade(&self.model);
+        let subscription = self.subscription.clone();
+        let this_subscription = subscription.fetch_add(1, Ordering::Relaxed) + 1;
+        // stops once the trainer is dropped
+        thread::spawn(move || {
+            while let Some(interval) = sample_interval(&model) {
+                thread::sleep(interval);
+                if subscription.load(Ordering::Relaxed) != this_subscription {
+                    break;
+                }
+                let Some(model) = model.upgrade() else {
+                    break;
+                };
+                let sample = match model.lock() {
+                    Ok(mut model) => model.step(interval),
+                    Err(_) => break,
+                };
+                if let Some(data) = sample {
+                    if sender.send(TrainerData::IndoorBike(data)).is_err() {
+                        break; // nobody is listening anymore
+                    }
+                }
+            }
+        });
+        Ok(receiver)
+    }
+
+    fn start(&self, settings: &RiderSettings) -> Result<(), BleRequestError> {
+        if let Ok(mut model) = self.model.lock() {
+            model.settings.rider.mass = settings.rider_weight + settings.bike_weight;
+        }
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), BleRequestError> {
+        self.set_mode(VirtualMode::FreeRide)
+    }
+
+    fn set_target_power(&self, watts: i16) -> Result<(), BleRequestError> {
+        self.set_mode(VirtualMode::Erg(watts.max(0) as f32))
+    }
+
+    fn set_resistance(&self, percent: f32) -> Result<(), BleRequestError> {
+        self.set_mode(VirtualMode::Resistance(percent))
+    }
+
+    fn set_simulation(&self, parameters: IndoorBikeSimulation) -> Result<(), BleRequestError> {
+        self.set_mode(VirtualMode::Simulation(parameters))
+    }
+}
+
+/*=======================================================================
+ * FUNCTIONS
+ * ====================================================================*/
+/// None once the trainer has been dropped
+fn sample_interval(model: &Weak<Mutex<VirtualTrainerModel>>) -> Option<Duration> {
+    let model = model.upgrade()?;
+    let interval = model.lock().ok()?.settings.sample_interval;
+    Some(interval)
+}
+
+fn flat_road() -> IndoorBikeSimulation {
+    let settings = RiderSettings::default();
+    IndoorBikeSimulation {
+        wind_speed: settings.wind_speed,
+        grade: 0.0,
+        crr: settings.crr,
+        cw: settings.cw,
+    }
+}
+
+/// steady state speed (m/s) for a power on a road, by bisection
+fn speed_for_power(power: f32, mass: f32, road: &IndoorBikeSimulation) -> f32 {
+    let slope = (road.grade / 100.0).atan();
+    let gravity_and_rolling = mass * GRAVITY * (road.crr * slope.cos() + slope.sin());
+    let required_power = |speed: f32| {
+        let air_speed = speed + road.wind_speed;
+        speed * (gravity_and_rolling + road.cw * air_speed * air_speed.abs())
+    };
+    let (mut low, mut high) = (0.0, MAX_SPEED);
+    if required_power(high) < power {
+        return high;
+    }
+    for _ in 0..40 {
+        let middle = (low + high) / 2.0;
+        if required_power(middle) < power {
+            low = middle;
+        } else {
+            high = middle;
+        }
+    }
+    low
+}
+
+#[test]
+fn erg_target_is_reached() {
+    let mut model = VirtualTrainerModel::new(VirtualTrainerSettings::default());
+    model.mode = VirtualMode::Erg(250.0);
+    let mut last = None;
+    for _ in 0..120 {
+        last = model.step(Duration::from_millis(250));
+    }
+    let data = last.unwrap();
+    assert_eq!(data.instantaneous_power, Some(250));
+    assert_eq!(data.instantaneous_cadence, Some(90.0));
+    // upright on the hoods (cw 0.51) 250 W is a bit under 30 km/h
+    assert!((25.0..30.0).contains(&data.instantaneous_speed.unwrap()));
+}
+
+#[test]
+fn climbing_is_slower_than_the_flat() {
+    let road = flat_road();
+    let climb = IndoorBikeSimulation { grade: 8.0, ..road };
+    assert!(speed_for_power(200.0, 84.0, &climb) < speed_for_power(200.0, 84.0, &road) / 2.0);
+    // a steep descent is fast even without pedalling, around 40 km/h
+    let descent = IndoorBikeSimulation {
+        grade: -8.0,
+        ..road
+    };
+    assert!(speed_for_power(0.0, 84.0, &descent) > 10.0);
+}
+
+#[test]
+fn dropouts_and_noise_are_injected() {
+    let settings = VirtualTrainerSettings {
+        noise: 20.0,
+        dropout_chance: 0.1,
+        dropout_samples: 4,
+        ..VirtualTrainerSettings::default()
+    };
+    let mut model = VirtualTrainerModel::new(settings);
+    model.mode = VirtualMode::Erg(200.0);
+    let samples: Vec<Option<IndoorBikeData>> = (0..400)
+        .map(|_| model.step(Duration::from_millis(250)))
+        .collect();
+    let missing = samples.iter().filter(|sample| sample.is_none()).count();
+    assert!(missing > 0 && missing < 300);
+    let powers: Vec<i16> = samples[100..]
+        .iter()
+        .flatten()
+        .map(|data| data.instantaneous_power.unwrap())
+        .collect();
+    assert!(powers.iter().all(|power| (180..=220).contains(power)));
+    assert!(powers.iter().any(|power| *power != 200));
+}
+
+#[test]
+fn sample_threads_stop_without_sending() {
+    // every sample is dropped, so only a new subscription or the trainer going away ends them
+    let trainer = VirtualTrainer::new(VirtualTrainerSettings {
+        dropout_chance: 1.0,
+        sample_interval: Duration::from_millis(1),
+        ..VirtualTrainerSettings::default()
+    });
+    let first = trainer.measurements().unwrap();
+    let second = trainer.measurements().unwrap();
+    let timeout = Duration::from_secs(1);
+    assert_eq!(
+        first.recv_timeout(timeout),
+        Err(mpsc::RecvTimeoutError::Disconnected)
+    );
+    drop(trainer);
+    assert_eq!(
+        second.recv_timeout(timeout),
+        Err(mpsc::RecvTimeoutError::Disconnected)
+    );
+}
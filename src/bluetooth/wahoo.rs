@@ -0,0 +1,150 @@
+/*=======================================================================
+ * IMPORTS
+ * ====================================================================*/
+// local files
+use crate::bluetooth::{find_characteristic, BleRequestError};
+
+// external crates
+use btleplug::api::{Peripheral as Peripheral_api, WriteType};
+use btleplug::platform::Peripheral;
+use uuid::{uuid, Uuid};
+
+/*=======================================================================
+ * CONSTANTS
+ * ====================================================================*/
+// older Wahoo KICKRs take targets through an extra characteristic in the CPS service
+pub const WAHOO_TRAINER_CONTROL: Uuid = uuid!("a026e005-0a7d-4ab3-97fa-f1500f9feb8b");
+
+/*=======================================================================
+ * ENUMS
+ * ====================================================================*/
+/// Wahoo trainer control op codes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WahooOpCode {
+    Unlock = 0x20,
+    SetResistanceMode = 0x40,
+    SetErgMode = 0x42,
+    SetSimMode = 0x43,
+    SetGrade = 0x46,
+    SetWindSpeed = 0x47,
+}
+
+/// a Wahoo trainer control command with its parameter
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WahooCommand {
+    /// has to be sent once per connection before anything else is accepted
+    Unlock,
+    SetResistanceMode(f32), // % of maximum
+    SetErgMode(u16),        // W
+    /// switches to sim mode with the rider+bike mass (kg), crr and cw (kg/m)
+    SetSimMode {
+        weight: f32,
+        crr: f32,
+        cw: f32,
+    },
+    SetGrade(f32),     // %
+    SetWindSpeed(f32), // m/s, positive is a headwind
+}
+
+impl WahooCommand {
+    pub fn op_code(&self) -> WahooOpCode {
+        match self {
+            WahooCommand::Unlock => WahooOpCode::Unlock,
+            WahooCommand::SetResistanceMode(_) => WahooOpCode::SetResistanceMode,
+            WahooCommand::SetErgMode(_) => WahooOpCode::SetErgMode,
+            WahooCommand::SetSimMode { .. } => WahooOpCode::SetSimMode,
+            WahooCommand::SetGrade(_) => WahooOpCode::SetGrade,
+            WahooCommand::SetWindSpeed(_) => WahooOpCode::SetWindSpeed,
+        }
+    }
+
+    /// bytes written to the trainer control characteristic
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = vec![self.op_code() as u8];
+        match self {
+            WahooCommand::Unlock => buf.extend_from_slice(&[0xEE, 0xFC]),
+            WahooCommand::SetResistanceMode(percent) => {
+                // 14 bit scale where 0 is the most resistance
+                let fraction = (percent / 100.0).clamp(0.0, 1.0);
+                let level = ((1.0 - fraction) * 16383.0).round() as u16;
+                buf.extend_from_slice(&level.to_le_bytes());
+            }
+            WahooCommand::SetErgMode(power) => buf.extend_from_slice(&power.to_le_bytes()),
+            WahooCommand::SetSimMode { weight, crr, cw } => {
+                // resolutions: weight 0.01 kg, crr 0.0001, cw 0.001 kg/m
+                let weight = (weight * 100.0).round().clamp(0.0, 65535.0) as u16;
+                let crr = (crr * 10000.0).round().clamp(0.0, 65535.0) as u16;
+                let cw = (cw * 1000.0).round().clamp(0.0, 65535.0) as u16;
+                buf.extend_from_slice(&weight.to_le_bytes());
+                buf.extend_from_slice(&crr.to_le_bytes());
+                buf.extend_from_slice(&cw.to_le_bytes());
+            }
+            WahooCommand::SetGrade(grade) => {
+                // -100 % to 100 % mapped onto the whole u16 range
+                let fraction = (grade / 100.0).clamp(-1.0, 1.0);
+                let grade = ((fraction + 1.0) * 32767.0).round() as u16;
+                buf.extend_from_slice(&grade.to_le_bytes());
+            }
+            WahooCommand::SetWindSpeed(speed) => {
+                // offset by 32.768 m/s so a headwind and a tailwind both fit, 0.001 m/s
+                let speed = ((speed + 32.768) * 1000.0).round().clamp(0.0, 65535.0) as u16;
+                buf.extend_from_slice(&speed.to_le_bytes());
+            }
+        }
+        buf
+    }
+}
+
+/*=======================================================================
+ * FUNCTIONS
+ * ====================================================================*/
+/// writes a command to the Wahoo trainer control characteristic
+pub async fn wahoo_command(
+    peripheral: &Peripheral,
+    command: &WahooCommand,
+) -> Result<(), BleRequestError> {
+    let control = find_characteristic(peripheral, WAHOO_TRAINER_CONTROL).ok_or(
+        BleRequestError::MissingCharacteristic(WAHOO_TRAINER_CONTROL),
+    )?;
+    peripheral
+        .write(&control, &command.encode(), WriteType::WithResponse)
+        .await?;
+    Ok(())
+}
+
+#[test]
+fn encodes_erg_and_unlock() {
+    assert_eq!(WahooCommand::Unlock.encode(), vec![0x20, 0xEE, 0xFC]);
+    assert_eq!(
+        WahooCommand::SetErgMode(250).encode(),
+        vec![0x42, 0xFA, 0x00]
+    );
+}
+
+#[test]
+fn encodes_sim_parameters() {
+    let sim_mode = WahooCommand::SetSimMode {
+        weight: 84.0,
+        crr: 0.004,
+        cw: 0.51,
+    };
+    // 84 kg = 8400, crr 40, cw 510
+    assert_eq!(
+        sim_mode.encode(),
+        vec![0x43, 0xD0, 0x20, 0x28, 0x00, 0xFE, 0x01]
+    );
+    // flat road sits in the middle of the range
+    assert_eq!(WahooCommand::SetGrade(0.0).encode(), vec![0x46, 0xFF, 0x7F]);
+    assert_eq!(
+        WahooCommand::SetGrade(100.0).encode(),
+        vec![0x46, 0xFE, 0xFF]
+    );
+    assert_eq!(
+        WahooCommand::SetWindSpeed(0.0).encode(),
+        vec![0x47, 0x00, 0x80]
+    );
+    assert_eq!(
+        WahooCommand::SetResistanceMode(100.0).encode(),
+        vec![0x40, 0x00, 0x00]
+    );
+}
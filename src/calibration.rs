@@ -0,0 +1,198 @@
+// Results of every calibration, per device, so drift between rides can be seen.
+
+/*=======================================================================
+ * IMPORTS
+ * ====================================================================*/
+// local files
+use crate::csv_file::{csv_rows, read_csv_file, to_csv};
+
+// external crates
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/*=======================================================================
+ * CONSTANTS
+ * ====================================================================*/
+pub const CALIBRATION_FILE: &str = "calibrations.csv";
+const HEADER: [&str; 5] = ["device_id", "device_name", "time", "kind", "value"];
+
+/*=======================================================================
+ * ENUMS
+ * ====================================================================*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CalibrationKind {
+    ZeroOffset,         // CPS Start Offset Compensation, value is N or 1/32 Nm
+    EnhancedZeroOffset, // CPS Start Enhanced Offset Compensation, same value
+    SpinDown,           // trainer spin down, value is the spin down time in s if reported
+}
+
+impl CalibrationKind {
+    fn parse(text: &str) -> Option<Self> {
+        match text {
+            "zero offset" => Some(CalibrationKind::ZeroOffset),
+            "enhanced zero offset" => Some(CalibrationKind::EnhancedZeroOffset),
+            "spin down" => Some(CalibrationKind::SpinDown),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for CalibrationKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CalibrationKind::ZeroOffset => write!(f, "zero offset"),
+            CalibrationKind::EnhancedZeroOffset => write!(f, "enhanced zero offset"),
+            CalibrationKind::SpinDown => write!(f, "spin down"),
+        }
+    }
+}
+
+/*=======================================================================
+ * STRUCTS
+ * ====================================================================*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct CalibrationRecord {
+    pub device_id: String, // PeripheralId as text, same as the known devices
+    pub device_name: String,
+    pub time: u64, // s since the unix epoch
+    pub kind: CalibrationKind,
+    pub value: Option<f32>, // FTMS trainers only say a spin down worked
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CalibrationLog {
+    pub records: Vec<CalibrationRecord>, // oldest first
+}
+
+impl CalibrationLog {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Ok(Self::from_csv(&read_csv_file(path)?))
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_csv())
+    }
+
+    pub fn add(&mut self, record: CalibrationRecord) {
+        self.records.push(record);
+    }
+
+    /// newest first
+    pub fn history<'a>(
+        &'a self,
+        device_id: &'a str,
+    ) -> impl Iterator<Item = &'a CalibrationRecord> + 'a {
+        self.records
+            .iter()
+            .rev()
+            .filter(move |record| record.device_id == device_id)
+    }
+
+    /// a calibration without a value leaves the last column empty
+    pub fn to_csv(&self) -> String {
+        let rows = self.records.iter().map(|record| {
+            vec![
+                record.device_id.clone(),
+                record.device_name.clone(),
+                record.time.to_string(),
+                record.kind.to_string(),
+                record
+                    .value
+                    .map(|value| value.to_string())
+                    .unwrap_or_default(),
+            ]
+        });
+        to_csv(&HEADER, rows)
+    }
+
+    /// rows that don't parse are skipped
+    pub fn from_csv(csv: &str) -> Self {
+        let records = csv_rows(csv, HEADER.len())
+            .filter_map(|fields| {
+                let [device_id, device_name, time, kind, value] = fields[..] else {
+                    return None;
+                };
+                Some(CalibrationRecord {
+                    device_id: device_id.to_string(),
+                    device_name: device_name.to_string(),
+                    time: time.parse().ok()?,
+                    kind: CalibrationKind::parse(kind)?,
+                    value: match value {
+                        "" => None,
+                        value => Some(value.parse().ok()?),
+                    },
+                })
+            })
+            .collect();
+        Self { records }
+    }
+}
+
+/*=======================================================================
+ * FUNCTIONS
+ * ====================================================================*/
+pub fn unix_time_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs())
+}
+
+/// "2024-05-01 07:30 UTC", no time zone database to do better
+pub fn format_unix_time(time: u64) -> String {
+    let days = (time / 86400) as i64;
+    let minutes_of_day = (time % 86400) / 60;
+    // days to civil date, Howard Hinnant's algorithm
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        minutes_of_day / 60,
+        minutes_of_day % 60
+    )
+}
+
+#[test]
+fn calibration_log_round_trips_and_formats_times() {
+    let mut log = CalibrationLog::default();
+    for (time, value) in [(1_700_000_000, -12.0), (1_700_086_400, -9.0)] {
+        log.add(CalibrationRecord {
+            device_id: "hci0/dev_C0_11_22_33_44_55".to_string(),
+            device_name: "Assioma, left".to_string(),
+            time,
+            kind: CalibrationKind::ZeroOffset,
+            value: Some(value),
+        });
+    }
+    log.add(CalibrationRecord {
+        device_id: "hci0/dev_F1_22_33_44_55_66".to_string(),
+        device_name: "KICKR CORE".to_string(),
+        time: 1_700_090_000,
+        kind: CalibrationKind::SpinDown,
+        value: None,
+    });
+    let loaded = CalibrationLog::from_csv(&log.to_csv());
+    assert_eq!(loaded.records.len(), 3);
+    assert_eq!(loaded.records[0].device_name, "Assioma; left");
+    let latest = loaded.history("hci0/dev_C0_11_22_33_44_55").next().unwrap();
+    assert_eq!(latest.value, Some(-9.0));
+    let spin_down = loaded.history("hci0/dev_F1_22_33_44_55_66").next().unwrap();
+    assert_eq!(spin_down.kind, CalibrationKind::SpinDown);
+    assert_eq!(spin_down.value, None);
+    assert_eq!(loaded.history("other").count(), 0);
+    assert_eq!(format_unix_time(0), "1970-01-01 00:00 UTC");
+    assert_eq!(format_unix_time(1_700_000_000), "2023-11-14 22:13 UTC");
+}
@@ -0,0 +1,52 @@
+// Small CSV files kept next to the rides (known devices, calibrations, ride events), read and
+// written the same way so a hand edited file behaves the same everywhere.
+
+/*=======================================================================
+ * IMPORTS
+ * ====================================================================*/
+// external crates
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/*=======================================================================
+ * FUNCTIONS
+ * ====================================================================*/
+/// a missing file reads as empty, nothing has been saved yet
+pub fn read_csv_file(path: &Path) -> io::Result<String> {
+    match fs::read_to_string(path) {
+        Ok(csv) => Ok(csv),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(String::new()),
+        Err(e) => Err(e),
+    }
+}
+
+/// header line, then a line per row
+/// commas in fields are swapped for semicolons so every row keeps its columns
+pub fn to_csv(header: &[&str], rows: impl Iterator<Item = Vec<String>>) -> String {
+    let mut csv = header.join(",");
+    csv.push('\n');
+    for row in rows {
+        let fields: Vec<String> = row.iter().map(|field| field.replace(',', ";")).collect();
+        csv.push_str(&fields.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+/// fields of every row after the header, rows without the expected columns are skipped
+pub fn csv_rows(csv: &str, columns: usize) -> impl Iterator<Item = Vec<&str>> {
+    csv.lines()
+        .skip(1)
+        .map(|line| line.split(',').collect::<Vec<&str>>())
+        .filter(move |fields| fields.len() == columns)
+}
+
+#[test]
+fn csv_rows_keep_their_columns() {
+    let rows = vec![vec!["a,b".to_string(), String::new()]];
+    let csv = to_csv(&["name", "value"], rows.into_iter()) + "too,many,fields\n";
+    assert_eq!(csv_rows(&csv, 2).collect::<Vec<_>>(), vec![vec!["a;b", ""]]);
+    let missing = read_csv_file(Path::new("no such file.csv")).unwrap();
+    assert_eq!(csv_rows(&missing, 2).count(), 0);
+}
@@ -0,0 +1,164 @@
+// Devices connected in earlier sessions, so the app can find and connect them again on launch.
+
+/*=======================================================================
+ * IMPORTS
+ * ====================================================================*/
+// local files
+use crate::csv_file::{csv_rows, read_csv_file, to_csv};
+
+// external crates
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/*=======================================================================
+ * CONSTANTS
+ * ====================================================================*/
+pub const KNOWN_DEVICES_FILE: &str = "known_devices.csv";
+const HEADER: [&str; 5] = ["id", "address", "role", "adapter", "name"];
+
+/// macOS hides addresses, every peripheral reports this one
+const HIDDEN_ADDRESS: &str = "00:00:00:00:00:00";
+
+/*=======================================================================
+ * ENUMS
+ * ====================================================================*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceRole {
+    Trainer,
+    Sensor,
+}
+
+impl fmt::Display for DeviceRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceRole::Trainer => write!(f, "trainer"),
+            DeviceRole::Sensor => write!(f, "sensor"),
+        }
+    }
+}
+
+impl DeviceRole {
+    fn parse(text: &str) -> Option<Self> {
+        match text {
+            "trainer" => Some(DeviceRole::Trainer),
+            "sensor" => Some(DeviceRole::Sensor),
+            _ => None,
+        }
+    }
+}
+
+/*=======================================================================
+ * STRUCTS
+ * ====================================================================*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KnownDevice {
+    pub id: String, // PeripheralId as text, stable per platform
+    pub address: String,
+    pub role: DeviceRole,
+    pub adapter: String, // adapter info of the adapter it was found on
+    pub name: String,
+}
+
+impl KnownDevice {
+    /// matches on either, the id changes on some platforms and the address is hidden on others
+    pub fn matches(&self, id: &str, address: &str) -> bool {
+        self.id == id || (address != HIDDEN_ADDRESS && self.address == address)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KnownDevices {
+    pub devices: Vec<KnownDevice>,
+}
+
+impl KnownDevices {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Ok(Self::from_csv(&read_csv_file(path)?))
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_csv())
+    }
+
+    /// replaces whatever was remembered for the same device
+    pub fn remember(&mut self, device: KnownDevice) {
+        self.devices
+            .retain(|known| !known.matches(&device.id, &device.address));
+        self.devices.push(device);
+    }
+
+    pub fn forget(&mut self, id: &str) {
+        self.devices.retain(|known| known.id != id);
+    }
+
+    pub fn find(&self, id: &str, address: &str) -> Option<&KnownDevice> {
+        self.devices.iter().find(|known| known.matches(id, address))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.devices.is_empty()
+    }
+
+    pub fn to_csv(&self) -> String {
+        let rows = self.devices.iter().map(|device| {
+            vec![
+                device.id.clone(),
+                device.address.clone(),
+                device.role.to_string(),
+                device.adapter.clone(),
+                device.name.clone(),
+            ]
+        });
+        to_csv(&HEADER, rows)
+    }
+
+    /// rows that don't parse are skipped, a hand edited file shouldn't lose every device
+    pub fn from_csv(csv: &str) -> Self {
+        let devices = csv_rows(csv, HEADER.len())
+            .filter_map(|fields| {
+                let [id, address, role, adapter, name] = fields[..] else {
+                    return None;
+                };
+                Some(KnownDevice {
+                    id: id.to_string(),
+                    address: address.to_string(),
+                    role: DeviceRole::parse(role)?,
+                    adapter: adapter.to_string(),
+                    name: name.to_string(),
+                })
+            })
+            .collect();
+        Self { devices }
+    }
+}
+
+#[test]
+fn known_devices_round_trip_through_csv() {
+    let mut known = KnownDevices::default();
+    known.remember(KnownDevice {
+        id: "hci0/dev_F1_22_33_44_55_66".to_string(),
+        address: "F1:22:33:44:55:66".to_string(),
+        role: DeviceRole::Trainer,
+        adapter: "hci0 (usb:v1D6Bp0246d0537)".to_string(),
+        name: "KICKR CORE, garage".to_string(),
+    });
+    known.remember(KnownDevice {
+        id: "hci0/dev_C0_11_22_33_44_55".to_string(),
+        address: "C0:11:22:33:44:55".to_string(),
+        role: DeviceRole::Sensor,
+        adapter: "hci0 (usb:v1D6Bp0246d0537)".to_string(),
+        name: "Polar H10".to_string(),
+    });
+    let loaded = KnownDevices::from_csv(&(known.to_csv() + "not,a,device\n"));
+    assert_eq!(loaded.devices.len(), 2);
+    assert_eq!(loaded.devices[0].name, "KICKR CORE; garage");
+    assert_eq!(loaded.devices[1].role, DeviceRole::Sensor);
+    // found again by address after the id changed
+    let strap = loaded.find("other id", "C0:11:22:33:44:55").unwrap();
+    assert_eq!(strap.name, "Polar H10");
+    assert!(loaded.find("other id", HIDDEN_ADDRESS).is_none());
+    known.forget("hci0/dev_F1_22_33_44_55_66");
+    assert_eq!(known.devices.len(), 1);
+}
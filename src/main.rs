@@ -7,7 +7,12 @@
 use app::*;
 mod app;
 mod bluetooth;
+mod calibration;
+mod csv_file;
+mod known_devices;
 mod math;
+mod ride_record;
+mod simulation;
 mod zwo_reader;
 
 // external crates
@@ -0,0 +1,283 @@
+// Everything recorded while a workout runs, written out as CSV when the rider saves it.
+
+/*=======================================================================
+ * IMPORTS
+ * ====================================================================*/
+// local files
+use crate::bluetooth::cps::PedalDynamics;
+use crate::csv_file::to_csv;
+
+// external crates
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/*=======================================================================
+ * STRUCTS
+ * ====================================================================*/
+/// one second of the ride
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RideSample {
+    pub time: usize,             // s into the workout
+    pub target_power: f32,       // W
+    pub power: f32,              // W
+    pub cadence: f32,            // rpm
+    pub speed: f32,              // km/h
+    pub heart_rate: Option<u16>, // bpm
+    pub interval: usize,         // section of the workout file
+    pub pedal_dynamics: PedalDynamics,
+}
+
+/// averages over one section of the workout, pedal dynamics only over the seconds that had them
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IntervalSummary {
+    pub interval: usize,
+    pub start: usize, // s into the workout
+    pub duration: usize,
+    pub power: f32,
+    pub pedal_dynamics: PedalDynamics,
+}
+
+/// something worth knowing about when looking back at the ride, e.g. a sensor dropout
+#[derive(Debug, Clone, PartialEq)]
+pub struct RideEvent {
+    pub time: usize, // s into the workout
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RideRecord {
+    pub samples: Vec<RideSample>,
+    pub rr_intervals: Vec<f32>, // s, every beat the strap reported during the ride
+    pub events: Vec<RideEvent>,
+}
+
+impl RideRecord {
+    pub fn add_sample(&mut self, sample: RideSample) {
+        self.samples.push(sample);
+    }
+
+    pub fn add_rr_intervals(&mut self, rr_intervals: &[f32]) {
+        self.rr_intervals.extend_from_slice(rr_intervals);
+    }
+
+    pub fn add_event(&mut self, time: usize, message: String) {
+        self.events.push(RideEvent { time, message });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// average over the samples that have a heart rate
+    pub fn average_heart_rate(&self) -> Option<f32> {
+        let heart_rates: Vec<u16> = self.samples.iter().filter_map(|s| s.heart_rate).collect();
+        if heart_rates.is_empty() {
+            return None;
+        }
+        Some(heart_rates.iter().map(|hr| *hr as f32).sum::<f32>() / heart_rates.len() as f32)
+    }
+
+    /// consecutive samples of the same interval, in ride order
+    pub fn interval_summaries(&self) -> Vec<IntervalSummary> {
+        let average = |values: Vec<f32>| match values.is_empty() {
+            true => None,
+            false => Some(values.iter().sum::<f32>() / values.len() as f32),
+        };
+        let mut summaries = Vec::new();
+        let mut start = 0;
+        for (i, sample) in self.samples.iter().enumerate() {
+            let last = self.samples.get(i + 1).map(|next| next.interval) != Some(sample.interval);
+            if !last {
+                continue;
+            }
+            let samples = &self.samples[start..=i];
+            let pedal = |field: fn(&PedalDynamics) -> Option<f32>| {
+                average(
+                    samples
+                        .iter()
+                        .filter_map(|s| field(&s.pedal_dynamics))
+                        .collect(),
+                )
+            };
+            summaries.push(IntervalSummary {
+                interval: sample.interval,
+                start,
+                duration: samples.len(),
+                power: average(samples.iter().map(|s| s.power).collect()).unwrap_or(0.0),
+                pedal_dynamics: PedalDynamics {
+                    left_balance: pedal(|p| p.left_balance),
+                    torque_effectiveness: pedal(|p| p.torque_effectiveness),
+                    pedal_smoothness: pedal(|p| p.pedal_smoothness),
+                },
+            });
+            start = i + 1;
+        }
+        summaries
+    }
+
+    /// whether any second had balance, torque effectiveness or smoothness
+    pub fn has_pedal_dynamics(&self) -> bool {
+        self.samples
+            .iter()
+            .any(|s| s.pedal_dynamics != PedalDynamics::default())
+    }
+
+    /// one row per second, heart rate is empty when there was no strap
+    pub fn to_csv(&self) -> String {
+        let mut csv = "time,target_power,power,cadence,speed,heart_rate\n".to_string();
+        for sample in &self.samples {
+            let heart_rate = match sample.heart_rate {
+                Some(heart_rate) => heart_rate.to_string(),
+                None => String::new(),
+            };
+            csv.push_str(&format!(
+                "{},{:.0},{:.0},{:.0},{:.2},{}\n",
+                sample.time,
+                sample.target_power,
+                sample.power,
+                sample.cadence,
+                sample.speed,
+                heart_rate
+            ));
+        }
+        csv
+    }
+
+    /// RR intervals in their own file, they don't line up with the seconds
+    pub fn rr_intervals_csv(&self) -> String {
+        let mut csv = "rr_interval\n".to_string();
+        for rr_interval in &self.rr_intervals {
+            csv.push_str(&format!("{:.4}\n", rr_interval));
+        }
+        csv
+    }
+
+    /// one row per interval, pedal dynamics are empty where the meter had none
+    pub fn intervals_csv(&self) -> String {
+        let mut csv =
+            "interval,start,duration,power,left_balance,torque_effectiveness_estimate,pedal_smoothness\n"
+                .to_string();
+        let optional = |value: Option<f32>| match value {
+            Some(value) => format!("{:.1}", value),
+            None => String::new(),
+        };
+        for summary in self.interval_summaries() {
+            csv.push_str(&format!(
+                "{},{},{},{:.0},{},{},{}\n",
+                summary.interval,
+                summary.start,
+                summary.duration,
+                summary.power,
+                optional(summary.pedal_dynamics.left_balance),
+                optional(summary.pedal_dynamics.torque_effectiveness),
+                optional(summary.pedal_dynamics.pedal_smoothness)
+            ));
+        }
+        csv
+    }
+
+    pub fn events_csv(&self) -> String {
+        let rows = self
+            .events
+            .iter()
+            .map(|event| vec![event.time.to_string(), event.message.clone()]);
+        to_csv(&["time", "event"], rows)
+    }
+
+    /// writes <name>.csv into directory, plus <name>_rr.csv, <name>_events.csv and
+    /// <name>_intervals.csv if there is anything to put in them
+    pub fn save(&self, directory: &Path, name: &str) -> io::Result<()> {
+        fs::write(directory.join(format!("{}.csv", name)), self.to_csv())?;
+        if !self.rr_intervals.is_empty() {
+            fs::write(
+                directory.join(format!("{}_rr.csv", name)),
+                self.rr_intervals_csv(),
+            )?;
+        }
+        if !self.events.is_empty() {
+            fs::write(
+                directory.join(format!("{}_events.csv", name)),
+                self.events_csv(),
+            )?;
+        }
+        if self.has_pedal_dynamics() {
+            fs::write(
+                directory.join(format!("{}_intervals.csv", name)),
+                self.intervals_csv(),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn ride_record_writes_csv_rows() {
+    let mut record = RideRecord::default();
+    record.add_sample(RideSample {
+        time: 0,
+        target_power: 150.0,
+        power: 148.4,
+        cadence: 90.2,
+        speed: 30.123,
+        heart_rate: Some(120),
+        interval: 0,
+        pedal_dynamics: PedalDynamics::default(),
+    });
+    record.add_sample(RideSample {
+        time: 1,
+        target_power: 150.0,
+        power: 151.0,
+        cadence: 91.0,
+        speed: 30.5,
+        heart_rate: None,
+        interval: 0,
+        pedal_dynamics: PedalDynamics::default(),
+    });
+    record.add_rr_intervals(&[0.5, 0.49]);
+    assert_eq!(
+        record.to_csv(),
+        "time,target_power,power,cadence,speed,heart_rate\n\
+         0,150,148,90,30.12,120\n\
+         1,150,151,91,30.50,\n"
+    );
+    assert_eq!(record.rr_intervals_csv(), "rr_interval\n0.5000\n0.4900\n");
+    assert_eq!(record.average_heart_rate(), Some(120.0));
+    record.add_event(1, "Trainer reconnected after 2.5 s, 3 s lost".to_string());
+    assert_eq!(
+        record.events_csv(),
+        "time,event\n1,Trainer reconnected after 2.5 s; 3 s lost\n"
+    );
+}
+
+#[test]
+fn ride_record_averages_pedal_dynamics_per_interval() {
+    let mut record = RideRecord::default();
+    for (time, interval, balance) in [(0, 0, Some(48.0)), (1, 0, Some(50.0)), (2, 1, None)] {
+        record.add_sample(RideSample {
+            time,
+            target_power: 100.0,
+            power: 100.0 + time as f32 * 10.0,
+            cadence: 90.0,
+            speed: 30.0,
+            heart_rate: None,
+            interval,
+            pedal_dynamics: PedalDynamics {
+                left_balance: balance,
+                ..Default::default()
+            },
+        });
+    }
+    assert!(record.has_pedal_dynamics());
+    let summaries = record.interval_summaries();
+    assert_eq!(summaries.len(), 2);
+    assert_eq!(summaries[0].duration, 2);
+    assert_eq!(summaries[0].pedal_dynamics.left_balance, Some(49.0));
+    assert_eq!(summaries[1].start, 2);
+    assert_eq!(
+        record.intervals_csv(),
+        "interval,start,duration,power,left_balance,torque_effectiveness_estimate,pedal_smoothness\n\
+         0,0,2,105,49.0,,\n\
+         1,2,1,120,,,\n"
+    );
+}
@@ -0,0 +1,202 @@
+// Simulation ("sim") mode: the trainer sets its own resistance from grade, wind and
+// rolling resistance instead of holding a target power.
+
+/*=======================================================================
+ * IMPORTS
+ * ====================================================================*/
+// local files
+use crate::bluetooth::ftms::IndoorBikeSimulation;
+
+// external crates
+use std::fmt;
+use std::time::Duration;
+
+/*=======================================================================
+ * CONSTANTS
+ * ====================================================================*/
+/// smallest grade change (%) worth sending to the trainer
+pub const GRADE_STEP: f32 = 0.1;
+
+/*=======================================================================
+ * STRUCTS
+ * ====================================================================*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct GradeProfileError(String);
+
+impl fmt::Display for GradeProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid grade profile: {}", self.0)
+    }
+}
+
+/// one point of a grade profile
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GradePoint {
+    pub distance: f32, // m
+    pub grade: f32,    // %
+}
+
+/// course grade by distance, linearly interpolated between points
+#[derive(Debug, Clone, PartialEq)]
+pub struct GradeProfile {
+    points: Vec<GradePoint>,
+}
+
+impl GradeProfile {
+    /// parses "distance:grade" pairs separated by commas, e.g. "0:0, 500:4.5, 1200:-2"
+    pub fn parse(text: &str) -> Result<Self, GradeProfileError> {
+        let mut points: Vec<GradePoint> = Vec::new();
+        for pair in text.split(',').map(|p| p.trim()).filter(|p| !p.is_empty()) {
+            let (distance, grade) = pair
+                .split_once(':')
+                .ok_or_else(|| GradeProfileError(format!("'{}' is not distance:grade", pair)))?;
+            let distance = distance
+                .trim()
+                .parse::<f32>()
+                .map_err(|_| GradeProfileError(format!("bad distance in '{}'", pair)))?;
+            let grade = grade
+                .trim()
+                .parse::<f32>()
+                .map_err(|_| GradeProfileError(format!("bad grade in '{}'", pair)))?;
+            if let Some(last) = points.last() {
+                if distance <= last.distance {
+                    return Err(GradeProfileError(
+                        "distances must be increasing".to_string(),
+                    ));
+                }
+            }
+            points.push(GradePoint { distance, grade });
+        }
+        if points.is_empty() {
+            return Err(GradeProfileError("no points".to_string()));
+        }
+        Ok(Self { points })
+    }
+
+    /// grade at a distance, holds the first/last grade outside the profile
+    pub fn grade_at(&self, distance: f32) -> f32 {
+        let first = self.points[0];
+        if distance <= first.distance {
+            return first.grade;
+        }
+        for window in self.points.windows(2) {
+            let (start, end) = (window[0], window[1]);
+            if distance <= end.distance {
+                let fraction = (distance - start.distance) / (end.distance - start.distance);
+                return start.grade + fraction * (end.grade - start.grade);
+            }
+        }
+        self.points[self.points.len() - 1].grade
+    }
+}
+
+/// rider/environment settings that don't change with the course
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RiderSettings {
+    pub wind_speed: f32, // m/s, positive is a headwind
+    pub crr: f32,
+    pub cw: f32, // kg/m
+    // FE-C trainers work out resistance themselves and need the total mass
+    pub rider_weight: f32, // kg
+    pub bike_weight: f32,  // kg
+}
+
+impl Default for RiderSettings {
+    fn default() -> Self {
+        // road bike on tarmac, rider on the hoods
+        Self {
+            wind_speed: 0.0,
+            crr: 0.004,
+            cw: 0.51,
+            rider_weight: 75.0,
+            bike_weight: 9.0,
+        }
+    }
+}
+
+/// tracks how far the rider has gone on the grade profile and decides when the
+/// trainer needs new simulation parameters
+#[derive(Debug, Clone)]
+pub struct SimulationController {
+    profile: GradeProfile,
+    settings: RiderSettings,
+    distance: f32, // m
+    last_sent_grade: Option<f32>,
+}
+
+impl SimulationController {
+    pub fn new(profile: GradeProfile, settings: RiderSettings) -> Self {
+        Self {
+            profile,
+            settings,
+            distance: 0.0,
+            last_sent_grade: None,
+        }
+    }
+
+    /// moves the rider along the course at the given speed
+    pub fn advance(&mut self, speed: f32, elapsed: Duration) {
+        // km/h -> m/s
+        self.distance += speed.max(0.0) / 3.6 * elapsed.as_secs_f32();
+    }
+
+    pub fn distance(&self) -> f32 {
+        self.distance
+    }
+
+    pub fn parameters(&self) -> IndoorBikeSimulation {
+        IndoorBikeSimulation {
+            wind_speed: self.settings.wind_speed,
+            grade: self.profile.grade_at(self.distance),
+            crr: self.settings.crr,
+            cw: self.settings.cw,
+        }
+    }
+
+    /// parameters to send if the grade moved enough since the last update
+    pub fn next_update(&mut self) -> Option<IndoorBikeSimulation> {
+        let parameters = self.parameters();
+        let changed = match self.last_sent_grade {
+            Some(last_grade) => (parameters.grade - last_grade).abs() >= GRADE_STEP,
+            None => true,
+        };
+        if changed {
+            self.last_sent_grade = Some(parameters.grade);
+            Some(parameters)
+        } else {
+            None
+        }
+    }
+
+    /// makes the next call to next_update send, e.g. after switching back from ERG
+    pub fn force_update(&mut self) {
+        self.last_sent_grade = None;
+    }
+}
+
+#[test]
+fn grade_profile_interpolates_between_points() {
+    let profile = GradeProfile::parse("0:0, 1000:5, 2000:-1").unwrap();
+    assert_eq!(profile.grade_at(-10.0), 0.0);
+    assert_eq!(profile.grade_at(500.0), 2.5);
+    assert_eq!(profile.grade_at(1500.0), 2.0);
+    assert_eq!(profile.grade_at(5000.0), -1.0);
+    assert!(GradeProfile::parse("0:0, 0:3").is_err());
+    assert!(GradeProfile::parse("hill").is_err());
+}
+
+#[test]
+fn controller_only_updates_when_grade_changes() {
+    let profile = GradeProfile::parse("0:0, 100:10").unwrap();
+    let mut controller = SimulationController::new(profile, RiderSettings::default());
+    assert_eq!(controller.next_update().unwrap().grade, 0.0);
+    assert_eq!(controller.next_update(), None);
+    // 36 km/h for 1 s = 10 m = 1 % on this profile
+    controller.advance(36.0, Duration::from_secs(1));
+    assert!((controller.distance() - 10.0).abs() < 0.001);
+    let update = controller.next_update().unwrap();
+    assert!((update.grade - 1.0).abs() < 0.001);
+    assert_eq!(update.crr, 0.004);
+    controller.force_update();
+    assert!(controller.next_update().is_some());
+}
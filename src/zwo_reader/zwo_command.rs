@@ -95,3 +95,41 @@ pub fn create_timeseries(workout: Workout) -> Result<WorkoutTimeSeries, TimeSeri
     };
     return Ok(final_series);
 }
+
+/// seconds a section adds to the series from create_timeseries
+fn section_length(tag: &ExerciseTag) -> Result<usize, TimeSeriesError> {
+    let length = match tag {
+        ExerciseTag::Warmup(s) => s.to_time_series()?.time.len(),
+        ExerciseTag::SteadyState(s) => s.to_time_series()?.time.len(),
+        ExerciseTag::Cooldown(s) => s.to_time_series()?.time.len(),
+        ExerciseTag::FreeRide(s) => s.to_time_series()?.time.len(),
+        ExerciseTag::Freeride(s) => s.to_time_series()?.time.len(),
+        ExerciseTag::IntervalsT(s) => s.to_time_series()?.time.len(),
+        ExerciseTag::MaxEffort(s) => s.to_time_series()?.time.len(),
+        ExerciseTag::Ramp(s) => s.to_time_series()?.time.len(),
+        ExerciseTag::SolidState(s) => s.to_time_series()?.time.len(),
+        ExerciseTag::RestDay | ExerciseTag::Unknown => 0,
+    };
+    Ok(length)
+}
+
+/// one number per second of the workout, the index of the section it belongs to
+/// same length as the series from create_timeseries
+pub fn interval_numbers(workout: &Workout) -> Result<Vec<usize>, TimeSeriesError> {
+    let mut numbers: Vec<usize> = Vec::new();
+    for (interval, tag) in workout.exercise.iter().enumerate() {
+        numbers.extend(vec![interval; section_length(tag)?]);
+    }
+    Ok(numbers)
+}
+
+/// one flag per second of the workout, true during FreeRide/Freeride sections
+/// same length as the series from create_timeseries
+pub fn free_ride_mask(workout: &Workout) -> Result<Vec<bool>, TimeSeriesError> {
+    let mut mask: Vec<bool> = Vec::new();
+    for tag in workout.exercise.iter() {
+        let free_ride = matches!(tag, ExerciseTag::FreeRide(_) | ExerciseTag::Freeride(_));
+        mask.extend(vec![free_ride; section_length(tag)?]);
+    }
+    Ok(mask)
+}